@@ -0,0 +1,160 @@
+use std::{borrow::Borrow, io, pin::Pin, task::{Context, Poll, ready}};
+
+use crate::utils::*;
+
+// a pipe for async code, the runtime thread wakes the task when data arrives or the write queue drains
+// the pipe can be owned or borrowed, like with PipeIo
+pub struct AsyncNamedPipe<P = NamedPipe> {
+    pipe: P,
+    buffer: Vec<u8>, // read from the pipe but not consumed yet
+    position: usize, // of the first byte not consumed yet
+    shut_down: bool, // writes fail after a shutdown, reads go on
+}
+
+impl<P: Borrow<NamedPipe>> AsyncNamedPipe<P> {
+    pub fn new(pipe: P) -> Self {
+        Self {
+            pipe,
+            buffer: Vec::new(),
+            position: 0,
+            shut_down: false,
+        }
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        self.pipe.borrow()
+    }
+    
+    // whatever was read from the pipe but not consumed is lost
+    pub fn into_inner(self) -> P {
+        self.pipe
+    }
+    
+    // empty once the pipe is closed and everything has been read
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<&[u8]> {
+        if self.position == self.buffer.len() {
+            match ready!(self.pipe.borrow().poll_read(cx)) {
+                ReadResult::Data(data) => self.buffer = data,
+                _ => self.buffer.clear(),
+            }
+            
+            self.position = 0;
+        }
+        
+        Poll::Ready(&self.buffer[self.position..])
+    }
+    
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.buffer.len());
+    }
+    
+    #[cfg(feature = "futures-io")]
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        let available = ready!(self.poll_fill_buf(cx));
+        let len = available.len().min(buf.len());
+        
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        
+        Poll::Ready(len)
+    }
+    
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.shut_down {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        
+        Poll::Ready(match ready!(self.pipe().poll_write(cx, buf)) {
+            Ok(()) => Ok(buf.len()),
+            Err(error) => Err(error.into()),
+        })
+    }
+    
+    // ready once the runtime has taken everything written
+    fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.pipe().poll_drained(cx).map_err(io::Error::from)
+    }
+    
+    // flushes and stops writing, the connection stays up for the peer and for reading
+    // the pipe has no half close, so the peer sees the end only once the pipe is interrupted
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_flush(cx))?;
+        
+        self.shut_down = true;
+        
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<P: Borrow<NamedPipe> + Unpin> tokio::io::AsyncRead for AsyncNamedPipe<P> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let available = ready!(this.poll_fill_buf(cx));
+        let len = available.len().min(buf.remaining());
+        
+        buf.put_slice(&available[..len]);
+        this.consume(len);
+        
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<P: Borrow<NamedPipe> + Unpin> tokio::io::AsyncBufRead for AsyncNamedPipe<P> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().poll_fill_buf(cx).map(Ok)
+    }
+    
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        self.get_mut().consume(amount);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<P: Borrow<NamedPipe> + Unpin> tokio::io::AsyncWrite for AsyncNamedPipe<P> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, buf)
+    }
+    
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+    
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<P: Borrow<NamedPipe> + Unpin> futures_io::AsyncRead for AsyncNamedPipe<P> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read(cx, buf).map(Ok)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<P: Borrow<NamedPipe> + Unpin> futures_io::AsyncBufRead for AsyncNamedPipe<P> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().poll_fill_buf(cx).map(Ok)
+    }
+    
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        self.get_mut().consume(amount);
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<P: Borrow<NamedPipe> + Unpin> futures_io::AsyncWrite for AsyncNamedPipe<P> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, buf)
+    }
+    
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+    
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_shutdown(cx)
+    }
+}
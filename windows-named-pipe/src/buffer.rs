@@ -27,3 +27,20 @@ pub struct NamedPipeBuffer {
     pub read_channel: channel::Channel<u8>,
     pub write_channel: channel::Channel<u8>,
 }
+
+impl NamedPipeBuffer {
+    // io buffers of the given size and unbounded queues
+    pub fn new(io_buffer_size: usize) -> Self {
+        Self {
+            read: IoBuffer::new(io_buffer_size),
+            write: IoBuffer::new(io_buffer_size),
+            read_channel: channel::Channel::new(),
+            write_channel: channel::Channel::new(),
+        }
+    }
+    
+    // caps the bytes queued for writing, NamedPipe::write fails instead of queueing more
+    pub fn bounded(self, write_capacity: usize) -> Self {
+        Self { write_channel: channel::Channel::bounded(write_capacity), ..self }
+    }
+}
@@ -1,14 +1,84 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard, atomic::{self, AtomicBool, AtomicUsize, Ordering}},
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
 
+// the async counterpart of a condvar, registered and woken with the write side locked
+#[derive(Debug, Default)]
+struct Wakers {
+    wakers: Mutex<Vec<Waker>>,
+    waiting: AtomicBool, // set while there are wakers, so wakes can skip the lock
+}
+
+impl Wakers {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        
+        self.waiting.store(true, Ordering::Relaxed);
+    }
+    
+    fn is_waiting(&self) -> bool {
+        self.waiting.load(Ordering::Relaxed)
+    }
+    
+    fn wake_all(&self) {
+        if self.is_waiting() {
+            self.waiting.store(false, Ordering::Relaxed);
+            std::mem::take(&mut *self.wakers.lock().unwrap()).into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+// the write side is always locked before the read side, so nothing waits on a lock while holding the other one in reverse
+// writes block on each other instead of being dropped, anything not moved to the read side yet is moved by the next read
+// items only ever move by appending the whole write side to the back of the read side, be it on a write, a read, flush or
+// try_flush, so they are read in the order they were written and flushing never reorders anything
 #[derive(Debug, Default)]
 pub struct DoubleBuffer<T> {
     write: Mutex<Vec<T>>,
-    read: Mutex<Vec<T>>,
+    read: Mutex<VecDeque<T>>, // oldest first
+    written: Condvar, // notified with the write side locked, after every write
+    written_wakers: Wakers, // woken along with written
+    capacity: Option<usize>,
+    read_len: AtomicUsize, // stored with the read side locked after every change, so writers can check the capacity without it
+    space: Condvar, // notified after every read of a bounded buffer or while something waits for the buffer to drain
+    space_wakers: Wakers, // woken along with space
+    draining: AtomicUsize, // how many wait for the buffer to drain
+    disconnected: AtomicBool, // waits give up once set
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitError {
+    Timeout,
+    Disconnected,
+}
+
+// waits on the condvar until the deadline
+fn wait_until<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>, deadline: Option<Instant>) -> Result<MutexGuard<'a, T>, WaitError> {
+    match deadline {
+        Some(deadline) => {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            
+            if timeout.is_zero() {
+                Err(WaitError::Timeout)
+            }
+            else {
+                Ok(condvar.wait_timeout(guard, timeout).unwrap().0)
+            }
+        }
+        None => Ok(condvar.wait(guard).unwrap()),
+    }
 }
 
 impl<T> DoubleBuffer<T> {
     pub fn new() -> Self {
-        Self { write: Mutex::new(Vec::new()), read: Mutex::new(Vec::new()) }
+        Self::with_capacity(0)
     }
     
     pub fn new_arc() -> Arc<Self> {
@@ -16,71 +86,374 @@ impl<T> DoubleBuffer<T> {
     }
     
     pub fn with_capacity(capacity: usize) -> Self {
-        Self { write: Mutex::new(Vec::with_capacity(capacity)), read: Mutex::new(Vec::with_capacity(capacity)) }
+        Self {
+            write: Mutex::new(Vec::with_capacity(capacity)),
+            read: Mutex::new(VecDeque::with_capacity(capacity)),
+            written: Condvar::new(),
+            written_wakers: Wakers::default(),
+            capacity: None,
+            read_len: AtomicUsize::new(0),
+            space: Condvar::new(),
+            space_wakers: Wakers::default(),
+            draining: AtomicUsize::new(0),
+            disconnected: AtomicBool::new(false),
+        }
+    }
+    
+    // holds at most capacity items, except that anything fits into an empty buffer
+    pub fn bounded(capacity: usize) -> Self {
+        Self { capacity: Some(capacity), ..Self::with_capacity(capacity) }
+    }
+    
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+    
+    // wakes up every wait, which fails from now on unless it can still be satisfied
+    pub fn disconnect(&self) {
+        let _write = self.write.lock().unwrap();
+        
+        self.disconnected.store(true, Ordering::Relaxed);
+        self.written.notify_all();
+        self.written_wakers.wake_all();
+        self.space.notify_all();
+        self.space_wakers.wake_all();
+    }
+    
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+    
+    // wakes up every read wait without writing anything, so it checks its condition again
+    pub fn wake_readers(&self) {
+        let _write = self.write.lock().unwrap();
+        
+        self.written.notify_all();
+        self.written_wakers.wake_all();
+    }
+    
+    // waits work again, for reusing a buffer after a disconnect
+    pub fn reconnect(&self) {
+        let _write = self.write.lock().unwrap();
+        
+        self.disconnected.store(false, Ordering::Relaxed);
+    }
+    
+    // waits until everything written has been read, fails once disconnected with something left
+    // waits forever without a timeout
+    pub fn wait_drained(&self, timeout: Option<Duration>) -> Result<(), WaitError> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut write = self.write.lock().unwrap();
+        
+        self.draining.fetch_add(1, Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+        
+        let result = loop {
+            if write.is_empty() && self.read_len.load(Ordering::Relaxed) == 0 {
+                break Ok(());
+            }
+            
+            if self.is_disconnected() {
+                break Err(WaitError::Disconnected);
+            }
+            
+            // the write side stays locked between the check and the wait, so no read is missed
+            write = match wait_until(&self.space, write, deadline) {
+                Ok(write) => write,
+                Err(error) => break Err(error),
+            };
+        };
+        
+        self.draining.fetch_sub(1, Ordering::Relaxed);
+        
+        result
+    }
+    
+    // registers the waker before checking, so a read in between still wakes it
+    fn poll_space(&self, write: &[T], waker: &Waker, ready: impl Fn(usize) -> bool) -> bool {
+        if ready(write.len() + self.read_len.load(Ordering::Relaxed)) {
+            return true;
+        }
+        
+        self.space_wakers.register(waker);
+        
+        // pairs with the fence in read_done, like the one in wait_drained
+        atomic::fence(Ordering::SeqCst);
+        
+        ready(write.len() + self.read_len.load(Ordering::Relaxed))
+    }
+    
+    // like wait_drained, but wakes the waker instead of waiting
+    pub fn poll_drained(&self, waker: &Waker) -> Poll<Result<(), WaitError>> {
+        let write = self.write.lock().unwrap();
+        
+        if self.poll_space(&write, waker, |len| len == 0) {
+            Poll::Ready(Ok(()))
+        }
+        else if self.is_disconnected() {
+            Poll::Ready(Err(WaitError::Disconnected))
+        }
+        else {
+            Poll::Pending
+        }
     }
     
     pub fn flush(&self) {
-        let ref mut write = self.write.lock().unwrap();
+        let write = &mut self.write.lock().unwrap();
         let mut read = self.read.lock().unwrap();
         
-        read.append(write);
+        read.extend(write.drain(..));
+        self.read_len.store(read.len(), Ordering::Relaxed);
     }
     
     pub fn try_flush(&self) -> bool {
         let Ok(ref mut write) = self.write.try_lock() else { return false };
         let Ok(mut read) = self.read.try_lock() else { return false };
         
-        read.append(write);
+        read.extend(write.drain(..));
+        self.read_len.store(read.len(), Ordering::Relaxed);
         
         true
     }
     
+    fn write_locked(&self, write: &mut Vec<T>, f: impl FnOnce(&mut Vec<T>)) {
+        f(write);
+        
+        if let Ok(mut read) = self.read.try_lock() {
+            read.extend(write.drain(..));
+            self.read_len.store(read.len(), Ordering::Relaxed);
+        }
+        
+        self.written.notify_all();
+        self.written_wakers.wake_all();
+    }
+    
+    // ignores the capacity and disconnection
     pub fn write(&self, f: impl FnOnce(&mut Vec<T>)) {
-        if let Ok(ref mut write) = self.write.try_lock() {
-            f(write);
+        self.write_locked(&mut self.write.lock().unwrap(), f);
+    }
+    
+    // waits until n more items fit before calling f, f is not called if the wait fails
+    // waits forever without a timeout
+    pub fn write_reserve(&self, n: usize, timeout: Option<Duration>, f: impl FnOnce(&mut Vec<T>)) -> Result<(), WaitError> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut write = self.write.lock().unwrap();
+        
+        loop {
+            if self.is_disconnected() {
+                return Err(WaitError::Disconnected);
+            }
+            
+            let len = write.len() + self.read_len.load(Ordering::Relaxed);
             
-            if let Ok(mut read) = self.read.try_lock() {
-                read.append(write);
+            if self.capacity.is_none_or(|capacity| len == 0 || len + n <= capacity) {
+                break;
             }
+            
+            write = wait_until(&self.space, write, deadline)?;
+        }
+        
+        self.write_locked(&mut write, f);
+        
+        Ok(())
+    }
+    
+    // like write_reserve, but wakes the waker once there is space instead of waiting
+    pub fn poll_write_reserve(&self, n: usize, waker: &Waker, f: impl FnOnce(&mut Vec<T>)) -> Poll<Result<(), WaitError>> {
+        let mut write = self.write.lock().unwrap();
+        
+        if self.is_disconnected() {
+            return Poll::Ready(Err(WaitError::Disconnected));
         }
+        
+        let fits = match self.capacity {
+            Some(capacity) => self.poll_space(&write, waker, |len| len == 0 || len + n <= capacity),
+            None => true,
+        };
+        
+        if !fits {
+            return Poll::Pending;
+        }
+        
+        self.write_locked(&mut write, f);
+        
+        Poll::Ready(Ok(()))
+    }
+    
+    pub fn write_vec(&self, vec: &mut Vec<T>) -> Result<(), WaitError> {
+        self.write_reserve(vec.len(), None, |t| t.append(vec))
     }
     
-    pub fn write_vec(&self, vec: &mut Vec<T>) {
-        self.write(|t| t.append(vec));
+    pub fn push(&self, t: T) -> Result<(), (T, WaitError)> {
+        self.push_timeout(t, None)
     }
     
-    pub fn push(&self, t: T) {
-        self.write(|vec| vec.push(t));
+    // gives t back if the wait failed
+    pub fn push_timeout(&self, t: T, timeout: Option<Duration>) -> Result<(), (T, WaitError)> {
+        let mut t = Some(t);
+        
+        self.write_reserve(1, timeout, |vec| vec.push(t.take().unwrap())).map_err(|error| (t.take().unwrap(), error))
     }
     
-    pub fn read(&self, f: impl FnOnce(&mut Vec<T>)) {
-        if let Ok(ref mut read) = self.read.try_lock() {
+    // wakes up writers waiting for space, the write side must not be locked by the caller
+    fn read_done(&self) {
+        // pairs with the fence in wait_drained, either the reader sees the waiter or the waiter sees the new length
+        atomic::fence(Ordering::SeqCst);
+        
+        if self.capacity.is_some() || self.draining.load(Ordering::Relaxed) > 0 || self.space_wakers.is_waiting() {
+            let _write = self.write.lock().unwrap(); // a writer is either waiting already or has not checked the length yet
+            
+            self.space.notify_all();
+            self.space_wakers.wake_all();
+        }
+    }
+    
+    pub fn read(&self, f: impl FnOnce(&mut VecDeque<T>)) {
+        {
+            let read = &mut {
+                let write = &mut self.write.lock().unwrap();
+                let mut read = self.read.lock().unwrap();
+                
+                read.extend(write.drain(..));
+                self.read_len.store(read.len(), Ordering::Relaxed);
+                
+                read
+            };
+            
             f(read);
+            self.read_len.store(read.len(), Ordering::Relaxed);
+        }
+        
+        self.read_done();
+    }
+    
+    // calls f on the read side again after every write until it returns true
+    // after a disconnection f gets one last call with everything that was written
+    // waits forever without a timeout
+    pub fn read_until(&self, timeout: Option<Duration>, mut f: impl FnMut(&mut VecDeque<T>) -> bool) -> Result<(), WaitError> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut write = self.write.lock().unwrap();
+        
+        loop {
+            let done = {
+                let mut read = self.read.lock().unwrap();
+                
+                read.extend(write.drain(..));
+                
+                let done = f(&mut read);
+                
+                self.read_len.store(read.len(), Ordering::Relaxed);
+                
+                done
+            };
+            
+            // the write side is locked, so waiting writers are already waiting
+            self.space.notify_all();
+            self.space_wakers.wake_all();
+            
+            if done {
+                return Ok(());
+            }
             
-            if let Ok(ref mut write) = self.write.try_lock() {
-                read.append(write);
+            if self.is_disconnected() {
+                return Err(WaitError::Disconnected);
             }
+            
+            // the write side stays locked between the check and the wait, so no write is missed
+            write = wait_until(&self.written, write, deadline)?;
         }
     }
     
+    // like read_until, but registers the waker to be woken by the next write instead of waiting
+    pub fn poll_read(&self, waker: &Waker, f: impl FnOnce(&mut VecDeque<T>) -> bool) -> Poll<Result<(), WaitError>> {
+        let mut write = self.write.lock().unwrap();
+        
+        let done = {
+            let mut read = self.read.lock().unwrap();
+            
+            read.extend(write.drain(..));
+            
+            let done = f(&mut read);
+            
+            self.read_len.store(read.len(), Ordering::Relaxed);
+            
+            done
+        };
+        
+        self.space.notify_all();
+        self.space_wakers.wake_all();
+        
+        if done {
+            Poll::Ready(Ok(()))
+        }
+        else if self.is_disconnected() {
+            Poll::Ready(Err(WaitError::Disconnected))
+        }
+        else {
+            // the write side stays locked until the waker is registered, so no write is missed
+            self.written_wakers.register(waker);
+            
+            Poll::Pending
+        }
+    }
+    
+    // removes the oldest item once there is one
+    pub fn pop_front_until(&self, timeout: Option<Duration>) -> Result<T, WaitError> {
+        let mut result = None;
+        
+        self.read_until(timeout, |vec| {
+            result = vec.pop_front();
+            result.is_some()
+        })?;
+        
+        Ok(result.unwrap())
+    }
+    
     pub fn read_vec(&self) -> Vec<T> {
         let mut vec = Vec::new();
         
-        self.read(|t| vec.append(t));
+        self.read(|t| vec = std::mem::take(t).into());
+        
+        vec
+    }
+    
+    // up to n of the oldest items
+    pub fn read_n(&self, n: usize) -> Vec<T> {
+        let mut vec = Vec::new();
+        
+        self.read(|t| vec.extend(t.drain(..n.min(t.len()))));
         
         vec
     }
     
+    // the newest item
     pub fn pop(&self) -> Option<T> {
         let mut result = None;
         
-        self.read(|vec| result = vec.pop());
+        self.read(|vec| result = vec.pop_back());
+        
+        result
+    }
+    
+    // the oldest item
+    pub fn pop_front(&self) -> Option<T> {
+        let mut result = None;
+        
+        self.read(|vec| result = vec.pop_front());
+        
+        result
+    }
+    
+    pub fn peek<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let mut result = None;
+        
+        self.read(|vec| result = vec.front().map(f));
         
         result
     }
     
     pub fn read_all(self) -> Vec<T> {
-        let mut read = self.read.into_inner().unwrap();
+        let mut read: Vec<T> = self.read.into_inner().unwrap().into();
         let mut write = self.write.into_inner().unwrap();
         
         read.append(&mut write);
@@ -1,8 +1,6 @@
-
 use crate::utils::*;
 
 pub struct IoBuffer {
-    overlapped: NonNull<OVERLAPPED>,
     buffer: NonNull<[u8]>,
 }
 
@@ -10,24 +8,26 @@ impl IoBuffer {
     pub fn new(len: usize) -> Self {
         unsafe {
             Self {
-                overlapped: init_zero(alloc()),
                 buffer: init_zeroes(alloc_n(len)),
             }
         }
     }
     
-    pub fn set_event(&self, event: Event) {
-        unsafe {
-            self.overlapped.write(OVERLAPPED { hEvent: event.handle(), ..Default::default() });
-        }
+    /// # Safety
+    /// no pending io operation may be writing to the buffer
+    pub unsafe fn as_ref(&self) -> &[u8] {
+        unsafe { self.buffer.as_ref() }
     }
     
-    pub unsafe fn as_ref(&self) -> (&[u8], *mut OVERLAPPED) {
-        unsafe { (self.buffer.as_ref(), self.overlapped.as_ptr()) }
+    /// # Safety
+    /// no pending io operation may be using the buffer
+    pub unsafe fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { self.buffer.as_mut() }
     }
     
-    pub unsafe fn as_mut(&mut self) -> (&mut [u8], *mut OVERLAPPED) {
-        unsafe { (self.buffer.as_mut(), self.overlapped.as_ptr()) }
+    // stable pointer handed to pending io operations
+    pub(crate) fn as_ptr(&self) -> NonNull<[u8]> {
+        self.buffer
     }
 }
 
@@ -37,7 +37,6 @@ impl Drop for IoBuffer {
     fn drop(&mut self) {
         unsafe {
             dealloc(self.buffer);
-            dealloc(self.overlapped);
         }
     }
 }
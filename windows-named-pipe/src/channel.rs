@@ -1,15 +1,37 @@
-
-use std::sync::Arc;
+use std::{collections::VecDeque, mem::ManuallyDrop, sync::Arc, task::{Context, Poll}, time::Duration};
 
 use crate::utils::*;
 
+// every sender shares one side and every receiver shares the other, the buffer is disconnected once either side is dropped
+#[derive(Debug)]
+struct Side<T>(Arc<DoubleBuffer<T>>);
+
+impl<T> Side<T> {
+    fn into_buffer(self) -> Arc<DoubleBuffer<T>> {
+        let side = ManuallyDrop::new(self);
+        let Self(buffer) = &*side;
+        
+        unsafe { std::ptr::read(buffer) }
+    }
+}
+
+impl<T> Drop for Side<T> {
+    fn drop(&mut self) {
+        let Self(buffer) = self;
+        
+        buffer.disconnect();
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug)]
-pub struct Sender<T>(Arc<DoubleBuffer<T>>);
+pub struct Sender<T>(Arc<Side<T>>);
 
+// every item is received by only one of the receivers
+// items are received oldest first, in the order the sends took the write side, flushing never changes that
 #[repr(transparent)]
 #[derive(Debug)]
-pub struct Receiver<T>(Arc<DoubleBuffer<T>>);
+pub struct Receiver<T>(Arc<Side<T>>);
 
 #[repr(transparent)]
 #[derive(Debug)]
@@ -18,26 +40,65 @@ pub struct UniqueReceiver<T>(DoubleBuffer<T>);
 #[derive(Debug)]
 pub struct Channel<T>(Sender<T>, Receiver<T>);
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError<T> {
+    Disconnected(T), // every receiver is gone
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvError {
+    Timeout,
+    Disconnected, // every sender is gone and nothing is left
+}
+
+impl From<WaitError> for RecvError {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::Timeout => RecvError::Timeout,
+            WaitError::Disconnected => RecvError::Disconnected,
+        }
+    }
+}
+
 impl<T> Channel<T> {
-    pub fn new() -> Self {
-        let buffer = DoubleBuffer::new_arc();
+    fn from_buffer(buffer: DoubleBuffer<T>) -> Self {
+        let buffer = Arc::new(buffer);
         
-        Self(Sender(buffer.clone()), Receiver(buffer))
+        Self(Sender(Arc::new(Side(buffer.clone()))), Receiver(Arc::new(Side(buffer))))
+    }
+    
+    pub fn new() -> Self {
+        Self::from_buffer(DoubleBuffer::new())
     }
     
     pub fn with_capacity(capacity: usize) -> Self {
-        let buffer = Arc::new(DoubleBuffer::with_capacity(capacity));
-        
-        Self(Sender(buffer.clone()), Receiver(buffer))
+        Self::from_buffer(DoubleBuffer::with_capacity(capacity))
+    }
+    
+    // senders wait while the channel holds capacity items, anything fits into an empty channel
+    pub fn bounded(capacity: usize) -> Self {
+        Self::from_buffer(DoubleBuffer::bounded(capacity))
     }
     
-    pub unsafe fn sender(&self) -> &Sender<T> {
+    pub fn sender(&self) -> &Sender<T> {
         let Self(sender, _) = self;
         
         sender
     }
     
-    pub unsafe fn receiver(&self) -> &Receiver<T> {
+    pub fn receiver(&self) -> &Receiver<T> {
         let Self(_, receiver) = self;
         
         receiver
@@ -48,25 +109,46 @@ impl<T> Channel<T> {
         
         (sender, receiver)
     }
+    
+    // disconnects the channel while both sides are still around, as if one of them was dropped
+    pub fn disconnect(&self) {
+        self.sender().buffer().disconnect();
+    }
+    
+    // only for reusing a channel that nothing uses anymore
+    pub fn reconnect(&self) {
+        self.sender().buffer().reconnect();
+    }
 }
 
-pub unsafe fn clone_sender<T>(sender: &Sender<T>) -> Sender<T> {
-    let Sender(buffer) = sender;
-    
-    Sender(buffer.clone())
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub unsafe fn clone_receiver<T>(receiver: &Receiver<T>) -> Receiver<T> {
-    let Receiver(buffer) = receiver;
-    
-    Receiver(buffer.clone())
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let Self(side) = self;
+        
+        Self(side.clone())
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let Self(side) = self;
+        
+        Self(side.clone())
+    }
 }
 
 impl<T> Sender<T> {
     fn buffer(&self) -> &DoubleBuffer<T> {
-        let Self(buffer) = self;
+        let Self(side) = self;
+        let Side(buffer) = &**side;
         
-        &buffer
+        buffer
     }
     
     pub fn flush(&self) {
@@ -77,24 +159,87 @@ impl<T> Sender<T> {
         self.buffer().try_flush()
     }
     
-    pub fn send(&self, t: T) {
-        self.buffer().push(t);
+    pub fn capacity(&self) -> Option<usize> {
+        self.buffer().capacity()
+    }
+    
+    pub fn is_disconnected(&self) -> bool {
+        self.buffer().is_disconnected()
+    }
+    
+    // blocks while a bounded channel is full
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.buffer().push(t).map_err(|(t, _)| SendError::Disconnected(t))
+    }
+    
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        self.buffer().push_timeout(t, Some(Duration::ZERO)).map_err(|error| match error {
+            (t, WaitError::Timeout) => TrySendError::Full(t),
+            (t, WaitError::Disconnected) => TrySendError::Disconnected(t),
+        })
+    }
+    
+    pub fn send_timeout(&self, t: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.buffer().push_timeout(t, Some(timeout)).map_err(|error| match error {
+            (t, WaitError::Timeout) => SendTimeoutError::Timeout(t),
+            (t, WaitError::Disconnected) => SendTimeoutError::Disconnected(t),
+        })
+    }
+    
+    // waits for room in a bounded channel without blocking the thread
+    pub async fn send_async(&self, t: T) -> Result<(), SendError<T>> {
+        let mut t = Some(t);
+        
+        std::future::poll_fn(|cx| self.buffer().poll_write_reserve(1, cx.waker(), |vec| vec.push(t.take().unwrap()))).await
+            .map_err(|_| SendError::Disconnected(t.take().unwrap()))
     }
     
-    pub fn send_vec(&self, vec: &mut Vec<T>) {
-        self.buffer().write_vec(vec);
+    // blocks until the whole vec fits into a bounded channel, the vec is left untouched if it fails
+    pub fn send_vec(&self, vec: &mut Vec<T>) -> Result<(), SendError<()>> {
+        self.buffer().write_vec(vec).map_err(|_| SendError::Disconnected(()))
     }
     
+    /// ignores the capacity of a bounded channel and disconnection
+    ///
+    /// # Safety
+    /// f runs with the write side locked and must not use the channel, which would deadlock
     pub unsafe fn raw_buffer(&self, f: impl FnOnce(&mut Vec<T>)) {
         self.buffer().write(f);
     }
+    
+    // waits until the receiver has taken everything sent so far
+    pub fn wait_drained(&self, timeout: Option<Duration>) -> Result<(), WaitError> {
+        self.buffer().wait_drained(timeout)
+    }
+    
+    /// waits until n more items fit before calling f, f is not called if the wait fails
+    ///
+    /// # Safety
+    /// f runs with the write side locked and must not use the channel, and should add at most n items to keep the capacity
+    pub unsafe fn raw_buffer_reserve(&self, n: usize, timeout: Option<Duration>, f: impl FnOnce(&mut Vec<T>)) -> Result<(), WaitError> {
+        self.buffer().write_reserve(n, timeout, f)
+    }
+    
+    /// calls f once n more items fit, the task is woken when a receive makes room
+    ///
+    /// # Safety
+    /// f runs with the write side locked and must not use the channel, and should add at most n items to keep the capacity
+    pub unsafe fn poll_raw_buffer_reserve(&self, cx: &mut Context<'_>, n: usize, f: impl FnOnce(&mut Vec<T>)) -> Poll<Result<(), WaitError>> {
+        self.buffer().poll_write_reserve(n, cx.waker(), f)
+    }
+    
+    // ready once the receiver has taken everything sent so far
+    pub fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<Result<(), WaitError>> {
+        self.buffer().poll_drained(cx.waker())
+    }
 }
 
 impl<T> Receiver<T> {
     fn buffer(&self) -> &DoubleBuffer<T> {
-        let Self(buffer) = self;
+        let Self(side) = self;
+        let Side(buffer) = &**side;
         
-        &buffer
+        buffer
     }
     
     pub fn flush(&self) {
@@ -105,22 +250,104 @@ impl<T> Receiver<T> {
         self.buffer().try_flush()
     }
     
+    // true once every sender is gone, there may still be items left
+    pub fn is_disconnected(&self) -> bool {
+        self.buffer().is_disconnected()
+    }
+    
     pub fn receive_latest(&self) -> Option<T> {
         self.buffer().pop()
     }
     
+    pub fn receive_next(&self) -> Option<T> {
+        self.buffer().pop_front()
+    }
+    
+    // up to n items, oldest first
+    pub fn receive_n(&self, n: usize) -> Vec<T> {
+        self.buffer().read_n(n)
+    }
+    
+    // oldest first
     pub fn receive_all(&self) -> Vec<T> {
         self.buffer().read_vec()
     }
     
-    pub unsafe fn raw_buffer(&self, f: impl FnOnce(&mut Vec<T>)) {
+    // receives oldest first until the channel is empty, items sent in the meantime are included
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.receive_next())
+    }
+    
+    // looks at the oldest item without receiving it
+    pub fn peek<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.buffer().peek(f)
+    }
+    
+    // blocks until there is an item and returns the oldest one
+    pub fn recv_blocking(&self) -> Result<T, RecvError> {
+        Ok(self.buffer().pop_front_until(None)?)
+    }
+    
+    // returns the oldest item once there is one
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvError> {
+        Ok(self.buffer().pop_front_until(Some(timeout))?)
+    }
+    
+    // the oldest item once there is one, the task is woken by the next send otherwise
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        let mut result = None;
+        
+        self.buffer().poll_read(cx.waker(), |vec| {
+            result = vec.pop_front();
+            result.is_some()
+        }).map(|ready| ready.map(|()| result.take().unwrap()).map_err(RecvError::from))
+    }
+    
+    // waits for the oldest item without blocking the thread
+    pub async fn recv_async(&self) -> Result<T, RecvError> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+    
+    // wakes every receiver waiting in raw_buffer_until, which calls its f again
+    pub fn wake(&self) {
+        self.buffer().wake_readers();
+    }
+    
+    // returns false if nothing arrived in time or nothing ever will, without receiving anything
+    pub fn wait_nonempty(&self, timeout: Duration) -> bool {
+        self.buffer().read_until(Some(timeout), |vec| !vec.is_empty()).is_ok()
+    }
+    
+    /// oldest first
+    ///
+    /// # Safety
+    /// f runs with both sides locked and must not use the channel, which would deadlock
+    pub unsafe fn raw_buffer(&self, f: impl FnOnce(&mut VecDeque<T>)) {
         self.buffer().read(f);
     }
     
+    /// calls f again after every send until it returns true
+    ///
+    /// # Safety
+    /// f runs with both sides locked and must not use the channel, which would deadlock
+    pub unsafe fn raw_buffer_until(&self, timeout: Option<Duration>, f: impl FnMut(&mut VecDeque<T>) -> bool) -> Result<(), RecvError> {
+        Ok(self.buffer().read_until(timeout, f)?)
+    }
+    
+    /// ready once f returns true, the task is woken by the next send otherwise
+    ///
+    /// # Safety
+    /// f runs with both sides locked and must not use the channel, which would deadlock
+    pub unsafe fn poll_raw_buffer_until(&self, cx: &mut Context<'_>, f: impl FnOnce(&mut VecDeque<T>) -> bool) -> Poll<Result<(), RecvError>> {
+        self.buffer().poll_read(cx.waker(), f).map_err(RecvError::from)
+    }
+    
+    // succeeds once this is the only handle left to the channel
     pub fn unique(self) -> Result<UniqueReceiver<T>, Self> {
-        let Receiver(buffer) = self;
+        let Receiver(side) = self;
+        let side = Arc::try_unwrap(side).map_err(Receiver)?;
         
-        Arc::try_unwrap(buffer).map(UniqueReceiver).map_err(Receiver)
+        Arc::try_unwrap(side.into_buffer()).map(UniqueReceiver).map_err(|buffer| Receiver(Arc::new(Side(buffer))))
     }
 }
 
@@ -128,21 +355,42 @@ impl<T> UniqueReceiver<T> {
     fn buffer(&self) -> &DoubleBuffer<T> {
         let Self(buffer) = self;
         
-        &buffer
+        buffer
     }
     
     pub fn receive_latest(&self) -> Option<T> {
         self.buffer().pop()
     }
     
+    pub fn receive_next(&self) -> Option<T> {
+        self.buffer().pop_front()
+    }
+    
+    // up to n items, oldest first
+    pub fn receive_n(&self, n: usize) -> Vec<T> {
+        self.buffer().read_n(n)
+    }
+    
+    // oldest first
     pub fn receive_all(self) -> Vec<T> {
         let Self(buffer) = self;
         
         buffer.read_all()
     }
     
-    pub unsafe fn raw_buffer(&self, f: impl FnOnce(&mut Vec<T>)) {
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.receive_next())
+    }
+    
+    pub fn peek<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.buffer().peek(f)
+    }
+    
+    /// oldest first
+    ///
+    /// # Safety
+    /// f runs with both sides locked and must not use the channel, which would deadlock
+    pub unsafe fn raw_buffer(&self, f: impl FnOnce(&mut VecDeque<T>)) {
         self.buffer().read(f);
     }
 }
-
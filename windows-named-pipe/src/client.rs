@@ -1,13 +1,9 @@
+use std::{thread::JoinHandle, time::Duration};
 
-use std::{thread::JoinHandle, time::Duration, u32};
+use crate::{error::ErrorContext, handshake::*, sys::{self, PipeWait}, utils::*};
 
-use windows::Win32::{Foundation::{CloseHandle, ERROR_FILE_NOT_FOUND, ERROR_PIPE_BUSY, ERROR_SEM_TIMEOUT, GENERIC_ACCESS_RIGHTS, GENERIC_READ, GENERIC_WRITE}, Storage::FileSystem::{CreateFileA, FILE_FLAG_OVERLAPPED, FILE_SHARE_NONE, OPEN_EXISTING}, System::Pipes::{WaitNamedPipeA, NMPWAIT_USE_DEFAULT_WAIT, NMPWAIT_WAIT_FOREVER}};
-
-use crate::utils::*;
-
-#[repr(transparent)]
 #[derive(Debug)]
-pub struct Client(HANDLE);
+pub struct Client(RawHandle, NamedPipePath); // the path is kept for the errors of initialize
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum NamedPipeCheck {
@@ -17,63 +13,55 @@ pub enum NamedPipeCheck {
 }
 
 impl Client {
-    pub fn check_pipe(pipe_name: &NamedPipePath) -> WindowsResult<NamedPipeCheck> {
-        unsafe {
-            match WaitNamedPipeA(pipe_name.as_pcstr(), 0) {
-                Ok(_) => Ok(NamedPipeCheck::Available),
-                _ if GetLastError() == ERROR_FILE_NOT_FOUND => Ok(NamedPipeCheck::Unavailable),
-                _ if GetLastError() == ERROR_PIPE_BUSY => Ok(NamedPipeCheck::Busy),
-                Err(error) => Err(error),
-            }
-        }
+    pub fn check_pipe(pipe_name: &NamedPipePath) -> Result<NamedPipeCheck, Error> {
+        sys::check_pipe(pipe_name).on_pipe(pipe_name, None)
     }
     
-    fn wait_pipe(pipe_name: &NamedPipePath, timeout: u32) -> WindowsResult<Option<Self>> {
-        unsafe {
-            match WaitNamedPipeA(pipe_name.as_pcstr(), timeout) {
-                Ok(_) => {
-                    let GENERIC_ACCESS_RIGHTS(access) = GENERIC_READ | GENERIC_WRITE;
-                    
-                    Ok(Some(Self(CreateFileA(
-                        pipe_name.as_pcstr(),
-                        access,
-                        FILE_SHARE_NONE,
-                        None,
-                        OPEN_EXISTING,
-                        FILE_FLAG_OVERLAPPED,
-                        None,
-                    )?)))
-                }
-                _ if GetLastError() == ERROR_SEM_TIMEOUT => Ok(None),
-                Err(error) => Err(error),
-            }
-        }
+    fn wait_pipe(pipe_name: &NamedPipePath, wait: PipeWait) -> Result<Option<Self>, Error> {
+        sys::wait_pipe(pipe_name, wait).map(|handle| handle.map(|handle| Self(handle, pipe_name.to_owned()))).on_pipe(pipe_name, None)
     }
     
-    pub fn wait(pipe_name: &NamedPipePath) -> WindowsResult<Self> {
-        Self::wait_pipe(pipe_name, NMPWAIT_WAIT_FOREVER).map(Option::unwrap)
+    // fails with NotFound if there is no such pipe
+    pub fn wait(pipe_name: &NamedPipePath) -> Result<Self, Error> {
+        Self::wait_pipe(pipe_name, PipeWait::Forever).map(Option::unwrap)
     }
     
-    pub fn try_wait(pipe_name: &NamedPipePath, timeout: Duration) -> WindowsResult<Option<Self>> {
-        Self::wait_pipe(pipe_name, timeout.as_millis().try_into().unwrap_or(u32::MAX))
+    pub fn try_wait(pipe_name: &NamedPipePath, timeout: Duration) -> Result<Option<Self>, Error> {
+        Self::wait_pipe(pipe_name, PipeWait::Timeout(timeout))
     }
     
-    pub fn try_wait_default(pipe_name: &NamedPipePath) -> WindowsResult<Option<Self>> {
-        Self::wait_pipe(pipe_name, NMPWAIT_USE_DEFAULT_WAIT)
+    pub fn try_wait_default(pipe_name: &NamedPipePath) -> Result<Option<Self>, Error> {
+        Self::wait_pipe(pipe_name, PipeWait::Default)
     }
     
-    pub fn wait_in_background(pipe_name: &NamedPipePath, callback: impl FnOnce(WindowsResult<Self>) + Send + 'static) -> JoinHandle<()> {
+    pub fn wait_in_background(pipe_name: &NamedPipePath, callback: impl FnOnce(Result<Self, Error>) + Send + 'static) -> JoinHandle<()> {
         let pipe_name = pipe_name.to_owned();
         new_thread(move || callback(Self::wait(&pipe_name)))
     }
     
-    pub fn initialize(&self, buffer: NamedPipeBuffer, runtime: impl NamedPipeRuntimeExecutor) -> WindowsResult<NamedPipe> {
-        let Self(handle) = self;
-        NamedPipe::new(*handle, buffer, runtime)
+    pub fn initialize(&self, buffer: NamedPipeBuffer, runtime: impl NamedPipeRuntimeExecutor) -> Result<NamedPipe, Error> {
+        let Self(handle, path) = self;
+        let transport = unsafe { sys::share_handle(*handle) }.map_err(Error::from).and_then(PipeTransport::new).on_pipe(path, None)?;
+        
+        NamedPipe::new(transport, buffer, runtime).on_pipe(path, None)
+    }
+    
+    // the pipe is interrupted and joined if the handshake fails
+    pub fn initialize_with_handshake(&self, buffer: NamedPipeBuffer, runtime: impl NamedPipeRuntimeExecutor, handshake: &Handshake) -> Result<NamedPipe, HandshakeError> {
+        let mut pipe = self.initialize(buffer, runtime)?;
+        
+        match pipe.handshake(handshake) {
+            Ok(_) => Ok(pipe),
+            Err(error) => {
+                let _ = pipe.interrupt();
+                let _ = pipe.join();
+                Err(error)
+            }
+        }
     }
     
-    pub fn close(self) -> WindowsResult<()> {
-        let Self(handle) = self;
-        unsafe { CloseHandle(handle) }
+    pub fn close(self) -> Result<(), Error> {
+        let Self(handle, path) = self;
+        unsafe { sys::close_handle(handle) }.on_pipe(&path, None)
     }
 }
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak, atomic::{AtomicBool, Ordering}},
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use crate::utils::*;
+
+// what a dispatch thread hands every message arriving on its pipe to
+pub(crate) trait Dispatch: Send + Sync + 'static {
+    fn receive(&self, message: &[u8]);
+    
+    // the thread wakes up by then to call expire
+    fn next_deadline(&self) -> Option<Instant> {
+        None
+    }
+    
+    fn expire(&self, _now: Instant) {}
+    
+    // nothing is received after this, the error is why the stream cannot be read anymore
+    // None if the pipe closed or the dispatcher was dropped
+    fn close(&self, error: Option<FrameError>);
+}
+
+#[derive(Default)]
+struct Flags {
+    stop: AtomicBool,
+    woken: AtomicBool, // the deadline may have moved, the thread has to look again
+}
+
+// a thread that takes every message arriving on a pipe and hands it to D, until the pipe closes or the dispatcher is dropped
+// it blocks on the pipe in between, and is woken through the read channel of the pipe
+pub(crate) struct Dispatcher<D> {
+    pipe: Arc<NamedPipe>,
+    handler: Arc<D>,
+    flags: Arc<Flags>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// wakes the thread of a dispatcher from anywhere, so it looks at next_deadline again
+#[derive(Clone)]
+pub(crate) struct DispatchWaker {
+    pipe: Weak<NamedPipe>,
+    flags: Arc<Flags>,
+}
+
+impl DispatchWaker {
+    pub fn wake(&self) {
+        self.flags.woken.store(true, Ordering::Relaxed);
+        
+        if let Some(pipe) = self.pipe.upgrade() {
+            pipe.wake_readers();
+        }
+    }
+}
+
+impl<D: Dispatch> Dispatcher<D> {
+    pub fn new(pipe: NamedPipe, codec: impl MessageCodec + Send + 'static, handler: D) -> Self {
+        Self::with_waker(pipe, codec, |_| handler)
+    }
+    
+    // for a handler that has to wake the thread itself
+    pub fn with_waker(pipe: NamedPipe, codec: impl MessageCodec + Send + 'static, handler: impl FnOnce(DispatchWaker) -> D) -> Self {
+        let pipe = Arc::new(pipe);
+        let flags = Arc::new(Flags::default());
+        let handler = Arc::new(handler(DispatchWaker { pipe: Arc::downgrade(&pipe), flags: flags.clone() }));
+        
+        let thread = {
+            let pipe = pipe.clone();
+            let handler = handler.clone();
+            let flags = flags.clone();
+            
+            new_thread(move || Self::dispatch(&pipe, &codec, &*handler, &flags))
+        };
+        
+        Self { pipe, handler, flags, thread: Some(thread) }
+    }
+    
+    fn dispatch(pipe: &NamedPipe, codec: &impl MessageCodec, handler: &D, flags: &Flags) {
+        let error = loop {
+            if flags.stop.load(Ordering::Relaxed) {
+                break None;
+            }
+            
+            let timeout = handler.next_deadline().map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let closed = pipe.state() != NamedPipeState::Open; // checked first, nothing arrives after the disconnect
+            let woken = || flags.stop.load(Ordering::Relaxed) || flags.woken.swap(false, Ordering::Relaxed);
+            
+            match pipe.recv_message_until(codec, timeout, woken) {
+                Ok(Some(message)) => handler.receive(&message),
+                Ok(None) if closed => break None,
+                Ok(None) => {}
+                Err(error) => break Some(error),
+            }
+            
+            handler.expire(Instant::now());
+        };
+        
+        handler.close(error);
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        &self.pipe
+    }
+    
+    pub fn handler(&self) -> &Arc<D> {
+        &self.handler
+    }
+    
+    // makes the thread look at next_deadline again
+    pub fn wake(&self) {
+        self.flags.woken.store(true, Ordering::Relaxed);
+        self.pipe.wake_readers();
+    }
+}
+
+impl<D> Drop for Dispatcher<D> {
+    fn drop(&mut self) {
+        self.flags.stop.store(true, Ordering::Relaxed);
+        self.pipe.wake_readers();
+        
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub(crate) trait CallError {
+    fn timeout() -> Self;
+    fn disconnected() -> Self;
+    fn protocol(message: String) -> Self;
+}
+
+struct PendingCall<R, E> {
+    deadline: Option<Instant>,
+    callback: Box<dyn FnOnce(Result<R, E>) + Send>,
+}
+
+struct CallsState<R, E> {
+    next_id: u64,
+    pending: HashMap<u64, PendingCall<R, E>>,
+    closed: bool, // set once nothing will be resolved anymore, new calls fail right away
+}
+
+// reads the calls a message answers
+pub(crate) type Responses<R, E> = fn(&[u8]) -> Vec<(u64, Result<R, E>)>;
+
+// calls in flight, resolved by the responses the dispatch thread receives or failed once they expire
+pub(crate) struct Calls<R, E> {
+    state: Mutex<CallsState<R, E>>,
+    responses: Responses<R, E>,
+}
+
+impl<R, E: CallError> Calls<R, E> {
+    pub fn new(responses: Responses<R, E>) -> Self {
+        Self {
+            state: Mutex::new(CallsState { next_id: 0, pending: HashMap::new(), closed: false }),
+            responses,
+        }
+    }
+    
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+    
+    // returns the id of the call, or None after failing it with Disconnected right away
+    fn register(&self, deadline: Option<Instant>, callback: impl FnOnce(Result<R, E>) + Send + 'static) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        
+        if state.closed {
+            drop(state);
+            callback(Err(E::disconnected()));
+            return None;
+        }
+        
+        let id = state.next_id;
+        
+        state.next_id += 1;
+        state.pending.insert(id, PendingCall { deadline, callback: Box::new(callback) });
+        
+        Some(id)
+    }
+    
+    pub fn resolve(&self, id: u64, result: Result<R, E>) {
+        let call = self.state.lock().unwrap().pending.remove(&id);
+        
+        // responses to calls that timed out already are dropped
+        if let Some(call) = call {
+            (call.callback)(result);
+        }
+    }
+    
+    // forgets the calls without resolving them
+    #[cfg(feature = "json")]
+    pub fn remove(&self, ids: impl IntoIterator<Item = u64>) {
+        let mut state = self.state.lock().unwrap();
+        
+        for id in ids {
+            state.pending.remove(&id);
+        }
+    }
+    
+    // fails every pending call and every call made from now on
+    fn close_with(&self, error: impl Fn() -> E) {
+        let pending = {
+            let mut state = self.state.lock().unwrap();
+            
+            state.closed = true;
+            std::mem::take(&mut state.pending)
+        };
+        
+        for (_, call) in pending {
+            (call.callback)(Err(error()));
+        }
+    }
+}
+
+impl<R: Send + 'static, E: CallError + Send + 'static> Dispatch for Calls<R, E> {
+    fn receive(&self, message: &[u8]) {
+        for (id, result) in (self.responses)(message) {
+            self.resolve(id, result);
+        }
+    }
+    
+    fn next_deadline(&self) -> Option<Instant> {
+        self.state.lock().unwrap().pending.values().filter_map(|call| call.deadline).min()
+    }
+    
+    fn expire(&self, now: Instant) {
+        let expired: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            let ids: Vec<_> = state.pending.iter().filter(|(_, call)| call.deadline.is_some_and(|deadline| deadline <= now)).map(|(&id, _)| id).collect();
+            
+            ids.into_iter().filter_map(|id| state.pending.remove(&id)).collect()
+        };
+        
+        for call in expired {
+            (call.callback)(Err(E::timeout()));
+        }
+    }
+    
+    fn close(&self, error: Option<FrameError>) {
+        match error {
+            None | Some(FrameError::Truncated { .. }) => self.close_with(E::disconnected),
+            Some(error) => {
+                let message = error.to_string();
+                
+                self.close_with(|| E::protocol(message.clone()));
+            }
+        }
+    }
+}
+
+impl<R: Send + 'static, E: CallError + Send + 'static> Dispatcher<Calls<R, E>> {
+    // the callback runs on the dispatch thread once the call resolves or expires, right away if nothing can be resolved anymore
+    pub fn call(&self, deadline: Option<Instant>, callback: impl FnOnce(Result<R, E>) + Send + 'static) -> Option<u64> {
+        let id = self.handler.register(deadline, callback)?;
+        
+        if deadline.is_some() {
+            self.wake();
+        }
+        
+        Some(id)
+    }
+}
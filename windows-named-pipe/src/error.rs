@@ -0,0 +1,143 @@
+use std::{fmt, io};
+
+use crate::{sys, utils::*};
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Disconnected, // the other end is gone or the pipe was closed
+    Busy, // every instance of the pipe is connected
+    Timeout,
+    WouldBlock, // a bounded write queue is full and the write was not allowed to wait
+    NotFound, // there is no pipe of that name
+    Protocol(String), // the other end sent something that cannot be made sense of
+    RuntimePanic,
+    Os(io::Error),
+}
+
+// what went wrong and on which pipe, if that is known
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    path: Option<NamedPipePath>,
+    index: Option<usize>, // of the instance in its server
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Disconnected => write!(f, "pipe is disconnected"),
+            ErrorKind::Busy => write!(f, "every pipe instance is busy"),
+            ErrorKind::Timeout => write!(f, "timed out"),
+            ErrorKind::WouldBlock => write!(f, "write queue is full"),
+            ErrorKind::NotFound => write!(f, "pipe does not exist"),
+            ErrorKind::Protocol(message) => write!(f, "protocol error: {message}"),
+            ErrorKind::RuntimePanic => write!(f, "pipe runtime panicked"),
+            ErrorKind::Os(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        
+        if let Some(path) = &self.path {
+            write!(f, " on {path}")?;
+        }
+        
+        if let Some(index) = self.index {
+            write!(f, " (instance {index})")?;
+        }
+        
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Os(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl From<sys::Error> for Error {
+    fn from(error: sys::Error) -> Self {
+        Self::new(sys::error_kind(error))
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match &error.kind {
+            ErrorKind::Disconnected => io::ErrorKind::BrokenPipe,
+            ErrorKind::Busy => io::ErrorKind::ResourceBusy,
+            ErrorKind::Timeout => io::ErrorKind::TimedOut,
+            ErrorKind::WouldBlock => io::ErrorKind::WouldBlock,
+            ErrorKind::NotFound => io::ErrorKind::NotFound,
+            ErrorKind::Protocol(_) => io::ErrorKind::InvalidData,
+            ErrorKind::RuntimePanic => io::ErrorKind::Other,
+            ErrorKind::Os(error) => error.kind(),
+        };
+        
+        match error {
+            Error { kind: ErrorKind::Os(error), path: None, index: None } => error, // keeps the os error code
+            error => io::Error::new(kind, error),
+        }
+    }
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self { kind, path: None, index: None }
+    }
+    
+    pub fn with_path(self, path: &NamedPipePath) -> Self {
+        Self { path: Some(path.clone()), ..self }
+    }
+    
+    pub fn with_index(self, index: usize) -> Self {
+        Self { index: Some(index), ..self }
+    }
+    
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+    
+    pub fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+    
+    pub fn path(&self) -> Option<&NamedPipePath> {
+        self.path.as_ref()
+    }
+    
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+}
+
+// adds the pipe to errors on their way out
+pub(crate) trait ErrorContext<T> {
+    fn on_pipe(self, path: &NamedPipePath, index: Option<usize>) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ErrorContext<T> for Result<T, E> {
+    fn on_pipe(self, path: &NamedPipePath, index: Option<usize>) -> Result<T, Error> {
+        self.map_err(|error| {
+            let error = error.into().with_path(path);
+            
+            match index {
+                Some(index) => error.with_index(index),
+                None => error,
+            }
+        })
+    }
+}
@@ -1,101 +1,97 @@
+use std::{sync::Mutex, time::Duration};
 
-use std::sync::Mutex;
+use crate::{sys, utils::*};
 
-use windows::Win32::{Foundation::{CloseHandle, WAIT_EVENT, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT}, System::Threading::{CreateEventA, ResetEvent, SetEvent, WaitForMultipleObjects, WaitForSingleObject, INFINITE}};
-
-use crate::utils::*;
-
-#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct Event(HANDLE);
+pub struct Event(RawHandle);
 
 unsafe impl Sync for Event {}
 unsafe impl Send for Event {}
 
+impl Default for Event {
+    fn default() -> Self {
+        unsafe { Self::null() }
+    }
+}
+
 impl Event {
-    pub unsafe fn new(handle: HANDLE) -> Self {
+    /// # Safety
+    /// the handle must be an event that stays open as long as the Event is used
+    pub unsafe fn new(handle: RawHandle) -> Self {
         Self(handle)
     }
     
-    pub unsafe fn handle(self) -> HANDLE {
+    /// # Safety
+    /// the handle is still owned by its EventOwner and must not be closed
+    pub unsafe fn handle(self) -> RawHandle {
         let Event(handle) = self;
         
         handle
     }
     
-    pub fn signal(self) -> WindowsResult<bool> {
-        unsafe {
-            match WaitForSingleObject(self.handle(), 0) {
-                WAIT_OBJECT_0 => Ok(true),
-                WAIT_TIMEOUT => Ok(false),
-                WAIT_FAILED => Err(WindowsError::from_win32()),
-                _ => unreachable!(),
-            }
-        }
+    pub fn signal(self) -> Result<bool, Error> {
+        unsafe { sys::event_signal(self.handle()) }.map_err(Error::from)
     }
     
-    pub fn set(self) -> WindowsResult<()> {
-        unsafe { SetEvent(self.handle()) }
+    pub fn set(self) -> Result<(), Error> {
+        unsafe { sys::set_event(self.handle()) }.map_err(Error::from)
     }
     
-    pub fn reset(self) -> WindowsResult<()> {
-        unsafe { ResetEvent(self.handle()) }
+    pub fn reset(self) -> Result<(), Error> {
+        unsafe { sys::reset_event(self.handle()) }.map_err(Error::from)
     }
     
+    /// # Safety
+    /// the null event must not be set, reset or waited on
     pub unsafe fn null() -> Self {
-        Self(HANDLE(std::ptr::null_mut()))
+        Self(sys::null_handle())
     }
 }
 
+// returns true if nothing was signalled before the timeout, waits forever without one
+pub(crate) fn wait_signals(event_slice: &[Event], timeout: Option<Duration>, f: impl FnMut(usize)) -> Result<bool, Error> {
+    let handle_slice = unsafe { std::slice::from_raw_parts(event_slice.as_ptr() as *const RawHandle, event_slice.len()) };
+    
+    unsafe { sys::wait_handles(handle_slice, timeout, f) }.map_err(Error::from)
+}
+
 pub trait EventPool {
-    fn wait_signals_index(&self, f: impl FnMut(usize)) -> WindowsResult<()>;
-    fn wait_signals_event(&self, f: impl FnMut(Event)) -> WindowsResult<()>;
+    fn wait_signals_index(&self, f: impl FnMut(usize)) -> Result<(), Error>;
+    fn wait_signals_event(&self, f: impl FnMut(Event)) -> Result<(), Error>;
+    
+    // these return true if nothing was signalled before the timeout
+    fn wait_signals_index_timeout(&self, timeout: Duration, f: impl FnMut(usize)) -> Result<bool, Error>;
+    fn wait_signals_event_timeout(&self, timeout: Duration, f: impl FnMut(Event)) -> Result<bool, Error>;
 }
 
 impl<T: AsRef<[Event]>> EventPool for T {
-    fn wait_signals_index(&self, mut f: impl FnMut(usize)) -> WindowsResult<()> {
-        let event_slice = self.as_ref();
-        let handle_slice = unsafe { std::slice::from_raw_parts(event_slice.as_ptr() as *const HANDLE, event_slice.len()) };
-        
-        let WAIT_EVENT(zero) = WAIT_OBJECT_0;
-        
-        unsafe {
-            let mut code = match WaitForMultipleObjects(handle_slice, false, INFINITE) {
-                WAIT_FAILED => Err(WindowsError::from_win32())?,
-                WAIT_EVENT(code) => code,
-            };
-            
-            loop {
-                let index = (code - zero) as usize;
-                
-                ResetEvent(handle_slice[index])?;
-                f(index);
-                
-                code = match WaitForMultipleObjects(handle_slice, false, 0) {
-                    WAIT_FAILED => Err(WindowsError::from_win32())?,
-                    WAIT_TIMEOUT => break,
-                    WAIT_EVENT(code) => code,
-                };
-            }
-        }
-        
-        Ok(())
+    fn wait_signals_index(&self, f: impl FnMut(usize)) -> Result<(), Error> {
+        wait_signals(self.as_ref(), None, f).map(|_| ())
     }
     
-    fn wait_signals_event(&self, mut f: impl FnMut(Event)) -> WindowsResult<()> {
+    fn wait_signals_event(&self, mut f: impl FnMut(Event)) -> Result<(), Error> {
         self.wait_signals_index(|index| f(self.as_ref()[index]))
     }
+    
+    fn wait_signals_index_timeout(&self, timeout: Duration, f: impl FnMut(usize)) -> Result<bool, Error> {
+        wait_signals(self.as_ref(), Some(timeout), f)
+    }
+    
+    fn wait_signals_event_timeout(&self, timeout: Duration, mut f: impl FnMut(Event)) -> Result<bool, Error> {
+        self.wait_signals_index_timeout(timeout, |index| f(self.as_ref()[index]))
+    }
 }
 
 pub struct EventManager;
 
 static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
 
-fn create_event() -> WindowsResult<Event> {
-    unsafe { CreateEventA(None, true, false, None).map(Event) }
+fn create_event() -> Result<Event, Error> {
+    sys::create_event().map(Event).map_err(Error::from)
 }
 
-fn register(events: &mut Vec<Event>) -> WindowsResult<Event> {
+fn register(events: &mut Vec<Event>) -> Result<Event, Error> {
     if let Some(event) = events.last() {
         event.reset()?;
         
@@ -106,23 +102,21 @@ fn register(events: &mut Vec<Event>) -> WindowsResult<Event> {
     }
 }
 
-fn register_n<const N: usize>(mut f: impl FnMut() -> WindowsResult<Event>) -> WindowsResult<[Event; N]> {
+fn register_n<const N: usize>(mut f: impl FnMut() -> Result<Event, Error>) -> Result<[Event; N], Error> {
     let mut events = [unsafe { Event::null() }; N];
     
     for i in 0..N {
         events[i] = match f() {
             Ok(event) => event,
             Err(error) => {
-                for i in 0..i {
-                    EventManager::unregister(events[i]);
-                }
+                events[..i].iter().copied().for_each(EventManager::unregister);
                 
                 return Err(error);
             }
         }
     }
     
-    return Ok(events);
+    Ok(events)
 }
 
 #[allow(unused_must_use)]
@@ -135,31 +129,31 @@ fn try_lock_events<T>(f: impl FnOnce(Option<&mut Vec<Event>>) -> T) -> T {
 }
 
 impl EventManager {
-    pub fn register() -> WindowsResult<Event> {
-        Self::try_register().and_then(|event| event.map_or_else(|| create_event(), Ok))
+    pub fn register() -> Result<Event, Error> {
+        Self::try_register().and_then(|event| event.map_or_else(create_event, Ok))
     }
     
-    pub fn register_n<const N: usize>() -> WindowsResult<[Event; N]> {
+    pub fn register_n<const N: usize>() -> Result<[Event; N], Error> {
         try_lock_events(|events| match events {
             Some(events) => register_n(|| register(events)),
             None => register_n(create_event),
         })
     }
     
-    pub fn register_blocking() -> WindowsResult<Event> {
+    pub fn register_blocking() -> Result<Event, Error> {
         register(&mut EVENTS.lock().unwrap())
     }
     
-    pub fn register_n_blocking<const N: usize>() -> WindowsResult<[Event; N]> {
+    pub fn register_n_blocking<const N: usize>() -> Result<[Event; N], Error> {
         let mut events = EVENTS.lock().unwrap();
         register_n(|| register(&mut events))
     }
     
-    pub fn try_register() -> WindowsResult<Option<Event>> {
-        try_lock_events(|events| events.map(|events| register(events))).transpose()
+    pub fn try_register() -> Result<Option<Event>, Error> {
+        try_lock_events(|events| events.map(register)).transpose()
     }
     
-    pub fn try_register_n<const N: usize>() -> WindowsResult<Option<[Event; N]>> {
+    pub fn try_register_n<const N: usize>() -> Result<Option<[Event; N]>, Error> {
         try_lock_events(|events| events.map(|events| register_n(|| register(events)))).transpose()
     }
     
@@ -167,14 +161,14 @@ impl EventManager {
         EVENTS.lock().unwrap().push(event)
     }
     
-    pub fn close_events() -> Result<(), (WindowsError, HANDLE)> {
+    pub fn close_events() -> Result<(), (Error, RawHandle)> {
         unsafe {
             let mut events = EVENTS.lock().unwrap();
             
             while let Some(Event(event)) = events.pop() {
-                match CloseHandle(event) {
+                match sys::close_handle(event) {
                     Ok(()) => {}
-                    Err(error) => return Err((error, event))
+                    Err(error) => return Err((error.into(), event))
                 }
             }
             
@@ -0,0 +1,273 @@
+use std::{collections::VecDeque, fmt};
+
+use crate::utils::*;
+
+// every frame is its payload length as a little endian u32 followed by the payload
+pub const HEADER_SIZE: usize = size_of::<u32>();
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 << 20;
+
+// headers of content length frames longer than this are rejected
+pub const MAX_HEADER_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Oversize { len: usize, max: usize }, // the stream cannot be read past an oversize frame
+    Truncated { available: usize }, // the stream ended in the middle of a frame
+    Malformed(String), // the stream cannot be read past a malformed header
+    Pipe(Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Oversize { len, max } => write!(f, "frame of {len} bytes is larger than the maximum of {max} bytes"),
+            FrameError::Truncated { available } => write!(f, "stream ended with {available} bytes of an incomplete frame"),
+            FrameError::Malformed(message) => write!(f, "malformed frame header: {message}"),
+            FrameError::Pipe(error) => write!(f, "pipe error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+// anything frames can be taken from the front of
+pub trait FrameSource {
+    fn available(&self) -> usize;
+    
+    // fills the bytes starting at the offset, they must all be available
+    fn copy_to(&self, offset: usize, bytes: &mut [u8]);
+    
+    fn consume(&mut self, len: usize);
+}
+
+impl FrameSource for VecDeque<u8> {
+    fn available(&self) -> usize {
+        self.len()
+    }
+    
+    fn copy_to(&self, offset: usize, bytes: &mut [u8]) {
+        let len = bytes.len();
+        
+        for (byte, &b) in bytes.iter_mut().zip(self.range(offset..offset + len)) {
+            *byte = b;
+        }
+    }
+    
+    fn consume(&mut self, len: usize) {
+        self.drain(..len);
+    }
+}
+
+impl FrameSource for Vec<u8> {
+    fn available(&self) -> usize {
+        self.len()
+    }
+    
+    fn copy_to(&self, offset: usize, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self[offset..offset + bytes.len()]);
+    }
+    
+    fn consume(&mut self, len: usize) {
+        self.drain(..len);
+    }
+}
+
+impl FrameSource for &[u8] {
+    fn available(&self) -> usize {
+        self.len()
+    }
+    
+    fn copy_to(&self, offset: usize, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self[offset..offset + bytes.len()]);
+    }
+    
+    fn consume(&mut self, len: usize) {
+        *self = &self[len..];
+    }
+}
+
+// a way of splitting a byte stream into messages
+pub trait MessageCodec {
+    fn encode(&self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), FrameError>;
+    
+    // takes a message from the front of the source, returns None until a whole message is available
+    fn decode(&self, source: &mut impl FrameSource) -> Result<Option<Vec<u8>>, FrameError>;
+    
+    // for when no more bytes will arrive, fails if part of a message is left
+    fn finish(&self, source: &impl FrameSource) -> Result<(), FrameError> {
+        match source.available() {
+            0 => Ok(()),
+            available => Err(FrameError::Truncated { available }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameCodec {
+    // the maximum is capped to what the header can hold
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size: max_frame_size.min(u32::MAX as usize) }
+    }
+    
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+    
+    pub fn check(&self, len: usize) -> Result<(), FrameError> {
+        if len > self.max_frame_size {
+            Err(FrameError::Oversize { len, max: self.max_frame_size })
+        }
+        else {
+            Ok(())
+        }
+    }
+    
+    // the payload must have passed check
+    pub(crate) fn encode_unchecked(payload: &[u8], out: &mut Vec<u8>) {
+        out.extend((payload.len() as u32).to_le_bytes());
+        out.extend(payload);
+    }
+    
+    pub fn encode(&self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), FrameError> {
+        self.check(payload.len())?;
+        
+        Self::encode_unchecked(payload, out);
+        
+        Ok(())
+    }
+    
+    // takes a frame from the front of the source, returns None until a whole frame is available
+    pub fn decode(&self, source: &mut impl FrameSource) -> Result<Option<Vec<u8>>, FrameError> {
+        if source.available() < HEADER_SIZE {
+            return Ok(None);
+        }
+        
+        let mut header = [0; HEADER_SIZE];
+        
+        source.copy_to(0, &mut header);
+        
+        let len = u32::from_le_bytes(header) as usize;
+        
+        self.check(len)?;
+        
+        if source.available() < HEADER_SIZE + len {
+            return Ok(None);
+        }
+        
+        let mut payload = vec![0; len];
+        
+        source.copy_to(HEADER_SIZE, &mut payload);
+        source.consume(HEADER_SIZE + len);
+        
+        Ok(Some(payload))
+    }
+}
+
+impl MessageCodec for FrameCodec {
+    fn encode(&self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), FrameError> {
+        FrameCodec::encode(self, payload, out)
+    }
+    
+    fn decode(&self, source: &mut impl FrameSource) -> Result<Option<Vec<u8>>, FrameError> {
+        FrameCodec::decode(self, source)
+    }
+}
+
+// the header framing of the language server protocol, a Content-Length header and any others followed by an empty line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLengthCodec {
+    max_content_length: usize,
+}
+
+impl Default for ContentLengthCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl ContentLengthCodec {
+    pub fn new(max_content_length: usize) -> Self {
+        Self { max_content_length }
+    }
+    
+    pub fn max_content_length(&self) -> usize {
+        self.max_content_length
+    }
+    
+    fn content_length(&self, header: &[u8]) -> Result<usize, FrameError> {
+        let header = std::str::from_utf8(header).map_err(|_| FrameError::Malformed("header is not utf-8".into()))?;
+        let mut content_length = None;
+        
+        for line in header.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(FrameError::Malformed(format!("invalid header line {line:?}")));
+            };
+            
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let len = value.trim().parse().map_err(|_| FrameError::Malformed(format!("invalid content length {:?}", value.trim())))?;
+                
+                content_length = Some(len);
+            }
+        }
+        
+        let len = content_length.ok_or_else(|| FrameError::Malformed("missing content length".into()))?;
+        
+        if len > self.max_content_length {
+            Err(FrameError::Oversize { len, max: self.max_content_length })
+        }
+        else {
+            Ok(len)
+        }
+    }
+}
+
+impl MessageCodec for ContentLengthCodec {
+    fn encode(&self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), FrameError> {
+        if payload.len() > self.max_content_length {
+            return Err(FrameError::Oversize { len: payload.len(), max: self.max_content_length });
+        }
+        
+        out.extend(format!("Content-Length: {}\r\n\r\n", payload.len()).bytes());
+        out.extend(payload);
+        
+        Ok(())
+    }
+    
+    fn decode(&self, source: &mut impl FrameSource) -> Result<Option<Vec<u8>>, FrameError> {
+        let mut header = vec![0; source.available().min(MAX_HEADER_SIZE)];
+        
+        source.copy_to(0, &mut header);
+        
+        let Some(end) = header.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return match header.len() {
+                MAX_HEADER_SIZE => Err(FrameError::Malformed(format!("no end of header within {MAX_HEADER_SIZE} bytes"))),
+                _ => Ok(None),
+            };
+        };
+        
+        let len = self.content_length(&header[..end])?;
+        let header_len = end + 4;
+        
+        if source.available() < header_len + len {
+            return Ok(None);
+        }
+        
+        let mut payload = vec![0; len];
+        
+        source.copy_to(header_len, &mut payload);
+        source.consume(header_len + len);
+        
+        Ok(Some(payload))
+    }
+}
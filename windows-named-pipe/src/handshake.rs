@@ -0,0 +1,126 @@
+use std::{fmt, ops::RangeInclusive, time::Duration};
+
+use crate::utils::*;
+
+// both ends send a hello of the magic, their lowest and highest version as little endian u16 and their features as a little endian u64
+pub const HELLO_SIZE: usize = 4 + 2 * size_of::<u16>() + size_of::<u64>();
+
+pub const DEFAULT_MAGIC: [u8; 4] = *b"WNPH";
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Handshake {
+    pub magic: [u8; 4],
+    pub min_version: u16,
+    pub max_version: u16,
+    pub features: u64,
+    pub timeout: Duration,
+}
+
+// what both ends agreed on, both of them come to the same result
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Protocol {
+    pub version: u16,
+    pub features: u64, // the features both ends support
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    BadMagic { expected: [u8; 4], received: [u8; 4] },
+    Incompatible { local: RangeInclusive<u16>, remote: RangeInclusive<u16> },
+    Timeout, // the peer may not do a handshake at all
+    Disconnected,
+    Pipe(Error),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::BadMagic { expected, received } => write!(f, "peer sent magic {received:02x?} instead of {expected:02x?}"),
+            HandshakeError::Incompatible { local, remote } => write!(
+                f,
+                "no common protocol version, supported are {}..={} but the peer supports {}..={}",
+                local.start(), local.end(), remote.start(), remote.end(),
+            ),
+            HandshakeError::Timeout => write!(f, "peer did not complete the handshake in time"),
+            HandshakeError::Disconnected => write!(f, "pipe closed during the handshake"),
+            HandshakeError::Pipe(error) => write!(f, "pipe error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<Error> for HandshakeError {
+    fn from(error: Error) -> Self {
+        HandshakeError::Pipe(error)
+    }
+}
+
+impl Handshake {
+    pub fn new(min_version: u16, max_version: u16) -> Self {
+        assert!(min_version <= max_version, "Empty version range!");
+        
+        Self {
+            magic: DEFAULT_MAGIC,
+            min_version,
+            max_version,
+            features: 0,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+    
+    pub fn with_magic(self, magic: [u8; 4]) -> Self {
+        Self { magic, ..self }
+    }
+    
+    pub fn with_features(self, features: u64) -> Self {
+        Self { features, ..self }
+    }
+    
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+    
+    pub fn versions(&self) -> RangeInclusive<u16> {
+        self.min_version..=self.max_version
+    }
+    
+    pub fn hello(&self) -> [u8; HELLO_SIZE] {
+        let mut hello = [0; HELLO_SIZE];
+        
+        hello[..4].copy_from_slice(&self.magic);
+        hello[4..6].copy_from_slice(&self.min_version.to_le_bytes());
+        hello[6..8].copy_from_slice(&self.max_version.to_le_bytes());
+        hello[8..].copy_from_slice(&self.features.to_le_bytes());
+        
+        hello
+    }
+    
+    // the highest version both ends support
+    pub fn negotiate(&self, hello: &[u8; HELLO_SIZE]) -> Result<Protocol, HandshakeError> {
+        let magic = hello[..4].try_into().unwrap();
+        
+        if magic != self.magic {
+            return Err(HandshakeError::BadMagic { expected: self.magic, received: magic });
+        }
+        
+        let min_version = u16::from_le_bytes(hello[4..6].try_into().unwrap());
+        let max_version = u16::from_le_bytes(hello[6..8].try_into().unwrap());
+        let features = u64::from_le_bytes(hello[8..].try_into().unwrap());
+        let version = self.max_version.min(max_version);
+        
+        if version < self.min_version.max(min_version) {
+            return Err(HandshakeError::Incompatible { local: self.versions(), remote: min_version..=max_version });
+        }
+        
+        Ok(Protocol { version, features: self.features & features })
+    }
+}
+
+impl Protocol {
+    pub fn has(&self, features: u64) -> bool {
+        self.features & features == features
+    }
+}
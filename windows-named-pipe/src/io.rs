@@ -0,0 +1,108 @@
+use std::{borrow::Borrow, io, time::Duration};
+
+use crate::utils::*;
+
+// blocking std::io on top of a pipe, for anything that takes a reader or a writer
+// the pipe can be owned or borrowed, e.g. from ServerNamedPipeStatus::Connected
+pub struct PipeIo<P = NamedPipe> {
+    pipe: P,
+    buffer: Vec<u8>, // read from the pipe but not consumed yet
+    position: usize, // of the first byte not consumed yet
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl<P: Borrow<NamedPipe>> PipeIo<P> {
+    // waits forever by default
+    pub fn new(pipe: P) -> Self {
+        Self {
+            pipe,
+            buffer: Vec::new(),
+            position: 0,
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        self.pipe.borrow()
+    }
+    
+    // whatever was read from the pipe but not consumed is lost
+    pub fn into_inner(self) -> P {
+        self.pipe
+    }
+    
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+    
+    // reads fail with TimedOut if nothing arrives in time
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+    
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+    
+    // writes to a full bounded write queue and flushes fail with TimedOut if the runtime does not catch up in time
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+}
+
+impl<P: Borrow<NamedPipe>> io::Read for PipeIo<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        
+        let available = io::BufRead::fill_buf(self)?;
+        let len = available.len().min(buf.len());
+        
+        buf[..len].copy_from_slice(&available[..len]);
+        io::BufRead::consume(self, len);
+        
+        Ok(len)
+    }
+}
+
+impl<P: Borrow<NamedPipe>> io::BufRead for PipeIo<P> {
+    // empty once the pipe is closed and everything has been read
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.buffer.len() {
+            let result = match self.read_timeout {
+                Some(timeout) => self.pipe().read_timeout(timeout),
+                None => self.pipe().read_blocking(),
+            };
+            
+            match result {
+                ReadResult::Data(data) => self.buffer = data,
+                ReadResult::Eof => self.buffer.clear(),
+                ReadResult::Empty => return Err(io::ErrorKind::TimedOut.into()),
+            }
+            
+            self.position = 0;
+        }
+        
+        Ok(&self.buffer[self.position..])
+    }
+    
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.buffer.len());
+    }
+}
+
+impl<P: Borrow<NamedPipe>> io::Write for PipeIo<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe().write_timeout(buf, self.write_timeout.unwrap_or(Duration::MAX))?;
+        
+        Ok(buf.len())
+    }
+    
+    // waits for the runtime to take everything written
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.pipe().wait_drained(self.write_timeout)?)
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+
+use crate::{dispatch::*, runtime::{DisconnectReason, NamedPipeRuntimeExecutor}, server::Server, utils::*};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl ErrorObject {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+    
+    pub fn with_data(self, data: Value) -> Self {
+        Self { data: Some(data), ..self }
+    }
+    
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+    
+    pub fn internal_error(message: impl Into<String>) -> Self {
+        Self::new(INTERNAL_ERROR, message)
+    }
+    
+    fn invalid_request() -> Self {
+        Self::new(INVALID_REQUEST, "Invalid Request")
+    }
+    
+    fn to_value(&self) -> Value {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        
+        error
+    }
+    
+    fn from_value(error: &Value) -> Option<Self> {
+        Some(Self {
+            code: error.get("code")?.as_i64()?,
+            message: error.get("message")?.as_str()?.to_owned(),
+            data: error.get("data").cloned(),
+        })
+    }
+}
+
+impl fmt::Display for ErrorObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ErrorObject {}
+
+#[derive(Debug)]
+pub enum JsonRpcError {
+    Error(ErrorObject), // the peer answered with an error
+    Timeout,
+    Disconnected, // the pipe finished or the client was dropped before the response arrived
+    Protocol(String), // the stream cannot be read anymore
+    Frame(FrameError),
+}
+
+impl fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonRpcError::Error(error) => write!(f, "{error}"),
+            JsonRpcError::Timeout => write!(f, "request timed out"),
+            JsonRpcError::Disconnected => write!(f, "disconnected"),
+            JsonRpcError::Protocol(message) => write!(f, "protocol error: {message}"),
+            JsonRpcError::Frame(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for JsonRpcError {}
+
+impl From<FrameError> for JsonRpcError {
+    fn from(error: FrameError) -> Self {
+        JsonRpcError::Frame(error)
+    }
+}
+
+pub type JsonRpcResult = Result<Value, JsonRpcError>;
+
+fn response(id: Value, result: Result<Value, ErrorObject>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => json!({ "jsonrpc": "2.0", "error": error.to_value(), "id": id }),
+    }
+}
+
+// a request without an id is a notification
+fn request(method: &str, params: Option<Value>, id: Option<u64>) -> Value {
+    let mut request = json!({ "jsonrpc": "2.0", "method": method });
+    
+    if let Some(params) = params {
+        request["params"] = params;
+    }
+    
+    if let Some(id) = id {
+        request["id"] = id.into();
+    }
+    
+    request
+}
+
+pub type JsonRpcHandler<S> = Box<dyn Fn(&mut S, Option<Value>) -> Result<Value, ErrorObject> + Send + Sync>;
+
+// answers requests and notifications with the handler registered for their method
+// every client has a session of type S that its handlers get to change
+pub struct JsonRpcServer<S = ()> {
+    handlers: HashMap<String, JsonRpcHandler<S>>,
+    codec: ContentLengthCodec,
+    sessions: HashMap<usize, (Arc<OnceLock<DisconnectReason>>, S)>, // by the index of the pipe in the server, with the connection they belong to
+}
+
+impl<S> Default for JsonRpcServer<S> {
+    fn default() -> Self {
+        Self::new(ContentLengthCodec::default())
+    }
+}
+
+impl<S> JsonRpcServer<S> {
+    pub fn new(codec: ContentLengthCodec) -> Self {
+        Self { handlers: HashMap::new(), codec, sessions: HashMap::new() }
+    }
+    
+    // replaces the handler registered before for the method
+    pub fn register(&mut self, method: &str, handler: impl Fn(&mut S, Option<Value>) -> Result<Value, ErrorObject> + Send + Sync + 'static) -> &mut Self {
+        self.handlers.insert(method.to_owned(), Box::new(handler));
+        self
+    }
+    
+    // the response to a single request, None for notifications
+    fn call(&self, session: &mut S, request: Value) -> Option<Value> {
+        let invalid = |id: Option<Value>| Some(response(id.unwrap_or(Value::Null), Err(ErrorObject::invalid_request())));
+        
+        let Value::Object(mut request) = request else { return invalid(None) };
+        let id = request.remove("id");
+        
+        if id.as_ref().is_some_and(|id| !matches!(id, Value::Null | Value::Number(_) | Value::String(_))) {
+            return invalid(None);
+        }
+        
+        let Some(Value::String(method)) = request.remove("method") else { return invalid(id) };
+        let params = request.remove("params");
+        
+        if request.get("jsonrpc") != Some(&json!("2.0")) || params.as_ref().is_some_and(|params| !params.is_array() && !params.is_object()) {
+            return invalid(id);
+        }
+        
+        let result = match self.handlers.get(&method) {
+            Some(handler) => handler(session, params),
+            None => Err(ErrorObject::new(METHOD_NOT_FOUND, "Method not found").with_data(method.into())),
+        };
+        
+        // notifications are never answered, not even with an error
+        id.map(|id| response(id, result))
+    }
+    
+    // answers a single request or a batch, returns the response to send back if there is one
+    pub fn handle(&self, session: &mut S, message: &[u8]) -> Option<Vec<u8>> {
+        let response = match serde_json::from_slice(message) {
+            Err(error) => Some(response(Value::Null, Err(ErrorObject::new(PARSE_ERROR, "Parse error").with_data(error.to_string().into())))),
+            Ok(Value::Array(batch)) if batch.is_empty() => Some(response(Value::Null, Err(ErrorObject::invalid_request()))),
+            Ok(Value::Array(batch)) => {
+                let responses: Vec<_> = batch.into_iter().filter_map(|request| self.call(session, request)).collect();
+                
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            Ok(request) => self.call(session, request),
+        };
+        
+        response.map(|response| serde_json::to_vec(&response).unwrap())
+    }
+    
+    // answers every message that has arrived so far without blocking, returns how many there were
+    pub fn serve_pending(&self, session: &mut S, pipe: &NamedPipe) -> Result<usize, FrameError> {
+        let mut count = 0;
+        
+        while let Some(message) = pipe.recv_message_with(&self.codec)? {
+            if let Some(response) = self.handle(session, &message) {
+                pipe.send_message_with(&self.codec, &response)?;
+            }
+            
+            count += 1;
+        }
+        
+        Ok(count)
+    }
+    
+    // serves every connected pipe of the server, a session is created when a pipe connects and dropped once it is not connected
+    // a client whose stream cannot be read anymore is disconnected, the errors are returned with the index of its pipe
+    pub fn poll<F: 'static>(&mut self, server: &mut Server<F>) -> Vec<(usize, FrameError)> where S: Default {
+        let mut errors = Vec::new();
+        
+        for (index, pipe) in server.pipes().iter_mut().enumerate() {
+            let pipe = pipe.pipe_mut();
+            
+            let result = match pipe.update_status() {
+                ServerNamedPipeStatus::Connected(connected) => {
+                    let connection = connected.shared_disconnect_reason();
+                    
+                    // the next client of the instance starts over, even if the status never left Connected in between
+                    let mut session = match self.sessions.remove(&index) {
+                        Some((previous, session)) if Arc::ptr_eq(&previous, &connection) => session,
+                        _ => S::default(),
+                    };
+                    
+                    let result = self.serve_pending(&mut session, connected);
+                    
+                    self.sessions.insert(index, (connection, session));
+                    result
+                }
+                _ => {
+                    self.sessions.remove(&index);
+                    continue;
+                }
+            };
+            
+            if let Err(error) = result {
+                self.sessions.remove(&index);
+                errors.push((index, error));
+                let _ = pipe.disconnect(); // the status catches up once the runtime finishes
+            }
+        }
+        
+        errors
+    }
+    
+    pub fn session(&self, index: usize) -> Option<&S> {
+        self.sessions.get(&index).map(|(_, session)| session)
+    }
+    
+    // answers every message of the pipe as it arrives with a session of its own, blocks until the pipe closes
+    pub fn serve_connection(&self, pipe: &NamedPipe) -> Result<(), FrameError> where S: Default {
+        let mut session = S::default();
+        
+        loop {
+            let closed = pipe.state() != NamedPipeState::Open; // checked first, nothing arrives after the disconnect
+            
+            match pipe.recv_message_until(&self.codec, None, || false)? {
+                Some(message) => {
+                    if let Some(response) = self.handle(&mut session, &message) {
+                        pipe.send_message_with(&self.codec, &response)?;
+                    }
+                }
+                None if closed => return Ok(()),
+                None => {}
+            }
+        }
+    }
+    
+    // serves every client of the server on a thread of its own until the server is closed, see Server::serve
+    // a client whose stream cannot be read anymore is disconnected
+    pub fn serve<F, E, R>(&self, server: &mut Server<F>, runtime: R) -> Result<(), Error>
+    where
+        Self: Sync,
+        S: Default,
+        F: Fn() -> NamedPipeBuffer,
+        E: NamedPipeRuntimeExecutor,
+        R: FnMut() -> E,
+    {
+        server.serve(runtime, |pipe| {
+            if self.serve_connection(&pipe).is_err() {
+                let _ = pipe.interrupt();
+            }
+        })
+    }
+}
+
+// one entry of a batch
+#[derive(Clone, Debug)]
+pub struct BatchCall {
+    method: String,
+    params: Option<Value>,
+    notification: bool,
+}
+
+impl BatchCall {
+    pub fn request(method: &str, params: Option<Value>) -> Self {
+        Self { method: method.to_owned(), params, notification: false }
+    }
+    
+    pub fn notification(method: &str, params: Option<Value>) -> Self {
+        Self { method: method.to_owned(), params, notification: true }
+    }
+}
+
+impl CallError for JsonRpcError {
+    fn timeout() -> Self {
+        JsonRpcError::Timeout
+    }
+    
+    fn disconnected() -> Self {
+        JsonRpcError::Disconnected
+    }
+    
+    fn protocol(message: String) -> Self {
+        JsonRpcError::Protocol(message)
+    }
+}
+
+// requests and notifications from the server are ignored, only responses are taken
+fn parse_response(response: &Value) -> Option<(u64, JsonRpcResult)> {
+    let id = response.get("id").and_then(Value::as_u64)?;
+    
+    let result = match (response.get("result"), response.get("error")) {
+        (_, Some(error)) => Err(ErrorObject::from_value(error).map_or_else(|| JsonRpcError::Protocol(format!("invalid error object {error}")), JsonRpcError::Error)),
+        (Some(result), None) => Ok(result.clone()),
+        (None, None) => return None,
+    };
+    
+    Some((id, result))
+}
+
+fn responses(message: &[u8]) -> Vec<(u64, JsonRpcResult)> {
+    match serde_json::from_slice(message) {
+        Ok(Value::Array(responses)) => responses.iter().filter_map(parse_response).collect(),
+        Ok(response) => parse_response(&response).into_iter().collect(),
+        Err(_) => Vec::new(), // there is no telling which request it was meant for
+    }
+}
+
+// makes requests over a pipe, any number of them can be in flight at once
+// a background thread takes every message arriving on the pipe and resolves the request it answers
+pub struct JsonRpcClient {
+    dispatcher: Dispatcher<Calls<Value, JsonRpcError>>,
+    codec: ContentLengthCodec,
+}
+
+impl JsonRpcClient {
+    pub fn new(pipe: NamedPipe, codec: ContentLengthCodec) -> Self {
+        Self { dispatcher: Dispatcher::new(pipe, codec, Calls::new(responses)), codec }
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        self.dispatcher.pipe()
+    }
+    
+    // true once every request fails with Disconnected or Protocol
+    pub fn is_closed(&self) -> bool {
+        self.dispatcher.handler().is_closed()
+    }
+    
+    // the dispatch thread fails the request with Timeout once the deadline passes, None waits for as long as it takes
+    fn register(&self, deadline: Option<Instant>) -> Result<(u64, channel::Receiver<JsonRpcResult>), JsonRpcError> {
+        let (sender, receiver) = channel::Channel::bounded(1).unwrap();
+        
+        let id = self.dispatcher.call(deadline, move |result| {
+            let _ = sender.send(result);
+        });
+        
+        id.map(|id| (id, receiver)).ok_or(JsonRpcError::Disconnected)
+    }
+    
+    fn unregister(&self, ids: impl IntoIterator<Item = u64>) {
+        self.dispatcher.handler().remove(ids);
+    }
+    
+    fn wait(receiver: channel::Receiver<JsonRpcResult>) -> JsonRpcResult {
+        receiver.recv_blocking().unwrap_or(Err(JsonRpcError::Disconnected))
+    }
+    
+    fn send(&self, message: &Value) -> Result<(), JsonRpcError> {
+        Ok(self.pipe().send_message_with(&self.codec, &serde_json::to_vec(message).unwrap())?)
+    }
+    
+    pub fn notify(&self, method: &str, params: Option<Value>) -> Result<(), JsonRpcError> {
+        self.send(&request(method, params, None))
+    }
+    
+    // blocks until the response arrives, the request times out or the pipe disconnects
+    pub fn request(&self, method: &str, params: Option<Value>, timeout: Duration) -> JsonRpcResult {
+        let deadline = Instant::now().checked_add(timeout);
+        let (id, receiver) = self.register(deadline)?;
+        
+        if let Err(error) = self.send(&request(method, params, Some(id))) {
+            self.unregister([id]);
+            return Err(error);
+        }
+        
+        Self::wait(receiver)
+    }
+    
+    // sends every call as one batch, returns the results of the requests in order, notifications have none
+    pub fn batch(&self, calls: Vec<BatchCall>, timeout: Duration) -> Result<Vec<JsonRpcResult>, JsonRpcError> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut waiters = Vec::new();
+        let mut batch = Vec::new();
+        
+        for BatchCall { method, params, notification } in calls {
+            let id = match notification {
+                true => None,
+                false => match self.register(deadline) {
+                    Ok((id, receiver)) => {
+                        waiters.push((id, receiver));
+                        Some(id)
+                    }
+                    Err(error) => {
+                        self.unregister(waiters.iter().map(|&(id, _)| id));
+                        return Err(error);
+                    }
+                }
+            };
+            
+            batch.push(request(&method, params, id));
+        }
+        
+        if let Err(error) = self.send(&Value::Array(batch)) {
+            self.unregister(waiters.iter().map(|&(id, _)| id));
+            return Err(error);
+        }
+        
+        Ok(waiters.into_iter().map(|(_, receiver)| Self::wait(receiver)).collect())
+    }
+}
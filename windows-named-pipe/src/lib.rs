@@ -7,23 +7,50 @@ pub mod server_pipe;
 pub mod server;
 pub mod client;
 pub mod event;
+pub mod transport;
+pub mod framing;
+pub mod rpc;
+pub mod mux;
+pub mod handshake;
+pub mod error;
+pub mod io;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub mod async_pipe;
+#[cfg(feature = "typed")]
+pub mod typed;
+#[cfg(feature = "json")]
+pub mod jsonrpc;
 
 pub(crate) mod utils;
+pub(crate) mod sys;
+pub(crate) mod dispatch;
 
 pub mod prelude {
     pub use crate::{
         path::*,
         channel,
         buffer::{IoBuffer, NamedPipeBuffer},
-        pipe::{NamedPipe, NamedPipeEvents, ReadLineResult},
+        pipe::{NamedPipe, NamedPipeEvents, NamedPipeState, NamedPipeWriter, ReadLineResult, ReadResult},
         runtime::*,
-        utils::WindowsResult,
+        transport::*,
+        error::{Error, ErrorKind},
+        io::PipeIo,
         event::Event,
+        framing::{ContentLengthCodec, FrameCodec, FrameError, MessageCodec},
+        rpc::{RpcClient, RpcError, RpcHandlers},
+        mux::{Multiplexer, Stream},
+        handshake::{Handshake, HandshakeError, Protocol},
     };
     
+    #[cfg(feature = "typed")]
+    pub use crate::typed::{DecodeError, EncodeError, Format, TypedPipe};
+    
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub use crate::async_pipe::AsyncNamedPipe;
+    
     pub mod server {
         pub use super::*;
-        pub use crate::{server::*, server_pipe::*};
+        pub use crate::{buffer::LazyBuffer, server::*, server_pipe::*};
     }
     
     pub mod client {
@@ -35,6 +62,7 @@ pub mod prelude {
 pub mod path {
     use std::ffi::CString;
 
+    #[cfg(windows)]
     use windows::core::PCSTR;
 
     #[repr(transparent)]
@@ -42,14 +70,45 @@ pub mod path {
     pub struct NamedPipePath(CString);
 
     impl NamedPipePath {
+        #[cfg(windows)]
         pub fn new(pipe_name: &str) -> Self {
             Self(CString::new(String::from("\\\\.\\pipe\\") + pipe_name).expect("Pipe name should not contain NUL!"))
         }
         
+        // unix domain sockets live in the file system, so pipes are mapped into the temp directory
+        #[cfg(unix)]
+        pub fn new(pipe_name: &str) -> Self {
+            use std::os::unix::ffi::OsStringExt;
+            
+            let path = std::env::temp_dir().join(String::from("pipe-") + pipe_name);
+            
+            Self(CString::new(path.into_os_string().into_vec()).expect("Pipe name should not contain NUL!"))
+        }
+        
+        #[cfg(windows)]
+        /// # Safety
+        /// the pointer is only valid as long as the path is alive
         pub unsafe fn as_pcstr(&self) -> PCSTR {
             let Self(s) = self;
             
             PCSTR(s.to_bytes_with_nul().as_ptr())
         }
+        
+        #[cfg(unix)]
+        pub fn as_path(&self) -> &std::path::Path {
+            use std::os::unix::ffi::OsStrExt;
+            
+            let Self(s) = self;
+            
+            std::path::Path::new(std::ffi::OsStr::from_bytes(s.to_bytes()))
+        }
+    }
+    
+    impl std::fmt::Display for NamedPipePath {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let Self(s) = self;
+            
+            write!(f, "{}", s.to_string_lossy())
+        }
     }
 }
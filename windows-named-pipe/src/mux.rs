@@ -0,0 +1,563 @@
+use std::{
+    collections::{HashMap, VecDeque, hash_map::Entry},
+    sync::{Arc, Condvar, Mutex, MutexGuard, atomic::{AtomicBool, AtomicU32, Ordering}},
+    time::{Duration, Instant},
+};
+
+use crate::{dispatch::*, pipe::{take_last_line, take_line}, utils::*};
+
+// every mux message is one frame starting with its kind and the id of the stream
+// the top bit of the id is set if the sender of the message opened the stream, so both ends can open streams at once
+const OPEN: u8 = 0; // followed by the window of the opener
+const ACCEPT: u8 = 1; // followed by the window of the acceptor
+const DATA: u8 = 2;
+const WINDOW: u8 = 3; // followed by how many more bytes the peer can send
+const CLOSE: u8 = 4; // nothing more will be sent
+
+const OPENED_BY_SENDER: u32 = 1 << 31;
+const ID_SIZE: usize = size_of::<u32>();
+
+// how many bytes a stream can have in flight before the reader catches up
+pub const DEFAULT_WINDOW: usize = 256 << 10;
+
+// data is sent in chunks no larger than this, so streams take turns on the pipe
+const MAX_CHUNK: usize = 16 << 10;
+
+// how often the dispatch thread retries the streams it could not send for while the write queue of the pipe is full
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Default)]
+struct StreamState {
+    read: VecDeque<u8>,
+    write: VecDeque<u8>, // waiting for the peer to grant more window
+    credit: usize, // how many more bytes the peer can take
+    receivable: usize, // how many more bytes the peer is allowed to send
+    unacknowledged: usize, // read but not granted back to the peer yet
+    accepted: bool,
+    sending: bool, // a thread is sending for the stream, whatever is added is sent by it
+    dropped: bool, // nothing reads anymore, data is dropped as it arrives
+    closing: bool, // closed here, the close goes out once everything written is sent
+    close_sent: bool,
+    remote_closed: bool,
+    broken: bool, // the pipe is gone or the peer broke the protocol
+}
+
+impl StreamState {
+    fn is_done(&self) -> bool {
+        self.broken || (self.close_sent && self.remote_closed)
+    }
+    
+    // nothing more will arrive
+    fn is_finished(&self) -> bool {
+        self.broken || self.remote_closed
+    }
+}
+
+struct StreamInner {
+    key: u32, // the id as sent by this end
+    state: Mutex<StreamState>,
+    changed: Condvar,
+}
+
+struct Shared {
+    writer: NamedPipeWriter,
+    window: usize,
+    streams: Mutex<HashMap<u32, Arc<StreamInner>>>,
+    incoming: channel::Channel<Arc<StreamInner>>,
+    next_id: AtomicU32,
+    broken: AtomicBool,
+    stalled: Mutex<Vec<Arc<StreamInner>>>, // found no room to send for without waiting, retried on the next wakeup of the dispatch thread
+    waker: DispatchWaker,
+}
+
+impl Shared {
+    fn send(&self, kind: u8, key: u32, body: &[u8]) -> Result<(), Error> {
+        self.send_timeout(kind, key, body, Duration::ZERO)
+    }
+    
+    fn send_timeout(&self, kind: u8, key: u32, body: &[u8], timeout: Duration) -> Result<(), Error> {
+        let mut message = Vec::with_capacity(1 + ID_SIZE + body.len());
+        
+        message.push(kind);
+        message.extend(key.to_le_bytes());
+        message.extend(body);
+        
+        self.writer.send_message_timeout(&message, timeout).map_err(|error| match error {
+            FrameError::Pipe(error) => error,
+            error => ErrorKind::Protocol(error.to_string()).into(),
+        })
+    }
+    
+    fn stream(&self, key: u32) -> Option<Arc<StreamInner>> {
+        self.streams.lock().unwrap().get(&key).cloned()
+    }
+    
+    // sends whatever the window allows, then the close once everything is out
+    // the state is unlocked while a message waits for room in the write queue of the pipe, one thread sends at a time so the order holds
+    // with a zero timeout nothing waits, a stream left without room is retried by the dispatch thread
+    fn pump<'a>(&self, stream: &'a Arc<StreamInner>, mut state: MutexGuard<'a, StreamState>, timeout: Duration) -> Result<(), Error> {
+        if state.sending {
+            return Ok(());
+        }
+        
+        state.sending = true;
+        
+        let result = loop {
+            let (kind, chunk) = if state.accepted && !state.broken && state.credit > 0 && !state.write.is_empty() {
+                let len = state.credit.min(MAX_CHUNK).min(state.write.len());
+                
+                state.credit -= len;
+                (DATA, state.write.drain(..len).collect())
+            }
+            else if state.closing && !state.close_sent && !state.broken && state.write.is_empty() {
+                state.close_sent = true;
+                (CLOSE, Vec::new())
+            }
+            else {
+                break Ok(());
+            };
+            
+            drop(state);
+            
+            let result = self.send_timeout(kind, stream.key, &chunk, timeout);
+            
+            state = stream.state.lock().unwrap();
+            
+            if let Err(error) = result {
+                // nothing went out, it is still there to send
+                match kind {
+                    DATA => {
+                        state.credit += chunk.len();
+                        chunk.into_iter().rev().for_each(|byte| state.write.push_front(byte));
+                    }
+                    _ => state.close_sent = false,
+                }
+                
+                if timeout.is_zero() && matches!(error.kind(), ErrorKind::WouldBlock) {
+                    self.stall(stream);
+                    break Ok(());
+                }
+                
+                break Err(error);
+            }
+        };
+        
+        state.sending = false;
+        self.forget(stream, &state);
+        
+        result
+    }
+    
+    fn stall(&self, stream: &Arc<StreamInner>) {
+        let mut stalled = self.stalled.lock().unwrap();
+        
+        if !stalled.iter().any(|stalled| Arc::ptr_eq(stalled, stream)) {
+            stalled.push(stream.clone());
+        }
+        
+        // a reader stalls outside of the dispatch thread, which may be waiting without a deadline
+        self.waker.wake();
+    }
+    
+    fn forget(&self, stream: &StreamInner, state: &StreamState) {
+        if state.is_done() {
+            self.streams.lock().unwrap().remove(&stream.key);
+        }
+    }
+    
+    // a grant that finds no room in the write queue is retried by the dispatch thread, the peer may wait for nothing else
+    fn grant(&self, stream: &Arc<StreamInner>, state: &mut StreamState, read: usize) {
+        state.unacknowledged += read;
+        
+        // in bulk, so the peer is not flooded with tiny updates
+        if state.unacknowledged >= self.window / 2 && !state.is_finished() {
+            let credit = state.unacknowledged;
+            
+            match self.send(WINDOW, stream.key, &(credit as u32).to_le_bytes()) {
+                Ok(()) => {
+                    state.unacknowledged = 0;
+                    state.receivable += credit;
+                }
+                Err(error) if matches!(error.kind(), ErrorKind::WouldBlock) => self.stall(stream),
+                Err(_) => {}
+            }
+        }
+    }
+    
+    fn break_all(&self) {
+        self.broken.store(true, Ordering::Relaxed);
+        
+        let streams: Vec<_> = self.streams.lock().unwrap().drain().map(|(_, stream)| stream).collect();
+        
+        for stream in streams {
+            stream.state.lock().unwrap().broken = true;
+            stream.changed.notify_all();
+        }
+    }
+}
+
+impl Dispatch for Shared {
+    fn receive(&self, message: &[u8]) {
+        let Some((&kind, rest)) = message.split_first() else { return };
+        let Some((id, body)) = rest.split_first_chunk::<ID_SIZE>() else { return };
+        let key = u32::from_le_bytes(*id) ^ OPENED_BY_SENDER;
+        let number = |body: &[u8]| body.first_chunk::<4>().map(|&bytes| u32::from_le_bytes(bytes) as usize);
+        
+        if kind == OPEN {
+            let Some(window) = number(body) else { return };
+            
+            let stream = Arc::new(StreamInner {
+                key,
+                state: Mutex::new(StreamState { credit: window, receivable: self.window, accepted: true, ..Default::default() }),
+                changed: Condvar::new(),
+            });
+            
+            // a peer reusing an id that is still open is ignored
+            let inserted = match self.streams.lock().unwrap().entry(key) {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(entry) => {
+                    entry.insert(stream.clone());
+                    true
+                }
+            };
+            
+            if inserted && self.send(ACCEPT, key, &(self.window as u32).to_le_bytes()).is_ok() {
+                let _ = self.incoming.sender().send(stream);
+            }
+            
+            return;
+        }
+        
+        // anything for a stream that is gone already is dropped
+        let Some(stream) = self.stream(key) else { return };
+        let mut state = stream.state.lock().unwrap();
+        
+        match kind {
+            ACCEPT => if let Some(window) = number(body) {
+                state.accepted = true;
+                state.credit = window;
+                let _ = self.pump(&stream, state, Duration::ZERO);
+            }
+            DATA if body.len() > state.receivable || state.remote_closed => {
+                // the peer ignored the window or the close, the stream cannot be trusted anymore
+                state.broken = true;
+                self.forget(&stream, &state);
+            }
+            DATA => {
+                state.receivable -= body.len();
+                
+                match state.dropped {
+                    true => self.grant(&stream, &mut state, body.len()),
+                    false => state.read.extend(body),
+                }
+            }
+            WINDOW => if let Some(credit) = number(body) {
+                state.credit += credit;
+                let _ = self.pump(&stream, state, Duration::ZERO);
+            }
+            CLOSE => {
+                state.remote_closed = true;
+                self.forget(&stream, &state);
+            }
+            _ => {}
+        }
+        
+        stream.changed.notify_all();
+    }
+    
+    fn next_deadline(&self) -> Option<Instant> {
+        (!self.stalled.lock().unwrap().is_empty()).then(|| Instant::now() + RETRY_INTERVAL)
+    }
+    
+    fn expire(&self, _now: Instant) {
+        let stalled = std::mem::take(&mut *self.stalled.lock().unwrap());
+        
+        for stream in stalled {
+            let mut state = stream.state.lock().unwrap();
+            
+            self.grant(&stream, &mut state, 0);
+            let _ = self.pump(&stream, state, Duration::ZERO);
+        }
+    }
+    
+    fn close(&self, _error: Option<FrameError>) {
+        self.break_all();
+    }
+}
+
+// carries any number of streams over one pipe, both ends can open streams
+// every stream has its own window, so a stream whose reader falls behind stops sending instead of holding up the others
+// with a bounded write queue on the pipe, writes wait for room before they return
+pub struct Multiplexer {
+    dispatcher: Dispatcher<Shared>,
+}
+
+impl Multiplexer {
+    pub fn new(pipe: NamedPipe) -> Self {
+        Self::with_window(pipe, DEFAULT_WINDOW)
+    }
+    
+    // the window is capped to what fits into a message
+    pub fn with_window(pipe: NamedPipe, window: usize) -> Self {
+        let codec = *pipe.codec();
+        let writer = pipe.writer();
+        
+        let dispatcher = Dispatcher::with_waker(pipe, codec, |waker| Shared {
+            writer,
+            window: window.clamp(1, u32::MAX as usize),
+            streams: Mutex::new(HashMap::new()),
+            incoming: channel::Channel::new(),
+            next_id: AtomicU32::new(0),
+            broken: AtomicBool::new(false),
+            stalled: Mutex::new(Vec::new()),
+            waker,
+        });
+        
+        Self { dispatcher }
+    }
+    
+    fn shared(&self) -> &Arc<Shared> {
+        self.dispatcher.handler()
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        self.dispatcher.pipe()
+    }
+    
+    // true once the pipe is gone, every stream is broken
+    pub fn is_broken(&self) -> bool {
+        self.shared().broken.load(Ordering::Relaxed)
+    }
+    
+    // streams that are not completely closed yet
+    pub fn stream_count(&self) -> usize {
+        self.shared().streams.lock().unwrap().len()
+    }
+    
+    fn stream(&self, inner: Arc<StreamInner>) -> Stream {
+        Stream { inner, shared: self.shared().clone() }
+    }
+    
+    // the stream can be written to right away, the data goes out once the peer accepts
+    pub fn open(&self) -> Result<Stream, Error> {
+        if self.is_broken() {
+            return Err(ErrorKind::Disconnected.into());
+        }
+        
+        let inner = {
+            let mut streams = self.shared().streams.lock().unwrap();
+            
+            // ids wrap around before they reach the flag, those of streams that are still open are skipped
+            let key = (0..=!OPENED_BY_SENDER)
+                .map(|_| (self.shared().next_id.fetch_add(1, Ordering::Relaxed) & !OPENED_BY_SENDER) | OPENED_BY_SENDER)
+                .find(|key| !streams.contains_key(key));
+            
+            let Some(key) = key else {
+                return Err(ErrorKind::Busy.into()); // every id is taken
+            };
+            
+            let inner = Arc::new(StreamInner {
+                key,
+                state: Mutex::new(StreamState { receivable: self.shared().window, ..Default::default() }),
+                changed: Condvar::new(),
+            });
+            
+            streams.insert(key, inner.clone());
+            inner
+        };
+        
+        if let Err(error) = self.shared().send(OPEN, inner.key, &(self.shared().window as u32).to_le_bytes()) {
+            self.shared().streams.lock().unwrap().remove(&inner.key);
+            return Err(error);
+        }
+        
+        Ok(self.stream(inner))
+    }
+    
+    // a stream the peer opened, if there is one
+    pub fn accept(&self) -> Option<Stream> {
+        self.shared().incoming.receiver().receive_next().map(|inner| self.stream(inner))
+    }
+    
+    pub fn accept_timeout(&self, timeout: Duration) -> Option<Stream> {
+        self.shared().incoming.receiver().recv_timeout(timeout).ok().map(|inner| self.stream(inner))
+    }
+}
+
+// one end of a stream, dropping it closes the stream
+pub struct Stream {
+    inner: Arc<StreamInner>,
+    shared: Arc<Shared>,
+}
+
+impl Stream {
+    fn state(&self) -> MutexGuard<'_, StreamState> {
+        self.inner.state.lock().unwrap()
+    }
+    
+    // waits until the condition holds or the deadline passes, returns the state either way
+    fn wait_until(&self, deadline: Option<Instant>, mut f: impl FnMut(&mut StreamState) -> bool) -> MutexGuard<'_, StreamState> {
+        let mut state = self.state();
+        
+        while !f(&mut state) {
+            state = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    
+                    if timeout.is_zero() {
+                        break;
+                    }
+                    
+                    self.inner.changed.wait_timeout(state, timeout).unwrap().0
+                }
+                None => self.inner.changed.wait(state).unwrap(),
+            };
+        }
+        
+        state
+    }
+    
+    // takes what the reader consumed out of the read side and grants it back to the peer
+    fn consume<R>(&self, state: &mut StreamState, f: impl FnOnce(&mut VecDeque<u8>) -> R) -> R {
+        let len = state.read.len();
+        let result = f(&mut state.read);
+        let read = len - state.read.len();
+        
+        self.shared.grant(&self.inner, state, read);
+        
+        result
+    }
+    
+    // the same on both ends of the stream
+    pub fn id(&self) -> u32 {
+        self.inner.key & !OPENED_BY_SENDER
+    }
+    
+    // true once the peer accepted the stream
+    pub fn is_open(&self) -> bool {
+        let state = self.state();
+        
+        state.accepted && !state.broken
+    }
+    
+    // waits for the peer to accept the stream, returns false if it did not in time or the pipe is gone
+    pub fn wait_open(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let state = self.wait_until(deadline, |state| state.accepted || state.broken);
+        
+        state.accepted && !state.broken
+    }
+    
+    // true once the peer closed the stream, whatever it sent before can still be read
+    pub fn is_remote_closed(&self) -> bool {
+        self.state().remote_closed
+    }
+    
+    pub fn is_broken(&self) -> bool {
+        self.state().broken
+    }
+    
+    // bytes written but waiting for the peer to grant more window
+    pub fn unsent(&self) -> usize {
+        self.state().write.len()
+    }
+    
+    fn take_read(&self, state: &mut StreamState) -> ReadResult {
+        let finished = state.is_finished();
+        let bytes: Vec<u8> = self.consume(state, |buffer| buffer.drain(..).collect());
+        
+        match bytes.is_empty() {
+            false => ReadResult::Data(bytes),
+            true if finished => ReadResult::Eof,
+            true => ReadResult::Empty,
+        }
+    }
+    
+    pub fn read(&self) -> ReadResult {
+        let mut state = self.state();
+        
+        self.take_read(&mut state)
+    }
+    
+    // blocks until there is something to read, returns Eof once nothing ever will be
+    pub fn read_blocking(&self) -> ReadResult {
+        let mut state = self.wait_until(None, |state| !state.read.is_empty() || state.is_finished());
+        
+        self.take_read(&mut state)
+    }
+    
+    pub fn read_line(&self) -> ReadLineResult {
+        let mut state = self.state();
+        let finished = state.is_finished();
+        
+        self.consume(&mut state, |buffer| take_last_line(buffer, finished))
+    }
+    
+    // waits for a whole line (or invalid utf8), returns Empty or NotALine if it timed out first
+    pub fn read_line_timeout(&self, timeout: Duration) -> ReadLineResult {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut result = ReadLineResult::Empty;
+        
+        let mut state = self.wait_until(deadline, |state| {
+            result = self.consume(state, take_line);
+            state.is_finished() || !matches!(result, ReadLineResult::Empty | ReadLineResult::NotALine)
+        });
+        
+        if let ReadLineResult::Empty | ReadLineResult::NotALine = result {
+            let finished = state.is_finished();
+            
+            result = self.consume(&mut state, |buffer| take_last_line(buffer, finished));
+        }
+        
+        result
+    }
+    
+    // never blocks, whatever does not fit into the window is sent as the peer catches up
+    // fails with Disconnected once the stream is closed here or the pipe is gone
+    pub fn write(&self, bytes: &[u8]) -> Result<(), Error> {
+        let mut state = self.state();
+        
+        if state.closing || state.broken {
+            return Err(ErrorKind::Disconnected.into());
+        }
+        
+        state.write.extend(bytes);
+        self.shared.pump(&self.inner, state, Duration::MAX)
+    }
+    
+    pub fn write_line(&self, s: &str) -> Result<(), Error> {
+        let mut state = self.state();
+        
+        if state.closing || state.broken {
+            return Err(ErrorKind::Disconnected.into());
+        }
+        
+        state.write.extend(s.bytes());
+        state.write.push_back(b'\n');
+        self.shared.pump(&self.inner, state, Duration::MAX)
+    }
+    
+    // the peer can still send until it closes its end, whatever was written here is still delivered
+    pub fn close(&self) -> Result<(), Error> {
+        let mut state = self.state();
+        
+        state.closing = true;
+        self.shared.pump(&self.inner, state, Duration::MAX)
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        {
+            let mut state = self.state();
+            let len = state.read.len();
+            
+            // the peer is not held up waiting for window that a reader would have granted
+            state.dropped = true;
+            state.read.clear();
+            self.shared.grant(&self.inner, &mut state, len);
+        }
+        
+        let _ = self.close();
+    }
+}
@@ -1,7 +1,14 @@
 
-use std::thread::JoinHandle;
+use std::{collections::VecDeque, panic::{self, AssertUnwindSafe}, sync::{Arc, Mutex, OnceLock}, task::{Context, Poll}, thread::JoinHandle, time::Duration};
 
-use crate::utils::*;
+use crate::{channel::RecvError, handshake::*, utils::*};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadResult {
+    Empty,
+    Data(Vec<u8>),
+    Eof, // the pipe is closed and everything it received has been read
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ReadLineResult {
@@ -9,13 +16,57 @@ pub enum ReadLineResult {
     Empty,
     NotALine,
     Line(String),
+    Eof, // the pipe is closed and everything it received has been read
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NamedPipeState {
+    Open,
+    HalfClosed, // nothing more arrives and nothing can be written, but there is still something to read
+    Closed { reason: DisconnectReason },
+}
+
+// takes the first line out of the buffer if there is one
+pub(crate) fn take_line(buffer: &mut VecDeque<u8>) -> ReadLineResult {
+    if let Some(s) = buffer.make_contiguous().utf8_chunks().next() {
+        if s.invalid().is_empty() {
+            let s = s.valid();
+            
+            if s.contains('\n') {
+                let s = s.lines().next().unwrap().to_owned();
+                
+                buffer.drain(..=s.len());
+                
+                ReadLineResult::Line(s)
+            }
+            else {
+                ReadLineResult::NotALine
+            }
+        }
+        else {
+            ReadLineResult::InvalidUtf8
+        }
+    }
+    else {
+        ReadLineResult::Empty
+    }
+}
+
+// once nothing more arrives a last line without a newline is still a line
+pub(crate) fn take_last_line(buffer: &mut VecDeque<u8>, closed: bool) -> ReadLineResult {
+    match take_line(buffer) {
+        ReadLineResult::Empty if closed => ReadLineResult::Eof,
+        ReadLineResult::NotALine if closed => ReadLineResult::Line(String::from_utf8(buffer.drain(..).collect()).unwrap()),
+        result => result,
+    }
 }
 
+// the read and write events are left over from before transports, which signal their own io now
 #[derive(Clone, Copy, Debug)]
 pub struct NamedPipeEvents([Event; 4]);
 
 impl NamedPipeEvents {
-    pub fn register() -> WindowsResult<Self> {
+    pub fn register() -> Result<Self, Error> {
         Ok(Self(EventManager::register_n()?))
     }
     
@@ -25,11 +76,13 @@ impl NamedPipeEvents {
         events.into_iter().for_each(EventManager::unregister);
     }
     
+    #[deprecated(note = "the transport signals the completion of a read, this event is never signalled")]
     pub fn read(self) -> Event {
         let Self(events) = self;
         events[0]
     }
     
+    #[deprecated(note = "the transport signals the completion of a write, this event is never signalled")]
     pub fn write(self) -> Event {
         let Self(events) = self;
         events[1]
@@ -57,99 +110,384 @@ impl Drop for NamedPipeEventsOwner {
     }
 }
 
+// a cheap handle for writing to a pipe from other threads
+#[derive(Clone, Debug)]
+pub struct NamedPipeWriter {
+    sender: channel::Sender<u8>,
+    events: NamedPipeEvents,
+    #[allow(dead_code)]
+    events_owner: Arc<NamedPipeEventsOwner>, // keeps the events registered
+    codec: FrameCodec,
+    disconnect_reason: Arc<OnceLock<DisconnectReason>>,
+}
+
+impl NamedPipeWriter {
+    fn wait_error(&self, error: WaitError, timeout: Option<Duration>) -> Error {
+        match error {
+            WaitError::Timeout if timeout.is_some_and(|timeout| timeout.is_zero()) => ErrorKind::WouldBlock.into(),
+            WaitError::Timeout => ErrorKind::Timeout.into(),
+            // the reason is set before the queue is disconnected
+            WaitError::Disconnected => match self.disconnect_reason.get() {
+                Some(DisconnectReason::Panicked) => ErrorKind::RuntimePanic.into(),
+                _ => ErrorKind::Disconnected.into(),
+            },
+        }
+    }
+    
+    fn write_reserve(&self, len: usize, timeout: Duration, f: impl FnOnce(&mut Vec<u8>)) -> Result<(), Error> {
+        match unsafe { self.sender.raw_buffer_reserve(len, Some(timeout), f) } {
+            Ok(()) => Ok(self.events.data().set()?),
+            Err(error) => Err(self.wait_error(error, Some(timeout))),
+        }
+    }
+    
+    pub fn flush(&self) {
+        self.sender.flush();
+    }
+    
+    // waits until the runtime has taken everything written so far, which the reference runtime only does once it is written
+    // waits forever without a timeout
+    pub fn wait_drained(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.sender.wait_drained(timeout).map_err(|error| self.wait_error(error, timeout))
+    }
+    
+    // ready once the runtime has taken everything written so far
+    pub fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.sender.poll_drained(cx).map_err(|error| self.wait_error(error, None))
+    }
+    
+    // fails with WouldBlock if a bounded write queue is full
+    pub fn write(&self, bytes: &[u8]) -> Result<(), Error> {
+        self.write_timeout(bytes, Duration::ZERO)
+    }
+    
+    // queues all of the bytes once a bounded write queue has room for them
+    pub fn poll_write(&self, cx: &mut Context<'_>, bytes: &[u8]) -> Poll<Result<(), Error>> {
+        match unsafe { self.sender.poll_raw_buffer_reserve(cx, bytes.len(), |vec| vec.extend(bytes)) } {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(self.events.data().set()?)),
+            Poll::Ready(Err(error)) => Poll::Ready(Err(self.wait_error(error, None))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    
+    // waits up to the timeout for a bounded write queue to make room, Duration::MAX waits for as long as it takes
+    // the same goes for the other timeout variants
+    pub fn write_timeout(&self, bytes: &[u8], timeout: Duration) -> Result<(), Error> {
+        self.write_reserve(bytes.len(), timeout, |vec| vec.extend(bytes))
+    }
+    
+    pub fn write_line(&self, s: &str) -> Result<(), Error> {
+        self.write_line_timeout(s, Duration::ZERO)
+    }
+    
+    pub fn write_line_timeout(&self, s: &str, timeout: Duration) -> Result<(), Error> {
+        self.write_reserve(s.len() + 1, timeout, |vec| {
+            vec.extend(s.bytes());
+            vec.push(b'\n');
+        })
+    }
+    
+    // queues the whole frame at once, so messages from different writers never interleave
+    pub fn send_message(&self, payload: &[u8]) -> Result<(), FrameError> {
+        self.send_message_timeout(payload, Duration::ZERO)
+    }
+    
+    pub fn send_message_timeout(&self, payload: &[u8], timeout: Duration) -> Result<(), FrameError> {
+        self.codec.check(payload.len())?;
+        
+        self.write_reserve(HEADER_SIZE + payload.len(), timeout, |vec| FrameCodec::encode_unchecked(payload, vec))
+            .map_err(FrameError::Pipe)
+    }
+    
+    pub fn send_message_with(&self, codec: &impl MessageCodec, payload: &[u8]) -> Result<(), FrameError> {
+        self.send_message_with_timeout(codec, payload, Duration::ZERO)
+    }
+    
+    pub fn send_message_with_timeout(&self, codec: &impl MessageCodec, payload: &[u8], timeout: Duration) -> Result<(), FrameError> {
+        let mut bytes = Vec::new();
+        
+        codec.encode(payload, &mut bytes)?;
+        
+        self.write_reserve(bytes.len(), timeout, |vec| vec.append(&mut bytes)).map_err(FrameError::Pipe)
+    }
+}
+
 pub struct NamedPipe {
     thread: JoinHandle<NamedPipeBuffer>,
-    write_sender: channel::Sender<u8>,
+    writer: NamedPipeWriter,
     read_receiver: channel::Receiver<u8>,
     events: NamedPipeEvents,
-    #[allow(dead_code)]
-    events_owner: NamedPipeEventsOwner, // for auto unregistering via drop
+    protocol: Option<Protocol>,
+    disconnect_reason: Arc<OnceLock<DisconnectReason>>, // set right before the runtime thread finishes
+    finish_event: Arc<Mutex<Option<Arc<EventOwner>>>>, // set once the reason is
 }
 
 impl NamedPipe {
-    pub fn new(handle: HANDLE, buffer: NamedPipeBuffer, executor: impl NamedPipeRuntimeExecutor) -> WindowsResult<Self> {
-        let write_sender = unsafe { channel::clone_sender(buffer.write_channel.sender()) }; // reversed
-        let read_receiver = unsafe { channel::clone_receiver(buffer.read_channel.receiver()) };
+    pub fn new<T: Transport>(transport: T, buffer: NamedPipeBuffer, executor: impl NamedPipeRuntimeExecutor<T>) -> Result<Self, Error> {
+        // a server reuses the buffer of the previous connection
+        buffer.read_channel.reconnect();
+        buffer.write_channel.reconnect();
+        
+        let write_sender = buffer.write_channel.sender().clone(); // reversed
+        let read_receiver = buffer.read_channel.receiver().clone();
         let events = NamedPipeEvents::register()?;
+        let events_owner = Arc::new(NamedPipeEventsOwner(events));
+        let runtime_events_owner = events_owner.clone(); // the runtime may outlive the pipe, the events must not be reused before it ends
+        let disconnect_reason = Arc::new(OnceLock::new());
+        let runtime_disconnect_reason = disconnect_reason.clone();
+        let finish_event = Arc::new(Mutex::new(None::<Arc<EventOwner>>));
+        let runtime_finish_event = finish_event.clone();
         
         let mut runtime = NamedPipeRuntime::new(
-            handle,
+            transport,
             buffer,
-            events.clone(),
+            events,
         );
         
         Ok(Self {
             thread: new_thread(move || {
-                executor(&mut runtime);
+                let result = panic::catch_unwind(AssertUnwindSafe(|| executor(&mut runtime)));
+                
+                drop(runtime_events_owner);
+                
+                let (buffer, reason) = runtime.destruct();
                 
-                runtime.destruct()
+                let _ = runtime_disconnect_reason.set(if result.is_ok() { reason } else { DisconnectReason::Panicked });
+                
+                if let Some(event) = runtime_finish_event.lock().unwrap().take() {
+                    let _ = event.duplicate().set();
+                }
+                
+                // the reason is set first, so it is there as soon as reads see the end, and writes fail by then as well
+                buffer.write_channel.disconnect();
+                buffer.read_channel.disconnect();
+                
+                if let Err(payload) = result {
+                    panic::resume_unwind(payload);
+                }
+                
+                buffer
             }),
-            write_sender,
+            writer: NamedPipeWriter { sender: write_sender, events, events_owner, codec: FrameCodec::default(), disconnect_reason: disconnect_reason.clone() },
             read_receiver,
-            events_owner: NamedPipeEventsOwner(events.clone()),
             events,
+            protocol: None,
+            disconnect_reason,
+            finish_event,
         })
     }
     
+    // two pipes connected in process, each still driven by its own runtime thread
+    pub fn loopback_pair(
+        buffer_a: NamedPipeBuffer,
+        buffer_b: NamedPipeBuffer,
+        executor_a: impl NamedPipeRuntimeExecutor<MemoryTransport>,
+        executor_b: impl NamedPipeRuntimeExecutor<MemoryTransport>,
+    ) -> Result<(Self, Self), Error> {
+        let (a, b) = MemoryTransport::pair()?;
+        
+        Ok((Self::new(a, buffer_a, executor_a)?, Self::new(b, buffer_b, executor_b)?))
+    }
+    
     pub fn is_finished(&self) -> bool {
         self.thread.is_finished()
     }
     
+    // None until the runtime has ended
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.disconnect_reason.get().cloned()
+    }
+    
+    // for keeping track of the pipe once it is handed out
+    pub(crate) fn shared_disconnect_reason(&self) -> Arc<OnceLock<DisconnectReason>> {
+        self.disconnect_reason.clone()
+    }
+    
+    // sets the event once the disconnect reason is there, right away if it already is
+    pub(crate) fn set_on_finish(&self, event: Arc<EventOwner>) {
+        let mut finish_event = self.finish_event.lock().unwrap();
+        
+        if self.disconnect_reason.get().is_some() {
+            let _ = event.duplicate().set();
+        }
+        else {
+            finish_event.replace(event);
+        }
+    }
+    
+    // HalfClosed until what arrived before the runtime ended has been read
+    pub fn state(&self) -> NamedPipeState {
+        if !self.read_receiver.is_disconnected() {
+            NamedPipeState::Open
+        }
+        else if self.read_receiver.peek(|_| ()).is_some() {
+            NamedPipeState::HalfClosed
+        }
+        else {
+            NamedPipeState::Closed { reason: self.disconnect_reason().unwrap_or(DisconnectReason::Panicked) }
+        }
+    }
+    
     pub fn join(self) -> Result<NamedPipeBuffer, Box<dyn std::any::Any + Send + 'static>> {
         let Self { thread, .. } = self;
         
         thread.join()
     }
     
+    pub fn writer(&self) -> NamedPipeWriter {
+        self.writer.clone()
+    }
+    
+    // None until a handshake succeeded
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.protocol
+    }
+    
+    // both ends have to call this before anything else is written, whatever the peer writes after its hello stays readable
+    pub fn handshake(&mut self, handshake: &Handshake) -> Result<Protocol, HandshakeError> {
+        self.write(&handshake.hello()).map_err(|error| match error.kind() {
+            ErrorKind::Disconnected => HandshakeError::Disconnected,
+            _ => error.into(),
+        })?;
+        
+        let mut hello = None;
+        
+        let result = unsafe {
+            self.read_receiver.raw_buffer_until(Some(handshake.timeout), |buffer| {
+                if buffer.len() >= HELLO_SIZE {
+                    hello = buffer.drain(..HELLO_SIZE).collect::<Vec<_>>().try_into().ok();
+                }
+                
+                hello.is_some()
+            })
+        };
+        
+        let hello = match (hello, result) {
+            (Some(hello), _) => hello,
+            (None, Err(RecvError::Disconnected)) => return Err(HandshakeError::Disconnected),
+            (None, _) => return Err(HandshakeError::Timeout),
+        };
+        
+        let protocol = handshake.negotiate(&hello)?;
+        
+        self.protocol = Some(protocol);
+        
+        Ok(protocol)
+    }
+    
+    pub fn max_frame_size(&self) -> usize {
+        self.writer.codec.max_frame_size()
+    }
+    
+    // applies to both directions, writers handed out before keep their maximum
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.writer.codec = FrameCodec::new(max_frame_size);
+    }
+    
     pub fn flush(&self) {
         self.read_receiver.flush();
-        self.write_sender.flush();
+        self.writer.flush();
+    }
+    
+    pub fn wait_drained(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.writer.wait_drained(timeout)
     }
     
-    pub fn read(&self) -> Vec<u8> {
-        self.read_receiver.receive_all()
+    pub fn poll_drained(&self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.writer.poll_drained(cx)
     }
     
+    pub fn read(&self) -> ReadResult {
+        let closed = self.read_receiver.is_disconnected(); // checked first, nothing arrives after the disconnect
+        let bytes = self.read_receiver.receive_all();
+        
+        match bytes.is_empty() {
+            false => ReadResult::Data(bytes),
+            true if closed => ReadResult::Eof,
+            true => ReadResult::Empty,
+        }
+    }
+    
+    fn read_until(&self, timeout: Option<Duration>) -> ReadResult {
+        let mut result = Vec::new();
+        
+        let disconnected = unsafe {
+            self.read_receiver.raw_buffer_until(timeout, |buffer| {
+                result.extend(buffer.drain(..));
+                !result.is_empty()
+            })
+        };
+        
+        match disconnected {
+            _ if !result.is_empty() => ReadResult::Data(result),
+            Err(RecvError::Disconnected) => ReadResult::Eof,
+            _ => ReadResult::Empty,
+        }
+    }
+    
+    // blocks until there is something to read, returns Eof once nothing ever will be
+    pub fn read_blocking(&self) -> ReadResult {
+        self.read_until(None)
+    }
+    
+    // returns Empty if nothing arrived before the timeout
+    pub fn read_timeout(&self, timeout: Duration) -> ReadResult {
+        self.read_until(Some(timeout))
+    }
+    
+    // Data once there is something to read or Eof once nothing ever will be, the task is woken when data arrives
+    pub fn poll_read(&self, cx: &mut Context<'_>) -> Poll<ReadResult> {
+        let mut result = Vec::new();
+        
+        let disconnected = unsafe {
+            self.read_receiver.poll_raw_buffer_until(cx, |buffer| {
+                result.extend(buffer.drain(..));
+                !result.is_empty()
+            })
+        };
+        
+        match disconnected {
+            Poll::Ready(Ok(())) => Poll::Ready(ReadResult::Data(result)),
+            Poll::Ready(Err(_)) => Poll::Ready(ReadResult::Eof),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    
+    // a last line without a newline is returned once the pipe is closed, Eof after that
     pub fn read_line(&self) -> ReadLineResult {
+        let closed = self.read_receiver.is_disconnected(); // checked first, nothing arrives after the disconnect
         let mut result = ReadLineResult::Empty;
         
         unsafe {
-            self.read_receiver.raw_buffer(|buffer| {
-                
-                result = if let Some(s) = buffer.utf8_chunks().next() {
-                    if s.invalid().is_empty() {
-                        let s = s.valid();
-                        
-                        if s.contains('\n') {
-                            let s = s.lines().next().unwrap().to_owned();
-                            
-                            buffer.drain(..=s.len());
-                            
-                            ReadLineResult::Line(s)
-                        }
-                        else {
-                            ReadLineResult::NotALine
-                        }
-                    }
-                    else {
-                        ReadLineResult::InvalidUtf8
-                    }
-                }
-                else {
-                    ReadLineResult::Empty
-                }
-            });
+            self.read_receiver.raw_buffer(|buffer| result = take_last_line(buffer, closed));
         }
         
         result
     }
     
+    // waits for a whole line (or invalid utf8), returns Empty or NotALine if it timed out first
+    pub fn read_line_timeout(&self, timeout: Duration) -> ReadLineResult {
+        let mut result = ReadLineResult::Empty;
+        
+        let disconnected = unsafe {
+            self.read_receiver.raw_buffer_until(Some(timeout), |buffer| {
+                result = take_line(buffer);
+                !matches!(result, ReadLineResult::Empty | ReadLineResult::NotALine)
+            })
+        };
+        
+        match disconnected {
+            Err(RecvError::Disconnected) => self.read_line(),
+            _ => result,
+        }
+    }
+    
     pub fn read_invalid_utf8(&self) -> Option<Vec<u8>> {
         let mut result = None;
         
         unsafe {
             self.read_receiver.raw_buffer(|buffer| {
-                result = buffer.utf8_chunks().next()
+                result = buffer.make_contiguous().utf8_chunks().next()
                     .map(|s| s.invalid())
                     .and_then(|s| s.is_empty().then(|| s.to_owned()));
                 
@@ -162,24 +500,103 @@ impl NamedPipe {
         result
     }
     
-    pub fn write(&self, bytes: &[u8]) -> WindowsResult<()> {
+    // fails with WouldBlock if a bounded write queue is full
+    pub fn write(&self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write(bytes)
+    }
+    
+    // waits up to the timeout for a bounded write queue to make room
+    pub fn write_timeout(&self, bytes: &[u8], timeout: Duration) -> Result<(), Error> {
+        self.writer.write_timeout(bytes, timeout)
+    }
+    
+    pub fn poll_write(&self, cx: &mut Context<'_>, bytes: &[u8]) -> Poll<Result<(), Error>> {
+        self.writer.poll_write(cx, bytes)
+    }
+    
+    pub fn write_line(&self, s: &str) -> Result<(), Error> {
+        self.writer.write_line(s)
+    }
+    
+    pub fn write_line_timeout(&self, s: &str, timeout: Duration) -> Result<(), Error> {
+        self.writer.write_line_timeout(s, timeout)
+    }
+    
+    pub fn send_message(&self, payload: &[u8]) -> Result<(), FrameError> {
+        self.writer.send_message(payload)
+    }
+    
+    pub fn send_message_timeout(&self, payload: &[u8], timeout: Duration) -> Result<(), FrameError> {
+        self.writer.send_message_timeout(payload, timeout)
+    }
+    
+    // returns None until a whole message has arrived
+    // a message cut short by the end of the connection is reported as truncated once the runtime has finished
+    pub fn recv_message(&self) -> Result<Option<Vec<u8>>, FrameError> {
+        self.recv_message_with(&self.writer.codec)
+    }
+    
+    // waits for a whole message, returns None if it timed out first
+    pub fn recv_message_timeout(&self, timeout: Duration) -> Result<Option<Vec<u8>>, FrameError> {
+        self.recv_message_with_timeout(&self.writer.codec, timeout)
+    }
+    
+    pub fn send_message_with(&self, codec: &impl MessageCodec, payload: &[u8]) -> Result<(), FrameError> {
+        self.writer.send_message_with(codec, payload)
+    }
+    
+    pub fn send_message_with_timeout(&self, codec: &impl MessageCodec, payload: &[u8], timeout: Duration) -> Result<(), FrameError> {
+        self.writer.send_message_with_timeout(codec, payload, timeout)
+    }
+    
+    pub fn recv_message_with(&self, codec: &impl MessageCodec) -> Result<Option<Vec<u8>>, FrameError> {
+        let closed = self.read_receiver.is_disconnected(); // checked first, nothing arrives after the disconnect
+        let mut result = Ok(None);
+        
         unsafe {
-            self.write_sender.raw_buffer(|vec| vec.extend(bytes));
-            self.events.data().set()
+            self.read_receiver.raw_buffer(|buffer| {
+                result = match codec.decode(buffer) {
+                    Ok(None) if closed => codec.finish(buffer).map(|_| None),
+                    result => result,
+                };
+            });
         }
+        
+        result
+    }
+    
+    pub fn recv_message_with_timeout(&self, codec: &impl MessageCodec, timeout: Duration) -> Result<Option<Vec<u8>>, FrameError> {
+        self.recv_message_until(codec, Some(timeout), || false)
     }
     
-    pub fn write_line(&self, s: &str) -> WindowsResult<()> {
+    // like recv_message_with_timeout, but also returns None once woken by wake_readers with stop returning true
+    // waits until a message arrives or the pipe closes without a timeout
+    pub(crate) fn recv_message_until(&self, codec: &impl MessageCodec, timeout: Option<Duration>, stop: impl Fn() -> bool) -> Result<Option<Vec<u8>>, FrameError> {
+        let mut result = Ok(None);
+        
         unsafe {
-            self.write_sender.raw_buffer(|vec| {
-                vec.extend(s.bytes());
-                vec.push(b'\n');
+            let _ = self.read_receiver.raw_buffer_until(timeout, |buffer| {
+                result = codec.decode(buffer);
+                !matches!(result, Ok(None)) || stop()
             });
-            self.events.data().set()
+        }
+        
+        match result {
+            Ok(None) => self.recv_message_with(codec),
+            result => result,
         }
     }
     
-    pub fn interrupt(&self) -> WindowsResult<()> {
+    pub fn interrupt(&self) -> Result<(), Error> {
         self.events.interrupt().set()
     }
+    
+    pub(crate) fn codec(&self) -> &FrameCodec {
+        &self.writer.codec
+    }
+    
+    // wakes every thread waiting for something to read, for the waits that check a condition of their own
+    pub(crate) fn wake_readers(&self) {
+        self.read_receiver.wake();
+    }
 }
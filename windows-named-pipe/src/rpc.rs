@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{dispatch::*, utils::*};
+
+// every rpc message is one frame starting with its kind and the id of the call
+// a request continues with the method name prefixed by its length as a little endian u32, then the payload
+// a response continues with its status, then the payload or the error
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+
+const OK: u8 = 0;
+const UNKNOWN_METHOD: u8 = 1;
+const REMOTE_ERROR: u8 = 2;
+
+const ID_SIZE: usize = size_of::<u64>();
+
+#[derive(Debug)]
+pub enum RpcError {
+    Timeout,
+    UnknownMethod(String),
+    Remote(String), // the handler failed with this message
+    Disconnected, // the pipe finished or the client was dropped before the response arrived
+    Protocol(String), // the peer sent something that is not a valid response
+    Frame(FrameError),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Timeout => write!(f, "call timed out"),
+            RpcError::UnknownMethod(method) => write!(f, "unknown method {method:?}"),
+            RpcError::Remote(message) => write!(f, "remote error: {message}"),
+            RpcError::Disconnected => write!(f, "disconnected"),
+            RpcError::Protocol(message) => write!(f, "protocol error: {message}"),
+            RpcError::Frame(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<FrameError> for RpcError {
+    fn from(error: FrameError) -> Self {
+        RpcError::Frame(error)
+    }
+}
+
+pub type RpcResult = Result<Vec<u8>, RpcError>;
+
+fn encode_request(id: u64, method: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(1 + ID_SIZE + HEADER_SIZE + method.len() + payload.len());
+    
+    message.push(REQUEST);
+    message.extend(id.to_le_bytes());
+    message.extend((method.len() as u32).to_le_bytes());
+    message.extend(method.as_bytes());
+    message.extend(payload);
+    message
+}
+
+fn encode_response(id: u64, status: u8, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(1 + ID_SIZE + 1 + payload.len());
+    
+    message.push(RESPONSE);
+    message.extend(id.to_le_bytes());
+    message.push(status);
+    message.extend(payload);
+    message
+}
+
+// splits a message into its kind, id and the rest
+fn split_message(message: &[u8]) -> Option<(u8, u64, &[u8])> {
+    let (&kind, rest) = message.split_first()?;
+    let (id, rest) = rest.split_first_chunk::<ID_SIZE>()?;
+    
+    Some((kind, u64::from_le_bytes(*id), rest))
+}
+
+fn decode_request(rest: &[u8]) -> Option<(&str, &[u8])> {
+    let (len, rest) = rest.split_first_chunk::<HEADER_SIZE>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    
+    if rest.len() < len {
+        return None;
+    }
+    
+    let (method, payload) = rest.split_at(len);
+    
+    Some((std::str::from_utf8(method).ok()?, payload))
+}
+
+fn decode_response(rest: &[u8]) -> RpcResult {
+    let text = |payload: &[u8]| String::from_utf8_lossy(payload).into_owned();
+    
+    match rest.split_first() {
+        Some((&OK, payload)) => Ok(payload.to_owned()),
+        Some((&UNKNOWN_METHOD, payload)) => Err(RpcError::UnknownMethod(text(payload))),
+        Some((&REMOTE_ERROR, payload)) => Err(RpcError::Remote(text(payload))),
+        Some((status, _)) => Err(RpcError::Protocol(format!("unknown response status {status}"))),
+        None => Err(RpcError::Protocol("response without a status".into())),
+    }
+}
+
+fn responses(message: &[u8]) -> Vec<(u64, RpcResult)> {
+    match split_message(message) {
+        Some((RESPONSE, id, rest)) => vec![(id, decode_response(rest))],
+        Some((kind, id, _)) => vec![(id, Err(RpcError::Protocol(format!("unexpected message kind {kind}"))))],
+        None => Vec::new(), // without an id there is no call to fail
+    }
+}
+
+impl CallError for RpcError {
+    fn timeout() -> Self {
+        RpcError::Timeout
+    }
+    
+    fn disconnected() -> Self {
+        RpcError::Disconnected
+    }
+    
+    fn protocol(message: String) -> Self {
+        RpcError::Protocol(message)
+    }
+}
+
+// makes calls over a pipe, any number of them can be in flight at once
+// a background thread takes every message arriving on the pipe and resolves the call it answers
+pub struct RpcClient {
+    dispatcher: Dispatcher<Calls<Vec<u8>, RpcError>>,
+}
+
+impl RpcClient {
+    pub fn new(pipe: NamedPipe) -> Self {
+        let codec = *pipe.codec();
+        
+        Self { dispatcher: Dispatcher::new(pipe, codec, Calls::new(responses)) }
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        self.dispatcher.pipe()
+    }
+    
+    // true once every call fails with Disconnected or Protocol
+    pub fn is_closed(&self) -> bool {
+        self.dispatcher.handler().is_closed()
+    }
+    
+    // the callback runs on the dispatch thread once the call resolves, or right away if the request cannot be sent
+    pub fn call_with(&self, method: &str, payload: &[u8], timeout: Option<Duration>, callback: impl FnOnce(RpcResult) + Send + 'static) {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let Some(id) = self.dispatcher.call(deadline, callback) else { return };
+        
+        if let Err(error) = self.pipe().send_message(&encode_request(id, method, payload)) {
+            self.dispatcher.handler().resolve(id, Err(error.into()));
+        }
+    }
+    
+    // blocks until the response arrives, the call times out or the pipe disconnects
+    pub fn call(&self, method: &str, payload: &[u8], timeout: Duration) -> RpcResult {
+        let (sender, receiver) = channel::Channel::bounded(1).unwrap();
+        
+        self.call_with(method, payload, Some(timeout), move |result| {
+            let _ = sender.send(result);
+        });
+        
+        receiver.recv_blocking().unwrap_or(Err(RpcError::Disconnected))
+    }
+}
+
+pub type RpcHandler = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync>;
+
+// answers calls with the handler registered for their method
+#[derive(Default)]
+pub struct RpcHandlers {
+    handlers: HashMap<String, RpcHandler>,
+}
+
+impl RpcHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    // replaces the handler registered before for the method
+    pub fn register(&mut self, method: &str, handler: impl Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync + 'static) -> &mut Self {
+        self.handlers.insert(method.to_owned(), Box::new(handler));
+        self
+    }
+    
+    fn handle(&self, pipe: &NamedPipe, message: &[u8]) -> Result<(), FrameError> {
+        // malformed requests are dropped, there is nothing to answer them with
+        let Some((REQUEST, id, rest)) = split_message(message) else { return Ok(()) };
+        let Some((method, payload)) = decode_request(rest) else { return Ok(()) };
+        
+        let response = match self.handlers.get(method) {
+            Some(handler) => match handler(payload) {
+                Ok(result) => encode_response(id, OK, &result),
+                Err(error) => encode_response(id, REMOTE_ERROR, error.as_bytes()),
+            }
+            None => encode_response(id, UNKNOWN_METHOD, method.as_bytes()),
+        };
+        
+        pipe.send_message(&response)
+    }
+    
+    // answers every call that has arrived so far without blocking, returns how many there were
+    pub fn serve_pending(&self, pipe: &NamedPipe) -> Result<usize, FrameError> {
+        let mut count = 0;
+        
+        while let Some(message) = pipe.recv_message()? {
+            self.handle(pipe, &message)?;
+            count += 1;
+        }
+        
+        Ok(count)
+    }
+    
+    // answers calls until the pipe finishes, blocks while none arrive
+    // several threads can serve the same pipe so slow calls do not hold up the others
+    pub fn serve(&self, pipe: &NamedPipe) -> Result<(), FrameError> {
+        loop {
+            let finished = pipe.state() != NamedPipeState::Open; // checked first, nothing arrives after the disconnect
+            
+            match pipe.recv_message_until(pipe.codec(), None, || false)? {
+                Some(message) => self.handle(pipe, &message)?,
+                None if finished => return Ok(()),
+                None => {}
+            }
+        }
+    }
+}
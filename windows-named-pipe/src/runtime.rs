@@ -1,77 +1,114 @@
 
-use windows::Win32::{
-    Foundation::{CloseHandle, ERROR_IO_PENDING},
-    Storage::FileSystem::{
-        ReadFile,
-        WriteFile,
-    },
-};
+use std::{fmt, time::Duration};
 
 use crate::utils::*;
 
 pub mod utils;
+pub mod heartbeat;
 
-pub trait NamedPipeRuntimeExecutor: FnOnce(&mut NamedPipeRuntime) + Send + 'static {}
-impl<T: FnOnce(&mut NamedPipeRuntime) + Send + 'static> NamedPipeRuntimeExecutor for T {}
+pub trait NamedPipeRuntimeExecutor<T: Transport = PipeTransport>: FnOnce(&mut NamedPipeRuntime<T>) + Send + 'static {}
+impl<T: Transport, F: FnOnce(&mut NamedPipeRuntime<T>) + Send + 'static> NamedPipeRuntimeExecutor<T> for F {}
 
-pub struct NamedPipeRuntime {
-    handle: HANDLE,
+pub struct NamedPipeRuntime<T = PipeTransport> {
+    transport: T,
     buffer: NamedPipeBuffer,
     events: NamedPipeEvents,
     read_pending: bool,
     write_pending: bool,
+    disconnect_reason: Option<DisconnectReason>,
 }
 
-unsafe impl Send for NamedPipeRuntime {}
+// why a runtime ended, Closed if it did not say
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Closed, // by the other end
+    Interrupted,
+    PeerTimeout(Duration), // nothing was heard from the other end for this long
+    Handshake(String),
+    Error(String),
+    Panicked, // the runtime itself
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Closed => write!(f, "closed by the other end"),
+            DisconnectReason::Interrupted => write!(f, "interrupted"),
+            DisconnectReason::PeerTimeout(silence) => write!(f, "nothing heard from the other end for {silence:?}"),
+            DisconnectReason::Handshake(error) => write!(f, "handshake failed: {error}"),
+            DisconnectReason::Error(error) => write!(f, "pipe error: {error}"),
+            DisconnectReason::Panicked => write!(f, "runtime panicked"),
+        }
+    }
+}
+
+impl DisconnectReason {
+    pub fn from_error(error: &Error) -> Self {
+        if matches!(error.kind(), ErrorKind::Disconnected) {
+            DisconnectReason::Closed
+        }
+        else {
+            DisconnectReason::Error(error.to_string())
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct WaitResult {
-    pub read: Option<WindowsResult<usize>>,
-    pub write: Option<WindowsResult<usize>>,
+    pub read: Option<Result<usize, Error>>,
+    pub write: Option<Result<usize, Error>>,
     pub data: bool,
     pub interrupt: bool,
+    pub timed_out: bool, // nothing happened before the timeout
 }
 
-impl NamedPipeRuntime {
-    pub(crate) fn new(handle: HANDLE, buffer: NamedPipeBuffer, events: NamedPipeEvents) -> Self {
+impl<T: Transport> NamedPipeRuntime<T> {
+    pub(crate) fn new(transport: T, buffer: NamedPipeBuffer, events: NamedPipeEvents) -> Self {
         Self {
-            handle,
+            transport,
             buffer,
             events,
             read_pending: false,
             write_pending: false,
+            disconnect_reason: None,
         }
     }
     
-    pub(crate) fn destruct(self) -> NamedPipeBuffer {
-        let Self { buffer, .. } = self;
+    pub(crate) fn destruct(self) -> (NamedPipeBuffer, DisconnectReason) {
+        let Self { buffer, disconnect_reason, .. } = self;
         
-        buffer
+        (buffer, disconnect_reason.unwrap_or(DisconnectReason::Closed))
     }
     
-    pub fn wait(&mut self) -> (WaitResult, Option<WindowsError>) {
-        unsafe {
-            let mut result = WaitResult { ..Default::default() };
-            
-            let events = [
-                self.events.read(),
-                if self.write_pending { self.events.write() } else { self.events.data() },
-                self.events.interrupt(),
-            ];
-            
-            let error = events.wait_signals_event(|event| {
-                if event == self.events.read() {
-                    let (_, read_overlapped) = self.buffer.read.as_ref();
-                    result.read.replace(get_overlapped_result(self.handle, read_overlapped));
-                    self.read_pending = false;
-                }
-                
-                if event == self.events.write() {
-                    let (_, write_overlapped) = self.buffer.write.as_ref();
-                    result.write.replace(get_overlapped_result(self.handle, write_overlapped));
-                    self.write_pending = false;
-                }
-                
+    // the first reason sticks
+    pub fn set_disconnect_reason(&mut self, reason: DisconnectReason) {
+        self.disconnect_reason.get_or_insert(reason);
+    }
+    
+    pub fn wait(&mut self) -> (WaitResult, Option<Error>) {
+        self.wait_for(None)
+    }
+    
+    pub fn wait_timeout(&mut self, timeout: Duration) -> (WaitResult, Option<Error>) {
+        self.wait_for(Some(timeout))
+    }
+    
+    fn wait_for(&mut self, timeout: Option<Duration>) -> (WaitResult, Option<Error>) {
+        let mut result = WaitResult { ..Default::default() };
+        
+        let events = [self.events.data(), self.events.interrupt()];
+        let events = if self.write_pending { &events[1..] } else { &events[..] };
+        
+        let timed_out = self.transport.wait(events, timeout, |completion| match completion {
+            IoCompletion::Read(bytes) => {
+                result.read.replace(bytes);
+                self.read_pending = false;
+            }
+            IoCompletion::Write(bytes) => {
+                result.write.replace(bytes);
+                self.write_pending = false;
+            }
+            IoCompletion::Event(event) => {
                 if event == self.events.data() {
                     result.data = true;
                 }
@@ -79,9 +116,15 @@ impl NamedPipeRuntime {
                 if event == self.events.interrupt() {
                     result.interrupt = true;
                 }
-            }).err();
-            
-            (result, error)
+            }
+        });
+        
+        match timed_out {
+            Ok(timed_out) => {
+                result.timed_out = timed_out;
+                (result, None)
+            }
+            Err(error) => (result, Some(error)),
         }
     }
     
@@ -94,19 +137,11 @@ impl NamedPipeRuntime {
     }
     
     // returns true if there is no ongoing read operation
-    pub fn read(&mut self) -> WindowsResult<bool> {
+    pub fn read(&mut self) -> Result<bool, Error> {
         unsafe {
             if self.read_pending { Ok(false) }
             else {
-                self.buffer.read.set_event(self.events.read());
-                
-                let (buffer, overlapped) = self.buffer.read.as_mut();
-                
-                match ReadFile(self.handle, Some(buffer), None, Some(overlapped)) {
-                    Ok(()) => self.events.read().set()?,
-                    Err(_) if GetLastError() == ERROR_IO_PENDING => {}
-                    Err(error) => Err(error)?,
-                }
+                self.transport.start_read(self.buffer.read.as_ptr())?;
                 
                 self.read_pending = true;
                 
@@ -116,19 +151,13 @@ impl NamedPipeRuntime {
     }
     
     // returns true if there is no ongoing write operation
-    pub fn write(&mut self, len: usize) -> WindowsResult<bool> {
+    pub fn write(&mut self, len: usize) -> Result<bool, Error> {
         unsafe {
             if self.write_buf().is_none_or(|buffer| !(1..=buffer.len()).contains(&len)) { Ok(false) }
             else {
-                self.buffer.write.set_event(self.events.write());
+                let buffer = self.buffer.write.as_ptr();
                 
-                let (buffer, overlapped) = self.buffer.write.as_ref();
-                
-                match WriteFile(self.handle, Some(&buffer[..len]), None, Some(overlapped)) {
-                    Ok(()) => self.events.write().set()?,
-                    Err(_) if GetLastError() == ERROR_IO_PENDING => {}
-                    Err(error) => Err(error)?,
-                }
+                self.transport.start_write(NonNull::slice_from_raw_parts(buffer.cast(), len))?;
                 
                 self.write_pending = true;
                 
@@ -140,18 +169,14 @@ impl NamedPipeRuntime {
     // returns the read buffer if there is no ongoing read operation
     pub fn read_buf(&self) -> Option<&[u8]> {
         (!self.read_pending).then(|| {
-            let (buffer, _) = unsafe { self.buffer.read.as_ref() };
-            
-            buffer
+            unsafe { self.buffer.read.as_ref() }
         })
     }
     
     // returns the write buffer if there is no ongoing write operation
     pub fn write_buf(&mut self) -> Option<&mut [u8]> {
         (!self.write_pending).then(|| {
-            let (buffer, _) = unsafe { self.buffer.write.as_mut() };
-            
-            buffer
+            unsafe { self.buffer.write.as_mut() }
         })
     }
     
@@ -160,8 +185,7 @@ impl NamedPipeRuntime {
         if self.write_pending { false }
         else {
             unsafe {
-                let (buffer, _) = self.buffer.write.as_mut();
-                f(self.buffer.write_channel.receiver(), buffer);
+                f(self.buffer.write_channel.receiver(), self.buffer.write.as_mut());
                 true
             }
         }
@@ -172,14 +196,15 @@ impl NamedPipeRuntime {
         if self.read_pending { false }
         else {
             unsafe {
-                let (buffer, _) = self.buffer.read.as_ref();
-                f(self.buffer.read_channel.sender(), buffer);
+                f(self.buffer.read_channel.sender(), self.buffer.read.as_ref());
                 true
             }
         }
     }
     
-    pub unsafe fn close(&self) -> WindowsResult<()> {
-        unsafe { CloseHandle(self.handle) }
+    /// # Safety
+    /// see Transport::close
+    pub unsafe fn close(&mut self) -> Result<(), Error> {
+        unsafe { self.transport.close() }
     }
 }
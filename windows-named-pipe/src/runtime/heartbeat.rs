@@ -0,0 +1,259 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+#[cfg(feature = "test-util")]
+use std::sync::{Arc, Mutex};
+
+use crate::utils::*;
+
+// with heartbeats everything on the wire is a chunk of a kind byte, a little endian u16 length and the payload
+// so both ends have to run a heartbeat runtime
+const CHUNK_HEADER_SIZE: usize = 1 + size_of::<u16>();
+const DATA: u8 = 0;
+const PING: u8 = 1;
+
+pub trait Clock: Send + 'static {
+    fn now(&self) -> Instant;
+    
+    // how long the runtime may wait before looking at the clock again
+    fn max_wait(&self) -> Duration {
+        Duration::MAX
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// only moves when told to, clones share the time
+#[cfg(feature = "test-util")]
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<Instant>>);
+
+#[cfg(feature = "test-util")]
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl ManualClock {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+    
+    pub fn advance(&self, duration: Duration) {
+        let Self(now) = self;
+        
+        *now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        let Self(now) = self;
+        
+        *now.lock().unwrap()
+    }
+    
+    // the time can jump whenever, so it is looked at often
+    fn max_wait(&self) -> Duration {
+        Duration::from_millis(1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Heartbeat {
+    pub interval: Duration, // a ping is sent after this long without writing anything
+    pub timeout: Duration, // the peer is dead after this long without reading anything
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(5))
+    }
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self { interval, timeout }
+    }
+}
+
+struct State<C> {
+    heartbeat: Heartbeat,
+    clock: C,
+    outgoing: VecDeque<u8>, // encoded chunks not written yet
+    taken: usize, // the payload in outgoing, left in the write channel until it is written so the pipe is not drained before that
+    incoming: Vec<u8>, // the start of a chunk that has not fully arrived yet
+    last_write: Instant,
+    last_read: Instant,
+}
+
+impl<C: Clock> State<C> {
+    // moves whole chunks to the read channel
+    fn decode(&mut self, sender: &channel::Sender<u8>) -> Result<(), DisconnectReason> {
+        let mut start = 0;
+        
+        while let Some(header) = self.incoming.get(start..start + CHUNK_HEADER_SIZE) {
+            let kind = header[0];
+            let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+            let Some(payload) = self.incoming.get(start + CHUNK_HEADER_SIZE..start + CHUNK_HEADER_SIZE + len) else {
+                break;
+            };
+            
+            match kind {
+                DATA => unsafe { sender.raw_buffer(|buffer| buffer.extend(payload)) },
+                PING => {}
+                kind => return Err(DisconnectReason::Error(format!("unknown heartbeat chunk kind {kind}"))),
+            }
+            
+            start += CHUNK_HEADER_SIZE + len;
+        }
+        
+        self.incoming.drain(..start);
+        
+        Ok(())
+    }
+    
+    // starts the next write, encoding what the application queued once the previous chunk is out
+    fn write<T: Transport>(&mut self, runtime: &mut NamedPipeRuntime<T>) -> Result<(), Error> {
+        let mut len = 0;
+        
+        runtime.receive(|receiver, bytes| {
+            if self.outgoing.is_empty() {
+                unsafe {
+                    receiver.raw_buffer(|buffer| {
+                        buffer.drain(..self.taken);
+                        
+                        let len = buffer.len().min(u16::MAX as usize);
+                        
+                        if len > 0 {
+                            self.outgoing.push_back(DATA);
+                            self.outgoing.extend((len as u16).to_le_bytes());
+                            self.outgoing.extend(buffer.range(..len));
+                        }
+                        
+                        self.taken = len;
+                    });
+                }
+            }
+            
+            len = self.outgoing.len().min(bytes.len());
+            
+            for (byte, &b) in bytes.iter_mut().zip(self.outgoing.range(..len)) {
+                *byte = b;
+            }
+        });
+        
+        runtime.write(len)?;
+        
+        Ok(())
+    }
+    
+    // a ping has to wait for the write in progress
+    fn next_deadline(&self, writing: bool) -> Instant {
+        let timeout = self.last_read + self.heartbeat.timeout;
+        
+        if writing { timeout } else { timeout.min(self.last_write + self.heartbeat.interval) }
+    }
+}
+
+fn heartbeat_implementation<T: Transport, C: Clock>(runtime: &mut NamedPipeRuntime<T>, heartbeat: Heartbeat, clock: C) -> Result<(), Error> {
+    let now = clock.now();
+    let mut state = State {
+        heartbeat,
+        clock,
+        outgoing: VecDeque::new(),
+        taken: 0,
+        incoming: Vec::new(),
+        last_write: now,
+        last_read: now,
+    };
+    
+    runtime.read()?;
+    state.write(runtime)?;
+    
+    loop {
+        let now = state.clock.now();
+        
+        if now.saturating_duration_since(state.last_read) >= heartbeat.timeout {
+            runtime.set_disconnect_reason(DisconnectReason::PeerTimeout(now.saturating_duration_since(state.last_read)));
+            break;
+        }
+        
+        if now.saturating_duration_since(state.last_write) >= heartbeat.interval && !runtime.is_writing() && state.outgoing.is_empty() {
+            state.outgoing.extend([PING, 0, 0]);
+            state.last_write = now;
+            state.write(runtime)?;
+        }
+        
+        let timeout = state.next_deadline(runtime.is_writing()).saturating_duration_since(now).min(state.clock.max_wait());
+        let (wait_result, error) = runtime.wait_timeout(timeout);
+        
+        if let Some(error) = error {
+            Err(error)?;
+        }
+        
+        if wait_result.interrupt {
+            runtime.set_disconnect_reason(DisconnectReason::Interrupted);
+            break;
+        }
+        
+        if let Some(read_len) = wait_result.read {
+            let read_len = read_len?;
+            let mut result = Ok(());
+            
+            state.last_read = state.clock.now();
+            
+            runtime.send(|sender, bytes| {
+                state.incoming.extend(&bytes[..read_len]);
+                result = state.decode(sender);
+            });
+            
+            if let Err(reason) = result {
+                runtime.set_disconnect_reason(reason);
+                break;
+            }
+            
+            runtime.read()?;
+        }
+        
+        if let Some(write_len) = wait_result.write {
+            let write_len = write_len?;
+            
+            state.outgoing.drain(..write_len);
+            state.last_write = state.clock.now();
+            
+            // reset first, so data queued after the check still wakes the runtime
+            runtime.events.data().reset()?;
+            state.write(runtime)?;
+        }
+        
+        if wait_result.data {
+            state.write(runtime)?;
+        }
+    }
+    
+    Ok(())
+}
+
+// keeps the connection alive with pings and ends it once the peer has been silent for the timeout
+// the peer has to run a heartbeat runtime as well, pings never show up in application reads
+pub fn runtime_heartbeat_implementation<T: Transport>(
+    error_handler: impl FnOnce(Error) + Send + 'static,
+    heartbeat: Heartbeat,
+    clock: impl Clock,
+) -> impl NamedPipeRuntimeExecutor<T> {
+    move |runtime| {
+        if let Err(error) = heartbeat_implementation(runtime, heartbeat, clock) {
+            runtime.set_disconnect_reason(DisconnectReason::from_error(&error));
+            error_handler(error)
+        }
+    }
+}
@@ -1,7 +1,9 @@
 
+use std::time::{Duration, Instant};
+
 use crate::utils::*;
 
-fn write(runtime: &mut NamedPipeRuntime) -> WindowsResult<bool> {
+fn write<T: Transport>(runtime: &mut NamedPipeRuntime<T>) -> Result<bool, Error> {
     let mut len = 0;
     
     runtime.receive(|receiver, bytes| {
@@ -9,7 +11,9 @@ fn write(runtime: &mut NamedPipeRuntime) -> WindowsResult<bool> {
             receiver.raw_buffer(|buffer| {
                 len = buffer.len().min(bytes.len());
                 
-                bytes[..len].copy_from_slice(&buffer[..len]);
+                for (byte, &b) in bytes.iter_mut().zip(buffer.range(..len)) {
+                    *byte = b;
+                }
             });
         }
     });
@@ -17,17 +21,27 @@ fn write(runtime: &mut NamedPipeRuntime) -> WindowsResult<bool> {
     runtime.write(len)
 }
 
-fn reference_implementation(runtime: &mut NamedPipeRuntime) -> WindowsResult<()> {
+// the tick is called roughly every interval, an error stops the runtime
+fn reference_implementation<T: Transport, F: FnMut(&mut NamedPipeRuntime<T>) -> Result<(), Error>>(
+    runtime: &mut NamedPipeRuntime<T>,
+    mut tick: Option<(Duration, F)>,
+) -> Result<(), Error> {
+    let mut next_tick = tick.as_ref().map(|(interval, _)| Instant::now() + *interval);
+    
     runtime.read()?;
     
     loop {
-        let (wait_result, error) = runtime.wait();
+        let (wait_result, error) = match next_tick {
+            Some(next_tick) => runtime.wait_timeout(next_tick.saturating_duration_since(Instant::now())),
+            None => runtime.wait(),
+        };
         
         if let Some(error) = error {
             Err(error)?;
         }
         
         if wait_result.interrupt {
+            runtime.set_disconnect_reason(DisconnectReason::Interrupted);
             break;
         }
         
@@ -46,22 +60,42 @@ fn reference_implementation(runtime: &mut NamedPipeRuntime) -> WindowsResult<()>
             
             runtime.receive(|receiver, _| unsafe { receiver.raw_buffer(|buffer| { buffer.drain(..write_len); }); });
             
-            if !write(runtime)? {
-                runtime.events.data().reset()?;
-            }
+            // reset first, so data queued after the check still wakes the runtime
+            runtime.events.data().reset()?;
+            write(runtime)?;
         }
         
         if wait_result.data {
             write(runtime)?;
         }
+        
+        if let (Some(next_tick), Some((interval, tick))) = (&mut next_tick, &mut tick) && Instant::now() >= *next_tick {
+            tick(runtime)?;
+            
+            *next_tick = Instant::now() + *interval;
+        }
     }
     
     Ok(())
 }
 
-pub fn runtime_reference_implementation(error_handler: impl FnOnce(WindowsError) + Send + 'static) -> impl NamedPipeRuntimeExecutor {
+pub fn runtime_reference_implementation<T: Transport>(error_handler: impl FnOnce(Error) + Send + 'static) -> impl NamedPipeRuntimeExecutor<T> {
     |runtime| {
-        if let Err(error) = reference_implementation(runtime) {
+        if let Err(error) = reference_implementation(runtime, None::<(Duration, fn(&mut NamedPipeRuntime<T>) -> Result<(), Error>)>) {
+            runtime.set_disconnect_reason(DisconnectReason::from_error(&error));
+            error_handler(error)
+        }
+    }
+}
+
+pub fn runtime_reference_implementation_with_tick<T: Transport>(
+    error_handler: impl FnOnce(Error) + Send + 'static,
+    interval: Duration,
+    tick: impl FnMut(&mut NamedPipeRuntime<T>) -> Result<(), Error> + Send + 'static,
+) -> impl NamedPipeRuntimeExecutor<T> {
+    move |runtime| {
+        if let Err(error) = reference_implementation(runtime, Some((interval, tick))) {
+            runtime.set_disconnect_reason(DisconnectReason::from_error(&error));
             error_handler(error)
         }
     }
@@ -1,7 +1,7 @@
 
-use std::time::Duration;
+use std::{sync::Arc, task::{Context, Poll, Wake, Waker}, thread::{self, JoinHandle, Thread}, time::{Duration, Instant}};
 
-use crate::utils::*;
+use crate::{error::ErrorContext, utils::*};
 
 pub struct ServerNamedPipeEvent<F>(ServerNamedPipe<F>, EventOwner);
 
@@ -22,6 +22,20 @@ impl<F> ServerNamedPipeEvent<F> {
     }
 }
 
+// how many instances Server::incoming and Server::serve keep
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServerPool {
+    pub min_idle: usize, // instances waiting for clients, at least one
+    pub max_instances: Option<u32>, // clients beyond it see the pipe as busy, it is the limit of the name as well
+    pub shrink_after: Option<Duration>, // an instance beyond min_idle that has been idle for so long is closed
+}
+
+impl Default for ServerPool {
+    fn default() -> Self {
+        Self { min_idle: 1, max_instances: None, shrink_after: None }
+    }
+}
+
 pub struct Server<F: 'static> {
     name: NamedPipePath,
     buffer_allocator: &'static F,
@@ -30,9 +44,26 @@ pub struct Server<F: 'static> {
     pipes: Vec<ServerNamedPipeEvent<&'static F>>,
     new_event_sender: channel::Sender<Event>,
     connection_receiver: channel::Receiver<usize>,
-    error_receiver: channel::Receiver<WindowsError>,
-    interrupt_event: EventOwner,
+    error_receiver: channel::Receiver<Error>, // from waiting on the events of every pipe
+    finish_receiver: channel::Receiver<()>, // once a handed out pipe has ended
+    interrupt_event: Arc<EventOwner>, // shared with every ServerCloser
     grow_event: EventOwner,
+    finish_event: Arc<EventOwner>, // set by every handed out pipe once it has ended
+    pool: ServerPool,
+    thread: Option<JoinHandle<()>>, // joined on drop, the events go back to the pool only once nothing waits on them
+}
+
+// ends Server::incoming and Server::serve from another thread
+#[derive(Clone)]
+pub struct ServerCloser {
+    name: NamedPipePath,
+    interrupt_event: Arc<EventOwner>,
+}
+
+impl ServerCloser {
+    pub fn close(&self) -> Result<(), Error> {
+        self.interrupt_event.duplicate().set().on_pipe(&self.name, None)
+    }
 }
 
 impl<F: 'static> Server<F> {
@@ -41,17 +72,19 @@ impl<F: 'static> Server<F> {
         buffer_allocator: &'static F,
         windows_named_pipe_buffer_size: u32,
         client_default_timeout: Duration
-    ) -> WindowsResult<Self> {
-        let interrupt_event = EventManager::register()?;
-        let grow_event = EventManager::register()?;
+    ) -> Result<Self, Error> {
+        let interrupt_event = EventManager::register().on_pipe(&name, None)?;
+        let grow_event = EventManager::register().on_pipe(&name, None)?;
+        let finish_event = EventManager::register().on_pipe(&name, None)?;
         
-        let mut events = vec![interrupt_event, grow_event];
+        let mut events = vec![interrupt_event, grow_event, finish_event];
         
         let (new_event_sender, new_event_receiver) = channel::Channel::new().unwrap();
         let (connection_sender, connection_receiver) = channel::Channel::new().unwrap();
         let (error_sender, error_receiver) = channel::Channel::new().unwrap();
+        let (finish_sender, finish_receiver) = channel::Channel::new().unwrap();
         
-        new_thread(move || {
+        let thread = new_thread(move || {
             let non_pipe_events = events.len();
             let mut thread_interrupt = false;
             
@@ -65,15 +98,18 @@ impl<F: 'static> Server<F> {
                     else if events[i] == grow_event {
                         grow = true;
                     }
+                    else if events[i] == finish_event {
+                        let _ = finish_sender.send(());
+                    }
                     else {
-                        connection_sender.send(i - non_pipe_events);
+                        let _ = connection_sender.send(i - non_pipe_events); // fails only once the server is gone
                     }
                 }) {
-                    error_sender.send(error);
+                    let _ = error_sender.send(error);
                 }
                 
                 if grow {
-                    unsafe { new_event_receiver.raw_buffer(|new_events| events.append(new_events)); }
+                    unsafe { new_event_receiver.raw_buffer(|new_events| events.extend(new_events.drain(..))); }
                 }
             }
         });
@@ -87,37 +123,67 @@ impl<F: 'static> Server<F> {
             new_event_sender,
             connection_receiver,
             error_receiver,
-            interrupt_event: EventOwner(interrupt_event),
+            finish_receiver,
+            interrupt_event: Arc::new(EventOwner(interrupt_event)),
             grow_event: EventOwner(grow_event),
+            finish_event: Arc::new(EventOwner(finish_event)),
+            pool: ServerPool::default(),
+            thread: Some(thread),
         })
     }
     
-    pub fn create_pipe(
-        &mut self,
+    fn new_pipe(
+        &self,
+        index: usize,
         windows_named_pipe_buffer_size: Option<u32>,
         client_default_timeout: Option<Duration>,
-    ) -> WindowsResult<&mut ServerNamedPipeEvent<&'static F>> {
-        let event = EventManager::register()?;
-        let pipe = ServerNamedPipe::new(
+    ) -> Result<ServerNamedPipe<&'static F>, Error> {
+        let mut pipe = ServerNamedPipe::with_max_instances(
             &self.name,
             windows_named_pipe_buffer_size.unwrap_or(self.windows_named_pipe_buffer_size),
             client_default_timeout.unwrap_or(self.client_default_timeout),
+            self.pool.max_instances,
             LazyBuffer::Unbuffered(self.buffer_allocator),
-        )?;
+        ).map_err(|error| error.with_index(index))?;
+        
+        pipe.set_index(index);
+        
+        Ok(pipe)
+    }
+    
+    // fails with Busy once the pool has max_instances
+    pub fn create_pipe(
+        &mut self,
+        windows_named_pipe_buffer_size: Option<u32>,
+        client_default_timeout: Option<Duration>,
+    ) -> Result<&mut ServerNamedPipeEvent<&'static F>, Error> {
+        let index = self.pipes.len();
+        let pipe = self.new_pipe(index, windows_named_pipe_buffer_size, client_default_timeout)?;
+        let event = EventManager::register().on_pipe(&self.name, Some(index))?;
         
         self.pipes.push(ServerNamedPipeEvent(pipe, EventOwner(event)));
-        self.new_event_sender.send(event);
-        self.grow_event.duplicate().set()?;
+        let _ = self.new_event_sender.send(event);
+        self.grow_event.duplicate().set().on_pipe(&self.name, Some(index))?;
         
         Ok(self.pipes.last_mut().unwrap())
     }
     
+    // a new instance in place of a closed one, which keeps its event
+    fn reopen_pipe(&mut self, index: usize) -> Result<&mut ServerNamedPipeEvent<&'static F>, Error> {
+        let pipe = self.new_pipe(index, None, None)?;
+        let ServerNamedPipeEvent(closed, _) = &mut self.pipes[index];
+        
+        *closed = pipe;
+        
+        Ok(&mut self.pipes[index])
+    }
+    
     pub fn create_pipes(
         &mut self,
         windows_named_pipe_buffer_size: Option<u32>,
         client_default_timeout: Option<Duration>,
         count: usize,
-    ) -> WindowsResult<&mut [ServerNamedPipeEvent<&'static F>]> {
+    ) -> Result<&mut [ServerNamedPipeEvent<&'static F>], Error> {
         for _ in 0..count {
             self.create_pipe(windows_named_pipe_buffer_size, client_default_timeout)?;
         }
@@ -127,16 +193,30 @@ impl<F: 'static> Server<F> {
         Ok(&mut self.pipes[len - count..])
     }
     
+    // doubles the instances, starting with one, but never beyond max_instances of the pool
     pub fn grow(
         &mut self,
         windows_named_pipe_buffer_size: Option<u32>,
         client_default_timeout: Option<Duration>,
-    ) -> WindowsResult<&mut [ServerNamedPipeEvent<&'static F>]> {
-        self.create_pipes(windows_named_pipe_buffer_size, client_default_timeout, self.pipes.len().min(1))
+    ) -> Result<&mut [ServerNamedPipeEvent<&'static F>], Error> {
+        let mut instances = 0;
+        
+        for pipe in &mut self.pipes {
+            if !matches!(pipe.pipe_mut().update_status(), ServerNamedPipeStatus::Closed) {
+                instances += 1;
+            }
+        }
+        
+        let count = match self.pool.max_instances {
+            Some(max_instances) => instances.max(1).min((max_instances as usize).saturating_sub(instances)),
+            None => instances.max(1),
+        };
+        
+        self.create_pipes(windows_named_pipe_buffer_size, client_default_timeout, count)
     }
     
-    pub fn close(&self) -> WindowsResult<()> {
-        self.interrupt_event.duplicate().set()?;
+    pub fn close(&self) -> Result<(), Error> {
+        self.interrupt_event.duplicate().set().on_pipe(&self.name, None)?;
         
         for pipe in &self.pipes {
             pipe.pipe_ref().close()?;
@@ -145,15 +225,264 @@ impl<F: 'static> Server<F> {
         Ok(())
     }
     
+    pub fn closer(&self) -> ServerCloser {
+        ServerCloser { name: self.name.clone(), interrupt_event: self.interrupt_event.clone() }
+    }
+    
+    pub fn pool(&self) -> ServerPool {
+        self.pool
+    }
+    
+    // max_instances has to be set before the first pipe is created, on windows the first instance sets the limit of the name
+    pub fn set_pool(&mut self, pool: ServerPool) {
+        self.pool = ServerPool { min_idle: pool.min_idle.max(1), ..pool };
+    }
+    
     pub fn get_connected_pipes(&self) -> Vec<usize> {
         self.connection_receiver.receive_all()
     }
     
-    pub fn get_thread_errors(&self) -> Vec<WindowsError> {
-        self.error_receiver.receive_all()
+    pub fn get_thread_errors(&self) -> Vec<Error> {
+        self.error_receiver.receive_all().into_iter().map(|error| error.with_path(&self.name)).collect()
     }
     
     pub fn pipes(&mut self) -> &mut [ServerNamedPipeEvent<&'static F>] {
         &mut self.pipes
     }
+    
+    // connected pipes as clients come in, each started with a runtime from the given function
+    // iterating blocks the caller's thread, ends once the server is closed through a ServerCloser
+    pub fn incoming<E: NamedPipeRuntimeExecutor, R: FnMut() -> E>(&mut self, runtime: R) -> Incoming<'_, F, R> {
+        Incoming { server: self, runtime, shrink_at: None }
+    }
+    
+    // runs the handler for every client on a thread of its own until the server is closed through a ServerCloser
+    // then closes the server, which ends every connection, and returns once every handler has returned
+    // clients that leave before they are served are skipped, any other error ends it the same way
+    pub fn serve<E, R, H>(&mut self, runtime: R, handler: H) -> Result<(), Error>
+    where
+        F: Fn() -> NamedPipeBuffer,
+        E: NamedPipeRuntimeExecutor,
+        R: FnMut() -> E,
+        H: Fn(NamedPipe) + Sync,
+    {
+        thread::scope(|s| {
+            let mut result = Ok(());
+            
+            for pipe in self.incoming(runtime) {
+                match pipe {
+                    Ok(pipe) => {
+                        let handler = &handler;
+                        
+                        s.spawn(move || handler(pipe));
+                    }
+                    Err(error) if matches!(error.kind(), ErrorKind::Disconnected) => {}
+                    Err(error) => {
+                        result = Err(error);
+                        break;
+                    }
+                }
+            }
+            
+            // closed before the handlers are joined, so none of them is left waiting on a client
+            result.and(self.close())
+        })
+    }
+}
+
+impl<F> Drop for Server<F> {
+    fn drop(&mut self) {
+        let _ = self.interrupt_event.duplicate().set();
+        
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        let Self(thread) = &*self;
+        
+        thread.unpark();
+    }
+}
+
+// keeps instances listening and hands out every pipe that connects, see Server::incoming and ServerPool
+// an instance is listening again once the connection it handed out has ended
+pub struct Incoming<'a, F: 'static, R> {
+    server: &'a mut Server<F>,
+    runtime: R,
+    shrink_at: Option<Instant>, // when the next idle instance is due to be closed
+}
+
+impl<F: Fn() -> NamedPipeBuffer + 'static, E: NamedPipeRuntimeExecutor, R: FnMut() -> E> Incoming<'_, F, R> {
+    // makes sure enough instances are waiting for the next client, reusing those whose connection has ended first
+    // closes the instances beyond that which have been idle for too long
+    fn listen(&mut self) -> Result<(), Error> {
+        let ServerPool { min_idle, max_instances, shrink_after } = self.server.pool;
+        let mut listening = 0;
+        let mut instances = 0;
+        let mut closed = Vec::new();
+        
+        for pipe in &mut self.server.pipes {
+            let pipe = pipe.pipe_mut();
+            
+            if let ServerNamedPipeStatus::Disconnected(_) | ServerNamedPipeStatus::ThreadPanic(_) = pipe.update_status() {
+                pipe.reset(LazyBuffer::Unbuffered(self.server.buffer_allocator))?;
+            }
+            
+            match pipe.update_status() {
+                ServerNamedPipeStatus::Pending => listening += 1,
+                ServerNamedPipeStatus::Closed => continue,
+                _ => {}
+            }
+            
+            instances += 1;
+        }
+        
+        self.shrink_at = None;
+        
+        // idle instances are started before new ones are created, those left over are closed once they have been idle for too long
+        for (index, pipe) in self.server.pipes.iter_mut().enumerate() {
+            let event = pipe.event();
+            let pipe = pipe.pipe_mut();
+            
+            match pipe.update_status() {
+                ServerNamedPipeStatus::Idle if listening < min_idle => {
+                    pipe.start_connecting(event)?;
+                    listening += 1;
+                }
+                ServerNamedPipeStatus::Idle => if let Some(shrink_after) = shrink_after {
+                    let shrink_at = pipe.idle_since() + shrink_after;
+                    
+                    if shrink_at <= Instant::now() {
+                        pipe.retire()?;
+                        closed.push(index);
+                        instances -= 1;
+                    }
+                    else {
+                        self.shrink_at = Some(self.shrink_at.map_or(shrink_at, |next| next.min(shrink_at)));
+                    }
+                }
+                ServerNamedPipeStatus::Closed => closed.push(index),
+                _ => {}
+            }
+        }
+        
+        // clients wait for a connected instance to end once there are max_instances
+        let mut closed = closed.into_iter();
+        
+        while listening < min_idle && max_instances.is_none_or(|max_instances| instances < max_instances as usize) {
+            let pipe = match closed.next() {
+                Some(index) => self.server.reopen_pipe(index)?,
+                None => self.server.create_pipe(None, None)?,
+            };
+            let event = pipe.event();
+            
+            pipe.pipe_mut().start_connecting(event)?;
+            listening += 1;
+            instances += 1;
+        }
+        
+        Ok(())
+    }
+    
+    // the task is woken when a client connects, a handed out pipe ends or the server thread fails
+    // idle instances are only closed when it is polled
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<NamedPipe, Error>>> {
+        // listen takes care of the instances whose pipe has ended
+        let _ = unsafe {
+            self.server.finish_receiver.poll_raw_buffer_until(cx, |finished| {
+                finished.clear();
+                false
+            })
+        };
+        
+        let mut error = None;
+        
+        let failed = unsafe {
+            self.server.error_receiver.poll_raw_buffer_until(cx, |errors| {
+                error = errors.pop_front();
+                error.is_some()
+            })
+        };
+        
+        if let Poll::Ready(Ok(())) = failed {
+            return Poll::Ready(Some(Err(error.unwrap().with_path(&self.server.name))));
+        }
+        
+        if let Err(error) = self.listen() {
+            return Poll::Ready(Some(Err(error)));
+        }
+        
+        loop {
+            let mut index = None;
+            
+            let connected = unsafe {
+                self.server.connection_receiver.poll_raw_buffer_until(cx, |indexes| {
+                    index = indexes.pop_front();
+                    index.is_some()
+                })
+            };
+            
+            match connected {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => return Poll::Ready(None), // the server thread is gone
+                Poll::Pending => return Poll::Pending,
+            }
+            
+            let pipe = self.server.pipes[index.unwrap()].pipe_mut();
+            
+            if let Err(error) = pipe.notify_connection((self.runtime)()) {
+                return Poll::Ready(Some(Err(error)));
+            }
+            
+            // nothing to hand out if the instance was not waiting for a client
+            if let Some(pipe) = pipe.release(LazyBuffer::Unbuffered(self.server.buffer_allocator)) {
+                pipe.set_on_finish(self.server.finish_event.clone());
+                
+                // the next client must not end up at the instance that is connected already
+                // if that fails the error comes with the next poll, the pipe is connected either way
+                let _ = self.listen();
+                
+                return Poll::Ready(Some(Ok(pipe)));
+            }
+        }
+    }
+    
+    // the next connected pipe, None once the server is closed
+    pub async fn accept(&mut self) -> Option<Result<NamedPipe, Error>> {
+        std::future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+}
+
+impl<F: Fn() -> NamedPipeBuffer + 'static, E: NamedPipeRuntimeExecutor, R: FnMut() -> E> Iterator for Incoming<'_, F, R> {
+    type Item = Result<NamedPipe, Error>;
+    
+    // blocks until the next client connects
+    fn next(&mut self) -> Option<Self::Item> {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        
+        loop {
+            match (self.poll_next(&mut cx), self.shrink_at) {
+                (Poll::Ready(pipe), _) => return pipe,
+                // nothing wakes the thread once an idle instance is due to be closed
+                (Poll::Pending, Some(shrink_at)) => thread::park_timeout(shrink_at.saturating_duration_since(Instant::now())),
+                (Poll::Pending, None) => thread::park(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<F: Fn() -> NamedPipeBuffer + 'static, E: NamedPipeRuntimeExecutor, R: FnMut() -> E + Unpin> futures_core::Stream for Incoming<'_, F, R> {
+    type Item = Result<NamedPipe, Error>;
+    
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next(cx)
+    }
 }
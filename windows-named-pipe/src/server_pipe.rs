@@ -1,43 +1,27 @@
 
-use std::time::Duration;
+use std::{sync::{Arc, OnceLock}, thread::JoinHandle, time::{Duration, Instant}};
 
-use crate::utils::*;
-
-use windows::Win32::{
-    Foundation::{
-        CloseHandle,
-        ERROR_IO_PENDING,
-        ERROR_PIPE_CONNECTED,
-    },
-    Storage::FileSystem::{
-        FlushFileBuffers,
-        FILE_FLAG_OVERLAPPED,
-        PIPE_ACCESS_DUPLEX,
-    },
-    System::Pipes::{
-        ConnectNamedPipe,
-        CreateNamedPipeA,
-        DisconnectNamedPipe,
-        PIPE_READMODE_BYTE,
-        PIPE_TYPE_BYTE,
-        PIPE_UNLIMITED_INSTANCES,
-    },
-};
+use crate::{error::ErrorContext, handshake::*, sys, utils::*};
 
 pub enum ServerNamedPipeStatus {
     None, // only used internally
     Idle, // unconnected and not connecting
     Pending, // unconnected and connecting
     Connected(NamedPipe),
-    Disconnected,
+    Handshaking(JoinHandle<(NamedPipe, Result<Protocol, HandshakeError>)>), // connected, Connected once the handshake on its own thread succeeded
+    Released(Arc<OnceLock<DisconnectReason>>), // connected, but the pipe was handed out, the reason is set once it has ended
+    Disconnected(DisconnectReason), // until the instance is reset
     ThreadPanic(Box<dyn std::any::Any + Send + 'static>), // contains error if thread panics
+    Closed, // by the server to shrink its pool, the next instance it creates takes the place
 }
 
 pub struct ServerNamedPipe<F> {
-    handle: HANDLE,
-    overlapped: NonNull<OVERLAPPED>,
+    instance: sys::PipeInstance,
     buffer: Option<LazyBuffer<NamedPipeBuffer, F>>,
     status: ServerNamedPipeStatus,
+    path: NamedPipePath, // for errors
+    index: Option<usize>, // in the server, for errors
+    idle_since: Instant,
 }
 
 impl<F> ServerNamedPipe<F> {
@@ -46,47 +30,35 @@ impl<F> ServerNamedPipe<F> {
         windows_named_pipe_buffer_size: u32,
         client_default_timeout: Duration,
         pipe_buffer: LazyBuffer<NamedPipeBuffer, F>
-    ) -> WindowsResult<Self> {
-        unsafe {
-            let handle = CreateNamedPipeA(
-                pipe_name.as_pcstr(),
-                PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED,
-                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE,
-                PIPE_UNLIMITED_INSTANCES,
-                windows_named_pipe_buffer_size,
-                windows_named_pipe_buffer_size,
-                client_default_timeout.as_millis() as u32,
-                None
-            )?;
-            
-            Ok(Self {
-                handle,
-                overlapped: init_zero(alloc()),
-                buffer: Some(pipe_buffer),
-                status: ServerNamedPipeStatus::Idle,
-            })
-        }
+    ) -> Result<Self, Error> {
+        Self::with_max_instances(pipe_name, windows_named_pipe_buffer_size, client_default_timeout, None, pipe_buffer)
     }
     
-    fn connect(&self, event: Event) -> WindowsResult<bool> {
-        unsafe {
-            self.overlapped.write(OVERLAPPED { hEvent: event.handle(), ..Default::default() });
-            
-            match ConnectNamedPipe(self.handle, Some(self.overlapped.as_ptr())) {
-                Ok(()) => Ok(true),
-                Err(error) => match GetLastError() {
-                    ERROR_IO_PENDING => Ok(false),
-                    ERROR_PIPE_CONNECTED => Ok(true),
-                    _ => Err(error),
-                }
-            }
-        }
+    pub fn with_max_instances(
+        pipe_name: &NamedPipePath,
+        windows_named_pipe_buffer_size: u32,
+        client_default_timeout: Duration,
+        max_instances: Option<u32>, // of every instance of the name, fails with Busy beyond it
+        pipe_buffer: LazyBuffer<NamedPipeBuffer, F>
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            instance: sys::PipeInstance::new(pipe_name, windows_named_pipe_buffer_size, client_default_timeout, max_instances).on_pipe(pipe_name, None)?,
+            buffer: Some(pipe_buffer),
+            status: ServerNamedPipeStatus::Idle,
+            path: pipe_name.clone(),
+            index: None,
+            idle_since: Instant::now(),
+        })
     }
     
-    pub fn start_connecting(&mut self, event: Event) -> WindowsResult<()> {
+    pub(crate) fn set_index(&mut self, index: usize) {
+        self.index = Some(index);
+    }
+    
+    pub fn start_connecting(&mut self, event: Event) -> Result<(), Error> {
         if let &ServerNamedPipeStatus::Idle = &self.status {
-            if self.connect(event)? {
-                event.set()?;
+            if self.instance.connect(event).on_pipe(&self.path, self.index)? {
+                event.set().on_pipe(&self.path, self.index)?;
             }
             
             self.status = ServerNamedPipeStatus::Pending;
@@ -95,24 +67,86 @@ impl<F> ServerNamedPipe<F> {
         Ok(())
     }
     
-    pub fn notify_connection(&mut self, runtime: impl NamedPipeRuntimeExecutor) -> WindowsResult<()> where F: FnOnce() -> NamedPipeBuffer {
+    pub fn notify_connection(&mut self, runtime: impl NamedPipeRuntimeExecutor) -> Result<(), Error> where F: FnOnce() -> NamedPipeBuffer {
         if let &ServerNamedPipeStatus::Pending = &self.status {
-            self.status = ServerNamedPipeStatus::Connected(NamedPipe::new(self.handle, self.buffer.take().unwrap().buffer(), runtime)?);
+            let transport = PipeTransport::new(self.instance.connection().on_pipe(&self.path, self.index)?).on_pipe(&self.path, self.index)?;
+            let pipe = NamedPipe::new(transport, self.buffer.take().unwrap().buffer(), runtime).on_pipe(&self.path, self.index)?;
+            
+            self.status = ServerNamedPipeStatus::Connected(pipe);
         }
         
         Ok(())
     }
     
+    // the handshake runs on a thread of its own, so a slow client holds up nobody else, see ServerNamedPipeStatus::Handshaking
+    // a failed handshake ends the connection, update_status reports it as Disconnected with the reason
+    pub fn notify_connection_with_handshake(&mut self, runtime: impl NamedPipeRuntimeExecutor, handshake: &Handshake) -> Result<(), Error> where F: FnOnce() -> NamedPipeBuffer {
+        self.notify_connection(runtime)?;
+        
+        if let ServerNamedPipeStatus::Connected(_) = &self.status {
+            let ServerNamedPipeStatus::Connected(mut pipe) = std::mem::replace(&mut self.status, ServerNamedPipeStatus::None) else {
+                unreachable!();
+            };
+            
+            let handshake = *handshake;
+            
+            self.status = ServerNamedPipeStatus::Handshaking(new_thread(move || {
+                let result = pipe.handshake(&handshake);
+                
+                (pipe, result)
+            }));
+        }
+        
+        Ok(())
+    }
+    
+    fn handshake_done(&mut self, handshake: JoinHandle<(NamedPipe, Result<Protocol, HandshakeError>)>) -> ServerNamedPipeStatus {
+        match handshake.join() {
+            Ok((pipe, Ok(_))) => ServerNamedPipeStatus::Connected(pipe),
+            Ok((pipe, Err(error))) => {
+                let _ = pipe.interrupt();
+                
+                match pipe.join() {
+                    Ok(buffer) => {
+                        self.buffer.replace(LazyBuffer::Buffered(buffer));
+                        ServerNamedPipeStatus::Disconnected(DisconnectReason::Handshake(error.to_string()))
+                    }
+                    Err(error) => ServerNamedPipeStatus::ThreadPanic(error),
+                }
+            }
+            Err(error) => ServerNamedPipeStatus::ThreadPanic(error),
+        }
+    }
+    
+    // hands out the connected pipe, which takes its buffer along, so the instance gets a new one for its next connection
+    pub fn release(&mut self, buffer: LazyBuffer<NamedPipeBuffer, F>) -> Option<NamedPipe> {
+        match std::mem::replace(&mut self.status, ServerNamedPipeStatus::None) {
+            ServerNamedPipeStatus::Connected(pipe) => {
+                self.buffer.replace(buffer);
+                self.status = ServerNamedPipeStatus::Released(pipe.shared_disconnect_reason());
+                
+                Some(pipe)
+            }
+            status => {
+                self.status = status;
+                
+                None
+            }
+        }
+    }
+    
     pub fn update_status(&mut self) -> &ServerNamedPipeStatus {
         self.status = match std::mem::replace(&mut self.status, ServerNamedPipeStatus::None) {
             ServerNamedPipeStatus::None => unreachable!(),
-            ServerNamedPipeStatus::Connected(pipe) if pipe.is_finished() => match pipe.join() {
-                Ok(buffer) => {
+            ServerNamedPipeStatus::Connected(pipe) if pipe.is_finished() => match (pipe.disconnect_reason(), pipe.join()) {
+                (reason, Ok(buffer)) => {
                     self.buffer.replace(LazyBuffer::Buffered(buffer));
-                    ServerNamedPipeStatus::Disconnected
+                    ServerNamedPipeStatus::Disconnected(reason.unwrap_or(DisconnectReason::Closed))
                 }
-                Err(error) => ServerNamedPipeStatus::ThreadPanic(error),
+                (_, Err(error)) => ServerNamedPipeStatus::ThreadPanic(error),
             }
+            ServerNamedPipeStatus::Released(reason) if reason.get().is_some() => ServerNamedPipeStatus::Disconnected(reason.get().unwrap().clone()),
+            ServerNamedPipeStatus::Handshaking(handshake) if handshake.is_finished() => self.handshake_done(handshake),
             status => status,
         };
         
@@ -120,32 +154,59 @@ impl<F> ServerNamedPipe<F> {
     }
     
     // does not update status
-    pub fn disconnect(&self) -> WindowsResult<()> {
-        if let ServerNamedPipeStatus::Connected(_) = &self.status {
-            unsafe {
-                FlushFileBuffers(self.handle)?;
-                
-                DisconnectNamedPipe(self.handle)
-            }
+    pub fn disconnect(&self) -> Result<(), Error> {
+        if let ServerNamedPipeStatus::Connected(_) | ServerNamedPipeStatus::Handshaking(_) | ServerNamedPipeStatus::Released(_) = &self.status {
+            self.instance.disconnect().on_pipe(&self.path, self.index)
         }
         else {
             Ok(())
         }
     }
     
-    pub fn close(&self) -> WindowsResult<()> {
+    // disconnects the instance once its connection has ended, so it can start connecting again
+    // the buffer the connection gave back is kept, the given one is only taken if the runtime panicked with it
+    pub fn reset(&mut self, buffer: LazyBuffer<NamedPipeBuffer, F>) -> Result<(), Error> {
+        if let ServerNamedPipeStatus::Disconnected(_) | ServerNamedPipeStatus::ThreadPanic(_) = &self.status {
+            match self.instance.disconnect() {
+                Err(error) if !sys::is_closed(&error) => return Err(error).on_pipe(&self.path, self.index),
+                _ => {}
+            }
+            
+            self.buffer.get_or_insert(buffer);
+            self.status = ServerNamedPipeStatus::Idle;
+            self.idle_since = Instant::now();
+        }
+        
+        Ok(())
+    }
+    
+    pub(crate) fn idle_since(&self) -> Instant {
+        self.idle_since
+    }
+    
+    // closes an instance nobody is waiting on, so it no longer counts towards the limit
+    pub(crate) fn retire(&mut self) -> Result<(), Error> {
+        if let ServerNamedPipeStatus::Idle = &self.status {
+            self.instance.close().on_pipe(&self.path, self.index)?;
+            self.status = ServerNamedPipeStatus::Closed;
+        }
+        
+        Ok(())
+    }
+    
+    pub fn close(&self) -> Result<(), Error> {
+        if let ServerNamedPipeStatus::Closed = &self.status {
+            return Ok(());
+        }
+        
         self.disconnect()?;
         
-        unsafe { CloseHandle(self.handle) }
+        self.instance.close().on_pipe(&self.path, self.index)
     }
     
+    /// # Safety
+    /// the instance must have a buffer again by the time a client connects
     pub unsafe fn buffer(&mut self) -> &mut Option<LazyBuffer<NamedPipeBuffer, F>> {
         &mut self.buffer
     }
 }
-
-impl<F> Drop for ServerNamedPipe<F> {
-    fn drop(&mut self) {
-        unsafe { dealloc(self.overlapped) }
-    }
-}
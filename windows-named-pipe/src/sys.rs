@@ -0,0 +1,26 @@
+#[cfg(windows)]
+mod win32;
+
+#[cfg(windows)]
+pub use win32::*;
+
+#[cfg(unix)]
+mod unix;
+
+#[cfg(unix)]
+pub use unix::*;
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub enum PipeWait {
+    Forever,
+    Default, // the default timeout of the pipe
+    Timeout(Duration),
+}
+
+// rounded up, so a short timeout still blocks instead of polling
+fn millis(timeout: Duration) -> u128 {
+    timeout.as_nanos().div_ceil(1_000_000)
+}
+
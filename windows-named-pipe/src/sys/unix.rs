@@ -0,0 +1,843 @@
+use std::{
+    cell::Cell,
+    fs::{File, TryLockError},
+    io::{ErrorKind, Write},
+    mem::ManuallyDrop,
+    net::Shutdown,
+    os::{
+        fd::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak, atomic::{AtomicUsize, Ordering}},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+pub use std::io::Error;
+
+// defaults to the error of the os, the transport reports the error of the crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+use crate::{client::NamedPipeCheck, error::Error as PipeError, utils::*};
+
+use super::PipeWait;
+
+pub type RawHandle = RawFd;
+
+// the wait of PipeWait::Default if the server gave none, like on windows
+const DEFAULT_WAIT: Duration = Duration::from_millis(50);
+
+pub fn null_handle() -> RawHandle {
+    -1
+}
+
+fn cvt(result: libc::c_int) -> Result<libc::c_int> {
+    if result == -1 { Err(Error::last_os_error()) } else { Ok(result) }
+}
+
+fn cvt_size(result: libc::ssize_t) -> Result<usize> {
+    if result == -1 { Err(Error::last_os_error()) } else { Ok(result as usize) }
+}
+
+fn retry<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    loop {
+        match f() {
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+// level triggered, so a manual reset event stays ready until it is reset
+struct Epoll {
+    fd: RawFd,
+    len: usize, // number of registered fds
+}
+
+impl Epoll {
+    fn new() -> Result<Self> {
+        Ok(Self { fd: cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?, len: 0 })
+    }
+    
+    fn control(&self, op: libc::c_int, fd: RawFd, interest: u32, token: u64) -> Result<()> {
+        let mut event = libc::epoll_event { events: interest, u64: token };
+        
+        cvt(unsafe { libc::epoll_ctl(self.fd, op, fd, &mut event) }).map(|_| ())
+    }
+    
+    fn add(&mut self, fd: RawFd, interest: u32, token: u64) -> Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, interest, token)?;
+        self.len += 1;
+        
+        Ok(())
+    }
+    
+    fn modify(&self, fd: RawFd, interest: u32, token: u64) -> Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, interest, token)
+    }
+    
+    fn delete(&mut self, fd: RawFd) -> Result<()> {
+        self.control(libc::EPOLL_CTL_DEL, fd, 0, 0)?;
+        self.len -= 1;
+        
+        Ok(())
+    }
+    
+    // returns the tokens and readiness of every ready fd
+    fn wait(&self, timeout: libc::c_int) -> Result<Vec<(u64, u32)>> {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; self.len.max(1)];
+        
+        let count = retry(|| cvt(unsafe {
+            libc::epoll_wait(self.fd, events.as_mut_ptr(), events.len() as libc::c_int, timeout)
+        }))?;
+        
+        Ok(events[..count as usize].iter().map(|event| (event.u64, event.events)).collect())
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
+
+unsafe fn stream(fd: RawFd) -> ManuallyDrop<UnixStream> {
+    ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
+pub fn create_event() -> Result<RawHandle> {
+    cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })
+}
+
+pub unsafe fn set_event(handle: RawHandle) -> Result<()> {
+    let one = 1u64;
+    
+    match cvt_size(unsafe { libc::write(handle, &one as *const u64 as *const libc::c_void, size_of::<u64>()) }) {
+        Err(error) if error.kind() != ErrorKind::WouldBlock => Err(error),
+        _ => Ok(()), // a saturated counter is still signalled
+    }
+}
+
+pub unsafe fn reset_event(handle: RawHandle) -> Result<()> {
+    let mut counter = 0u64;
+    
+    match cvt_size(unsafe { libc::read(handle, &mut counter as *mut u64 as *mut libc::c_void, size_of::<u64>()) }) {
+        Err(error) if error.kind() != ErrorKind::WouldBlock => Err(error),
+        _ => Ok(()), // already unsignalled
+    }
+}
+
+pub unsafe fn event_signal(handle: RawHandle) -> Result<bool> {
+    let mut fd = libc::pollfd { fd: handle, events: libc::POLLIN, revents: 0 };
+    
+    retry(|| cvt(unsafe { libc::poll(&mut fd, 1, 0) }))?;
+    
+    if fd.revents & libc::POLLNVAL != 0 {
+        Err(Error::from_raw_os_error(libc::EBADF))
+    }
+    else {
+        Ok(fd.revents != 0)
+    }
+}
+
+// counts the handles closed, a wait set registered before a close may hold an fd that was reused since
+static CLOSED_HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+pub unsafe fn close_handle(handle: RawHandle) -> Result<()> {
+    CLOSED_HANDLES.fetch_add(1, Ordering::Relaxed);
+    
+    cvt(unsafe { libc::close(handle) }).map(|_| ())
+}
+
+pub fn broken_pipe() -> Error {
+    Error::from_raw_os_error(libc::EPIPE)
+}
+
+// the other end is gone, as opposed to something having gone wrong
+pub fn is_closed(error: &Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EPIPE | libc::ECONNRESET | libc::ENOTCONN))
+}
+
+// the os errors that have a kind of their own
+pub fn error_kind(error: Error) -> crate::error::ErrorKind {
+    use crate::error::ErrorKind as Kind;
+    
+    match error.kind() {
+        _ if is_closed(&error) => Kind::Disconnected,
+        ErrorKind::WouldBlock => Kind::WouldBlock,
+        ErrorKind::TimedOut => Kind::Timeout,
+        ErrorKind::NotFound | ErrorKind::ConnectionRefused => Kind::NotFound, // refused means a socket file is left but nothing listens
+        ErrorKind::ResourceBusy => Kind::Busy,
+        _ => Kind::Os(error),
+    }
+}
+
+// the transport owns its handle on unix, so it gets a duplicate
+pub unsafe fn share_handle(handle: RawHandle) -> Result<RawHandle> {
+    cvt(unsafe { libc::fcntl(handle, libc::F_DUPFD_CLOEXEC, 0) })
+}
+
+// milliseconds left until the deadline, in the form epoll_wait takes
+fn epoll_timeout(deadline: Option<Instant>) -> libc::c_int {
+    deadline.map_or(-1, |deadline| {
+        super::millis(deadline.saturating_duration_since(Instant::now())).min(libc::c_int::MAX as u128) as libc::c_int
+    })
+}
+
+// the epoll of the last wait on a thread, which mostly waits on the same events again
+// kept registered in between, only events that were added or removed since are registered anew
+struct WaitSet {
+    epoll: Epoll,
+    handles: Vec<RawHandle>, // registered in epoll, their fd is the token
+    closed_handles: usize, // CLOSED_HANDLES when the set was created
+}
+
+thread_local! {
+    static WAIT_SET: Cell<Option<WaitSet>> = const { Cell::new(None) };
+}
+
+impl WaitSet {
+    fn new() -> Result<Self> {
+        Ok(Self { epoll: Epoll::new()?, handles: Vec::new(), closed_handles: CLOSED_HANDLES.load(Ordering::Relaxed) })
+    }
+    
+    // fails if a registered handle was closed in the meantime
+    fn register(&mut self, handle_slice: &[RawHandle]) -> Result<()> {
+        if self.handles == handle_slice {
+            return Ok(());
+        }
+        
+        for &handle in &self.handles.clone() {
+            if !handle_slice.contains(&handle) {
+                self.epoll.delete(handle)?;
+                self.handles.retain(|&registered| registered != handle);
+            }
+        }
+        
+        for &handle in handle_slice {
+            if !self.handles.contains(&handle) {
+                self.epoll.add(handle, libc::EPOLLIN as u32, handle as u64)?;
+                self.handles.push(handle);
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+// returns true if nothing was signalled before the timeout
+pub unsafe fn wait_handles(handle_slice: &[RawHandle], timeout: Option<Duration>, mut f: impl FnMut(usize)) -> Result<bool> {
+    if handle_slice.is_empty() {
+        return Err(Error::from_raw_os_error(libc::EINVAL)); // nothing could ever wake us up
+    }
+    
+    // taken out for the wait, so a wait from within f gets an epoll of its own
+    let mut wait_set = match WAIT_SET.take() {
+        Some(wait_set) if wait_set.closed_handles == CLOSED_HANDLES.load(Ordering::Relaxed) => wait_set,
+        _ => WaitSet::new()?,
+    };
+    
+    if wait_set.register(handle_slice).is_err() {
+        wait_set = WaitSet::new()?;
+        wait_set.register(handle_slice)?;
+    }
+    
+    let mut timeout = epoll_timeout(timeout.and_then(|timeout| Instant::now().checked_add(timeout)));
+    let mut timed_out = true;
+    
+    // keep draining until nothing is signalled, like WaitForMultipleObjects with a zero timeout
+    loop {
+        let mut indices: Vec<_> = wait_set.epoll.wait(timeout)?.into_iter()
+            .filter_map(|(token, _)| handle_slice.iter().position(|&handle| handle as u64 == token))
+            .collect();
+        
+        if indices.is_empty() {
+            break;
+        }
+        
+        timed_out = false;
+        
+        // lowest index first, which is the order WaitForMultipleObjects reports in
+        indices.sort_unstable();
+        
+        for index in indices {
+            unsafe { reset_event(handle_slice[index])?; }
+            f(index);
+        }
+        
+        timeout = 0;
+    }
+    
+    WAIT_SET.set(Some(wait_set));
+    
+    Ok(timed_out)
+}
+
+// the socket is moved here while no instance waits for a client, so clients see the pipe as busy like on windows
+// a client that connects right before it is moved waits in the backlog until an instance waits again
+fn busy_path(path: &Path) -> PathBuf {
+    let mut busy_path = path.as_os_str().to_owned();
+    
+    busy_path.push(".busy");
+    busy_path.into()
+}
+
+// locked by the server that owns the name for as long as it lives, so a dead server is told apart from a live one without connecting to it
+// it holds the default timeout of the server for clients, the file is left in place, removing it would race with another server locking it
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    
+    lock_path.push(".lock");
+    lock_path.into()
+}
+
+fn lock_name(path: &Path, default_timeout: Duration) -> Result<File> {
+    let mut lock = File::options().create(true).truncate(false).write(true).open(lock_path(path))?;
+    
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Err(ErrorKind::AddrInUse.into()),
+        Err(TryLockError::Error(error)) => return Err(error),
+    }
+    
+    lock.set_len(0)?;
+    write!(lock, "{}", default_timeout.as_millis())?;
+    
+    Ok(lock)
+}
+
+// a zero or unreadable timeout falls back to DEFAULT_WAIT, like a zero one does on windows
+fn default_timeout(path: &Path) -> Duration {
+    std::fs::read_to_string(lock_path(path)).ok()
+        .and_then(|millis| millis.parse().ok())
+        .map(Duration::from_millis)
+        .filter(|timeout| !timeout.is_zero())
+        .unwrap_or(DEFAULT_WAIT)
+}
+
+fn is_socket(path: &Path) -> Result<bool> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.file_type().is_socket()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+struct Listener {
+    listener: UnixListener,
+    path: PathBuf,
+    max_instances: Option<u32>, // of the first instance, like on windows
+    waiting: Mutex<usize>, // instances waiting for a client
+    _lock: File, // unlocked once the socket files are removed
+}
+
+impl Listener {
+    fn add_waiting(&self, waiting: bool) {
+        let mut count = self.waiting.lock().unwrap();
+        
+        if waiting {
+            *count += 1;
+            
+            if *count == 1 {
+                let _ = std::fs::rename(busy_path(&self.path), &self.path);
+            }
+        }
+        else {
+            *count -= 1;
+            
+            if *count == 0 {
+                let _ = std::fs::rename(&self.path, busy_path(&self.path));
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(busy_path(&self.path));
+    }
+}
+
+// every instance of a pipe shares one listening socket, like instances of a windows pipe share one name
+static LISTENERS: Mutex<Vec<Weak<Listener>>> = Mutex::new(Vec::new());
+
+fn listener(pipe_name: &NamedPipePath, default_timeout: Duration, max_instances: Option<u32>) -> Result<Arc<Listener>> {
+    let path = pipe_name.as_path();
+    let mut listeners = LISTENERS.lock().unwrap();
+    
+    listeners.retain(|listener| listener.strong_count() > 0);
+    
+    if let Some(listener) = listeners.iter().filter_map(Weak::upgrade).find(|listener| listener.path == path) {
+        return Ok(listener);
+    }
+    
+    // bound under a temporary name and moved into place, a client finding the socket between bind and listen would be refused
+    let mut temp_path = path.as_os_str().to_owned();
+    
+    temp_path.push(format!(".{}", std::process::id()));
+    
+    let temp_path = PathBuf::from(temp_path);
+    
+    // a live server keeps its name, busy or not, the sockets of a dead one are replaced
+    let lock = lock_name(path, default_timeout)?;
+    let _ = std::fs::remove_file(busy_path(path));
+    let _ = std::fs::remove_file(&temp_path); // left behind by a dead process with the same id
+    let listener = UnixListener::bind(&temp_path)?;
+    
+    if let Err(error) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        
+        return Err(error);
+    }
+    
+    listener.set_nonblocking(true)?;
+    
+    let listener = Arc::new(Listener { listener, path: path.to_owned(), max_instances, waiting: Mutex::new(0), _lock: lock });
+    
+    listeners.push(Arc::downgrade(&listener));
+    
+    Ok(listener)
+}
+
+fn set_buffer_size(fd: RawFd, buffer_size: u32) -> Result<()> {
+    let size = buffer_size.min(libc::c_int::MAX as u32) as libc::c_int;
+    
+    for option in [libc::SO_SNDBUF, libc::SO_RCVBUF] {
+        cvt(unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &size as *const libc::c_int as *const libc::c_void,
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })?;
+    }
+    
+    Ok(())
+}
+
+struct Acceptor {
+    listener: Arc<Listener>,
+    buffer_size: u32,
+    accepted: Mutex<Option<Result<RawFd>>>,
+    cancel: RawFd,
+    waiting: Mutex<bool>, // for a client, counted by the listener
+}
+
+impl Acceptor {
+    fn set_waiting(&self, waiting: bool) {
+        let mut was_waiting = self.waiting.lock().unwrap();
+        
+        if *was_waiting != waiting {
+            *was_waiting = waiting;
+            self.listener.add_waiting(waiting);
+        }
+    }
+    
+    // returns true if a client has been accepted
+    fn accept(&self) -> Result<bool> {
+        match self.listener.listener.accept() {
+            Ok((stream, _)) => {
+                self.set_waiting(false);
+                
+                let fd = stream.into_raw_fd();
+                let result = set_buffer_size(fd, self.buffer_size).map(|_| fd);
+                
+                if result.is_err() {
+                    unsafe { libc::close(fd); }
+                }
+                
+                self.accepted.lock().unwrap().replace(result);
+                
+                Ok(true)
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+    
+    fn accept_blocking(&self) -> Result<bool> {
+        const CANCEL: u64 = 0;
+        const LISTENER: u64 = 1;
+        
+        let mut epoll = Epoll::new()?;
+        
+        epoll.add(self.cancel, libc::EPOLLIN as u32, CANCEL)?;
+        epoll.add(self.listener.listener.as_raw_fd(), libc::EPOLLIN as u32, LISTENER)?;
+        
+        loop {
+            let ready = epoll.wait(-1)?;
+            
+            if ready.iter().any(|&(token, _)| token == CANCEL) {
+                return Ok(false);
+            }
+            
+            if self.accept()? {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Drop for Acceptor {
+    fn drop(&mut self) {
+        self.set_waiting(false);
+        
+        unsafe {
+            libc::close(self.cancel);
+            
+            if let Some(Ok(fd)) = self.accepted.get_mut().unwrap().take() {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+pub struct PipeInstance {
+    acceptor: Mutex<Option<Arc<Acceptor>>>,
+    connection: Mutex<Option<RawFd>>, // duplicate of the connected socket, kept for disconnecting
+    connecting: Mutex<Option<JoinHandle<()>>>, // joined on close, so the socket file is gone once the last instance is closed
+}
+
+impl PipeInstance {
+    pub fn new(pipe_name: &NamedPipePath, buffer_size: u32, default_timeout: Duration, max_instances: Option<u32>) -> Result<Self> {
+        let listener = listener(pipe_name, default_timeout, max_instances)?;
+        
+        // every acceptor holds the listener, the timeout and limit of the first instance hold for the others
+        if listener.max_instances.is_some_and(|max_instances| Arc::strong_count(&listener) > max_instances as usize) {
+            return Err(ErrorKind::ResourceBusy.into());
+        }
+        
+        let acceptor = Acceptor {
+            listener,
+            buffer_size,
+            accepted: Mutex::new(None),
+            cancel: create_event()?,
+            waiting: Mutex::new(false),
+        };
+        
+        // a new instance takes clients before it is connecting, like on windows
+        acceptor.set_waiting(true);
+        
+        Ok(Self {
+            acceptor: Mutex::new(Some(Arc::new(acceptor))),
+            connection: Mutex::new(None),
+            connecting: Mutex::new(None),
+        })
+    }
+    
+    fn acceptor(&self) -> Result<Arc<Acceptor>> {
+        self.acceptor.lock().unwrap().clone().ok_or_else(|| Error::from_raw_os_error(libc::EBADF))
+    }
+    
+    // returns true if a client is already connected
+    pub fn connect(&self, event: Event) -> Result<bool> {
+        let acceptor = self.acceptor()?;
+        
+        // the thread of an earlier connect has accepted its client already, so this does not block
+        if let Some(thread) = self.connecting.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+        
+        acceptor.set_waiting(true);
+        
+        if acceptor.accept()? {
+            return Ok(true);
+        }
+        
+        // emulates an overlapped ConnectNamedPipe by signalling the event from another thread
+        let thread = new_thread(move || {
+            match acceptor.accept_blocking() {
+                Ok(false) => acceptor.set_waiting(false),
+                Ok(true) => { let _ = event.set(); }
+                Err(error) => {
+                    acceptor.set_waiting(false);
+                    acceptor.accepted.lock().unwrap().replace(Err(error));
+                    let _ = event.set();
+                }
+            }
+        });
+        
+        *self.connecting.lock().unwrap() = Some(thread);
+        
+        Ok(false)
+    }
+    
+    // handle of the connected client, owned by the caller
+    pub fn connection(&self) -> Result<RawHandle> {
+        let fd = self.acceptor()?.accepted.lock().unwrap().take()
+            .unwrap_or_else(|| Err(Error::from(ErrorKind::NotConnected)))?;
+        
+        match unsafe { share_handle(fd) } {
+            Ok(connection) => {
+                if let Some(connection) = self.connection.lock().unwrap().replace(connection) {
+                    unsafe { libc::close(connection); }
+                }
+                
+                Ok(fd)
+            }
+            Err(error) => {
+                unsafe { libc::close(fd); }
+                
+                Err(error)
+            }
+        }
+    }
+    
+    pub fn disconnect(&self) -> Result<()> {
+        if let Some(connection) = self.connection.lock().unwrap().take() {
+            let result = unsafe { stream(connection) }.shutdown(Shutdown::Both);
+            
+            unsafe { close_handle(connection)?; }
+            
+            match result {
+                Err(error) if error.kind() != ErrorKind::NotConnected => Err(error)?,
+                _ => {}
+            }
+        }
+        
+        Ok(())
+    }
+    
+    pub fn close(&self) -> Result<()> {
+        if let Some(acceptor) = self.acceptor.lock().unwrap().take() {
+            unsafe { set_event(acceptor.cancel)?; }
+        }
+        
+        if let Some(thread) = self.connecting.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+        
+        Ok(())
+    }
+}
+
+impl Drop for PipeInstance {
+    fn drop(&mut self) {
+        let _ = self.close();
+        
+        if let Some(connection) = self.connection.get_mut().unwrap().take() {
+            unsafe { libc::close(connection); }
+        }
+    }
+}
+
+pub fn check_pipe(pipe_name: &NamedPipePath) -> Result<NamedPipeCheck> {
+    if is_socket(pipe_name.as_path())? {
+        Ok(NamedPipeCheck::Available)
+    }
+    else if is_socket(&busy_path(pipe_name.as_path()))? {
+        Ok(NamedPipeCheck::Busy)
+    }
+    else {
+        Ok(NamedPipeCheck::Unavailable)
+    }
+}
+
+pub fn wait_pipe(pipe_name: &NamedPipePath, wait: PipeWait) -> Result<Option<RawHandle>> {
+    let deadline = match wait {
+        PipeWait::Forever => None,
+        PipeWait::Default => Instant::now().checked_add(default_timeout(pipe_name.as_path())),
+        PipeWait::Timeout(timeout) => Instant::now().checked_add(timeout),
+    };
+    
+    loop {
+        match UnixStream::connect(pipe_name.as_path()) {
+            Ok(stream) => return Ok(Some(stream.into_raw_fd())),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {} // backlog is full, all instances are busy
+            Err(error) if error.kind() == ErrorKind::NotFound && is_socket(&busy_path(pipe_name.as_path()))? => {}
+            Err(error) => return Err(error),
+        }
+        
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(None);
+        }
+        
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+const SOCKET: u64 = u64::MAX; // epoll token of the socket, events use their fd
+
+pub struct PipeTransport {
+    fd: RawFd,
+    epoll: Epoll,
+    events: Vec<RawFd>, // events registered in epoll
+    interest: u32, // readiness of the socket registered in epoll
+    read: Option<NonNull<[u8]>>,
+    write: Option<NonNull<[u8]>>,
+}
+
+unsafe impl Send for PipeTransport {}
+
+impl PipeTransport {
+    // the transport owns the socket, any connected stream socket works including a socketpair
+    pub fn new(handle: RawHandle) -> Result<Self, PipeError> {
+        let epoll = match Epoll::new() {
+            Ok(epoll) => epoll,
+            Err(error) => {
+                unsafe { libc::close(handle); }
+                
+                return Err(error.into());
+            }
+        };
+        
+        let transport = Self { fd: handle, epoll, events: Vec::new(), interest: 0, read: None, write: None };
+        
+        unsafe { stream(handle) }.set_nonblocking(true)?;
+        
+        Ok(transport)
+    }
+    
+    pub fn pair() -> Result<(Self, Self), PipeError> {
+        let (a, b) = UnixStream::pair()?;
+        
+        Ok((Self::new(a.into_raw_fd())?, Self::new(b.into_raw_fd())?))
+    }
+    
+    fn recv(&self, buffer: NonNull<[u8]>) -> Result<usize> {
+        match cvt_size(unsafe { libc::recv(self.fd, buffer.as_ptr() as *mut libc::c_void, buffer.len(), 0) }) {
+            Ok(0) if !buffer.is_empty() => Err(broken_pipe()), // peer closed
+            result => result,
+        }
+    }
+    
+    fn send(&self, buffer: NonNull<[u8]>) -> Result<usize> {
+        cvt_size(unsafe { libc::send(self.fd, buffer.as_ptr() as *const libc::c_void, buffer.len(), libc::MSG_NOSIGNAL) })
+    }
+    
+    // brings the epoll registrations in line with the events and pending operations
+    fn register(&mut self, events: &[Event]) -> Result<()> {
+        let handles: Vec<_> = events.iter().map(|&event| unsafe { event.handle() }).collect();
+        
+        for &handle in &self.events.clone() {
+            if !handles.contains(&handle) {
+                self.epoll.delete(handle)?;
+                self.events.retain(|&event| event != handle);
+            }
+        }
+        
+        for handle in handles {
+            if !self.events.contains(&handle) {
+                self.epoll.add(handle, libc::EPOLLIN as u32, handle as u64)?;
+                self.events.push(handle);
+            }
+        }
+        
+        let mut interest = 0;
+        
+        if self.read.is_some() { interest |= libc::EPOLLIN as u32; }
+        if self.write.is_some() { interest |= libc::EPOLLOUT as u32; }
+        
+        // hang ups are always reported, so an idle socket must not be registered at all
+        match (self.interest, interest) {
+            (0, 0) => {}
+            (0, _) => self.epoll.add(self.fd, interest, SOCKET)?,
+            (_, 0) => self.epoll.delete(self.fd)?,
+            (old, new) if old != new => self.epoll.modify(self.fd, new, SOCKET)?,
+            _ => {}
+        }
+        
+        self.interest = interest;
+        
+        Ok(())
+    }
+}
+
+impl Transport for PipeTransport {
+    // the actual read happens in wait once the socket is readable
+    unsafe fn start_read(&mut self, buffer: NonNull<[u8]>) -> Result<(), PipeError> {
+        self.read.replace(buffer);
+        
+        Ok(())
+    }
+    
+    // the actual write happens in wait once the socket is writable
+    unsafe fn start_write(&mut self, buffer: NonNull<[u8]>) -> Result<(), PipeError> {
+        self.write.replace(buffer);
+        
+        Ok(())
+    }
+    
+    fn wait(&mut self, events: &[Event], timeout: Option<Duration>, mut f: impl FnMut(IoCompletion)) -> Result<bool, PipeError> {
+        self.register(events)?;
+        
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        
+        loop {
+            let mut completed = false;
+            let mut socket = 0;
+            
+            let ready = self.epoll.wait(epoll_timeout(deadline))?;
+            
+            if ready.is_empty() {
+                return Ok(true);
+            }
+            
+            for (token, readiness) in ready {
+                if token == SOCKET {
+                    socket = readiness;
+                }
+                else if let Some(&event) = events.iter().find(|&&event| unsafe { event.handle() } as u64 == token) {
+                    event.reset()?;
+                    f(IoCompletion::Event(event));
+                    completed = true;
+                }
+            }
+            
+            let error = (libc::EPOLLHUP | libc::EPOLLERR) as u32;
+            
+            // errors and hang ups are reported through the pending operations
+            if let Some(buffer) = self.read && socket & (libc::EPOLLIN as u32 | error) != 0 {
+                match self.recv(buffer) {
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                    result => {
+                        self.read = None;
+                        f(IoCompletion::Read(result.map_err(PipeError::from)));
+                        completed = true;
+                    }
+                }
+            }
+            
+            if let Some(buffer) = self.write && socket & (libc::EPOLLOUT as u32 | error) != 0 {
+                match self.send(buffer) {
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                    result => {
+                        self.write = None;
+                        f(IoCompletion::Write(result.map_err(PipeError::from)));
+                        completed = true;
+                    }
+                }
+            }
+            
+            if completed {
+                return Ok(false);
+            }
+            
+            // the socket was ready but the operation would still block
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(true);
+            }
+        }
+    }
+    
+    unsafe fn close(&mut self) -> Result<(), PipeError> {
+        let fd = std::mem::replace(&mut self.fd, null_handle());
+        
+        unsafe { close_handle(fd) }.map_err(PipeError::from)
+    }
+}
+
+impl Drop for PipeTransport {
+    fn drop(&mut self) {
+        if self.fd != null_handle() {
+            unsafe { libc::close(self.fd); }
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::time::Duration;
+
+use windows::Win32::{
+    Foundation::{
+        CloseHandle,
+        GetLastError,
+        ERROR_BROKEN_PIPE,
+        ERROR_FILE_NOT_FOUND,
+        ERROR_IO_PENDING,
+        ERROR_NO_DATA,
+        ERROR_PIPE_BUSY,
+        ERROR_PIPE_CONNECTED,
+        ERROR_PIPE_NOT_CONNECTED,
+        ERROR_SEM_TIMEOUT,
+        GENERIC_ACCESS_RIGHTS,
+        GENERIC_READ,
+        GENERIC_WRITE,
+        HANDLE,
+        WAIT_EVENT,
+        WAIT_FAILED,
+        WAIT_OBJECT_0,
+        WAIT_TIMEOUT,
+        WIN32_ERROR,
+    },
+    Storage::FileSystem::{
+        CreateFileA,
+        FlushFileBuffers,
+        ReadFile,
+        WriteFile,
+        FILE_FLAG_OVERLAPPED,
+        FILE_SHARE_NONE,
+        OPEN_EXISTING,
+        PIPE_ACCESS_DUPLEX,
+    },
+    System::{
+        IO::{
+            CancelIoEx,
+            GetOverlappedResult,
+            OVERLAPPED,
+        },
+        Pipes::{
+            ConnectNamedPipe,
+            CreateNamedPipeA,
+            DisconnectNamedPipe,
+            WaitNamedPipeA,
+            NMPWAIT_NOWAIT,
+            NMPWAIT_USE_DEFAULT_WAIT,
+            NMPWAIT_WAIT_FOREVER,
+            PIPE_READMODE_BYTE,
+            PIPE_TYPE_BYTE,
+            PIPE_UNLIMITED_INSTANCES,
+        },
+        Threading::{
+            CreateEventA,
+            ResetEvent,
+            SetEvent,
+            WaitForMultipleObjects,
+            WaitForSingleObject,
+            INFINITE,
+        },
+    },
+};
+
+pub use windows::core::Error;
+
+// defaults to the error of the os, the transport reports the error of the crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+use crate::{client::NamedPipeCheck, error::Error as PipeError, utils::*};
+
+use super::PipeWait;
+
+pub type RawHandle = HANDLE;
+
+pub fn null_handle() -> RawHandle {
+    HANDLE(std::ptr::null_mut())
+}
+
+pub fn get_overlapped_result(handle: HANDLE, overlapped: *mut OVERLAPPED) -> Result<usize> {
+    unsafe {
+        let mut bytes = 0;
+        
+        GetOverlappedResult(handle, overlapped, &mut bytes, false)
+                .map(|_| bytes as usize)
+    }
+}
+
+pub fn create_event() -> Result<RawHandle> {
+    unsafe { CreateEventA(None, true, false, None) }
+}
+
+pub unsafe fn set_event(handle: RawHandle) -> Result<()> {
+    unsafe { SetEvent(handle) }
+}
+
+pub unsafe fn reset_event(handle: RawHandle) -> Result<()> {
+    unsafe { ResetEvent(handle) }
+}
+
+pub unsafe fn event_signal(handle: RawHandle) -> Result<bool> {
+    unsafe {
+        match WaitForSingleObject(handle, 0) {
+            WAIT_OBJECT_0 => Ok(true),
+            WAIT_TIMEOUT => Ok(false),
+            WAIT_FAILED => Err(Error::from_win32()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub unsafe fn close_handle(handle: RawHandle) -> Result<()> {
+    unsafe { CloseHandle(handle) }
+}
+
+pub fn broken_pipe() -> Error {
+    Error::from(ERROR_BROKEN_PIPE.to_hresult())
+}
+
+// the other end is gone, as opposed to something having gone wrong
+pub fn is_closed(error: &Error) -> bool {
+    [ERROR_BROKEN_PIPE, ERROR_PIPE_NOT_CONNECTED, ERROR_NO_DATA].iter().any(|code| error.code() == code.to_hresult())
+}
+
+// pipes have no would block error of their own, this is WSAEWOULDBLOCK
+pub fn would_block() -> Error {
+    Error::from(WIN32_ERROR(10035).to_hresult())
+}
+
+// the os errors that have a kind of their own
+pub fn error_kind(error: Error) -> crate::error::ErrorKind {
+    use crate::error::ErrorKind as Kind;
+    
+    match error.code() {
+        _ if is_closed(&error) => Kind::Disconnected,
+        code if code == would_block().code() => Kind::WouldBlock,
+        code if code == ERROR_SEM_TIMEOUT.to_hresult() => Kind::Timeout,
+        code if code == ERROR_FILE_NOT_FOUND.to_hresult() => Kind::NotFound,
+        code if code == ERROR_PIPE_BUSY.to_hresult() => Kind::Busy,
+        _ => Kind::Os(error.into()),
+    }
+}
+
+// the transport does not own the handle on windows
+pub unsafe fn share_handle(handle: RawHandle) -> Result<RawHandle> {
+    Ok(handle)
+}
+
+// returns true if nothing was signalled before the timeout
+pub unsafe fn wait_handles(handle_slice: &[RawHandle], timeout: Option<Duration>, mut f: impl FnMut(usize)) -> Result<bool> {
+    let WAIT_EVENT(zero) = WAIT_OBJECT_0;
+    
+    // INFINITE itself is excluded from finite timeouts
+    let timeout = timeout.map_or(INFINITE, |timeout| super::millis(timeout).min((INFINITE - 1) as u128) as u32);
+    
+    unsafe {
+        let mut code = match WaitForMultipleObjects(handle_slice, false, timeout) {
+            WAIT_FAILED => Err(Error::from_win32())?,
+            WAIT_TIMEOUT => return Ok(true),
+            WAIT_EVENT(code) => code,
+        };
+        
+        loop {
+            let index = (code - zero) as usize;
+            
+            ResetEvent(handle_slice[index])?;
+            f(index);
+            
+            code = match WaitForMultipleObjects(handle_slice, false, 0) {
+                WAIT_FAILED => Err(Error::from_win32())?,
+                WAIT_TIMEOUT => break,
+                WAIT_EVENT(code) => code,
+            };
+        }
+    }
+    
+    Ok(false)
+}
+
+pub struct PipeInstance {
+    handle: HANDLE,
+    overlapped: NonNull<OVERLAPPED>,
+}
+
+impl PipeInstance {
+    // the limit of the first instance holds for the others, more instances fail with ERROR_PIPE_BUSY
+    pub fn new(pipe_name: &NamedPipePath, buffer_size: u32, default_timeout: Duration, max_instances: Option<u32>) -> Result<Self> {
+        unsafe {
+            let handle = CreateNamedPipeA(
+                pipe_name.as_pcstr(),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE,
+                max_instances.filter(|&max_instances| max_instances < PIPE_UNLIMITED_INSTANCES).unwrap_or(PIPE_UNLIMITED_INSTANCES),
+                buffer_size,
+                buffer_size,
+                default_timeout.as_millis() as u32,
+                None
+            )?;
+            
+            Ok(Self {
+                handle,
+                overlapped: init_zero(alloc()),
+            })
+        }
+    }
+    
+    // returns true if a client is already connected
+    pub fn connect(&self, event: Event) -> Result<bool> {
+        unsafe {
+            self.overlapped.write(OVERLAPPED { hEvent: event.handle(), ..Default::default() });
+            
+            match ConnectNamedPipe(self.handle, Some(self.overlapped.as_ptr())) {
+                Ok(()) => Ok(true),
+                Err(error) => match GetLastError() {
+                    ERROR_IO_PENDING => Ok(false),
+                    ERROR_PIPE_CONNECTED => Ok(true),
+                    _ => Err(error),
+                }
+            }
+        }
+    }
+    
+    // handle of the connected client, which is the instance itself on windows
+    pub fn connection(&self) -> Result<RawHandle> {
+        Ok(self.handle)
+    }
+    
+    pub fn disconnect(&self) -> Result<()> {
+        unsafe {
+            // a client that is gone cannot be flushed to, but the instance still has to be disconnected before it is reused
+            match FlushFileBuffers(self.handle) {
+                Err(error) if !is_closed(&error) => return Err(error),
+                _ => {}
+            }
+            
+            DisconnectNamedPipe(self.handle)
+        }
+    }
+    
+    pub fn close(&self) -> Result<()> {
+        unsafe { CloseHandle(self.handle) }
+    }
+}
+
+impl Drop for PipeInstance {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.overlapped) }
+    }
+}
+
+pub fn check_pipe(pipe_name: &NamedPipePath) -> Result<NamedPipeCheck> {
+    unsafe {
+        // zero would be the default wait, which takes as long as the server wants when every instance is busy
+        match WaitNamedPipeA(pipe_name.as_pcstr(), NMPWAIT_NOWAIT) {
+            Ok(_) => Ok(NamedPipeCheck::Available),
+            _ if GetLastError() == ERROR_FILE_NOT_FOUND => Ok(NamedPipeCheck::Unavailable),
+            _ if matches!(GetLastError(), ERROR_PIPE_BUSY | ERROR_SEM_TIMEOUT) => Ok(NamedPipeCheck::Busy),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+pub fn wait_pipe(pipe_name: &NamedPipePath, wait: PipeWait) -> Result<Option<RawHandle>> {
+    let timeout = match wait {
+        PipeWait::Forever => NMPWAIT_WAIT_FOREVER,
+        PipeWait::Default => NMPWAIT_USE_DEFAULT_WAIT,
+        PipeWait::Timeout(timeout) => timeout.as_millis().try_into().unwrap_or(u32::MAX),
+    };
+    
+    unsafe {
+        match WaitNamedPipeA(pipe_name.as_pcstr(), timeout) {
+            Ok(_) => {
+                let GENERIC_ACCESS_RIGHTS(access) = GENERIC_READ | GENERIC_WRITE;
+                
+                Ok(Some(CreateFileA(
+                    pipe_name.as_pcstr(),
+                    access,
+                    FILE_SHARE_NONE,
+                    None,
+                    OPEN_EXISTING,
+                    FILE_FLAG_OVERLAPPED,
+                    None,
+                )?))
+            }
+            _ if GetLastError() == ERROR_SEM_TIMEOUT => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+pub struct PipeTransport {
+    handle: HANDLE,
+    events: [EventOwner; 2], // read and write completion
+    read: NonNull<OVERLAPPED>,
+    write: NonNull<OVERLAPPED>,
+    pending: [bool; 2], // read and write started but not reported by wait yet, the kernel may still write their OVERLAPPED
+}
+
+unsafe impl Send for PipeTransport {}
+
+impl PipeTransport {
+    // the transport does not own the handle
+    pub fn new(handle: RawHandle) -> Result<Self, PipeError> {
+        let [read_event, write_event] = EventManager::register_n()?;
+        
+        unsafe {
+            Ok(Self {
+                handle,
+                events: [EventOwner(read_event), EventOwner(write_event)],
+                read: init_zero(alloc()),
+                write: init_zero(alloc()),
+                pending: [false; 2],
+            })
+        }
+    }
+    
+    fn read_event(&self) -> Event {
+        self.events[0].duplicate()
+    }
+    
+    fn write_event(&self) -> Event {
+        self.events[1].duplicate()
+    }
+    
+    // the OVERLAPPEDs and events can only be freed once the kernel is done with them
+    fn cancel_pending(&mut self) {
+        for (overlapped, pending) in [self.read, self.write].into_iter().zip(&mut self.pending) {
+            if std::mem::take(pending) {
+                unsafe {
+                    let mut bytes = 0;
+                    
+                    let _ = CancelIoEx(self.handle, Some(overlapped.as_ptr()));
+                    let _ = GetOverlappedResult(self.handle, overlapped.as_ptr(), &mut bytes, true);
+                }
+            }
+        }
+    }
+}
+
+impl Transport for PipeTransport {
+    unsafe fn start_read(&mut self, mut buffer: NonNull<[u8]>) -> Result<(), PipeError> {
+        unsafe {
+            self.read.write(OVERLAPPED { hEvent: self.read_event().handle(), ..Default::default() });
+            
+            match ReadFile(self.handle, Some(buffer.as_mut()), None, Some(self.read.as_ptr())) {
+                Ok(()) => self.read_event().set()?,
+                Err(_) if GetLastError() == ERROR_IO_PENDING => self.pending[0] = true,
+                Err(error) => Err(error)?,
+            }
+            
+            Ok(())
+        }
+    }
+    
+    unsafe fn start_write(&mut self, buffer: NonNull<[u8]>) -> Result<(), PipeError> {
+        unsafe {
+            self.write.write(OVERLAPPED { hEvent: self.write_event().handle(), ..Default::default() });
+            
+            match WriteFile(self.handle, Some(buffer.as_ref()), None, Some(self.write.as_ptr())) {
+                Ok(()) => self.write_event().set()?,
+                Err(_) if GetLastError() == ERROR_IO_PENDING => self.pending[1] = true,
+                Err(error) => Err(error)?,
+            }
+            
+            Ok(())
+        }
+    }
+    
+    fn wait(&mut self, events: &[Event], timeout: Option<Duration>, mut f: impl FnMut(IoCompletion)) -> Result<bool, PipeError> {
+        let (read_event, write_event) = (self.read_event(), self.write_event());
+        let mut wait_events = vec![read_event, write_event];
+        
+        wait_events.extend_from_slice(events);
+        
+        wait_signals(&wait_events, timeout, |index| {
+            let event = wait_events[index];
+            
+            if event == read_event {
+                self.pending[0] = false;
+                f(IoCompletion::Read(get_overlapped_result(self.handle, self.read.as_ptr()).map_err(PipeError::from)));
+            }
+            else if event == write_event {
+                self.pending[1] = false;
+                f(IoCompletion::Write(get_overlapped_result(self.handle, self.write.as_ptr()).map_err(PipeError::from)));
+            }
+            else {
+                f(IoCompletion::Event(event));
+            }
+        })
+    }
+    
+    // the handle is closed by the pipe instance or the client it belongs to
+    unsafe fn close(&mut self) -> Result<(), PipeError> {
+        self.cancel_pending();
+        
+        Ok(())
+    }
+}
+
+impl Drop for PipeTransport {
+    // a runtime that panicked may not have closed the transport
+    fn drop(&mut self) {
+        self.cancel_pending();
+        
+        unsafe {
+            dealloc(self.read);
+            dealloc(self.write);
+        }
+    }
+}
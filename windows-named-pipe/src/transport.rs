@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use crate::utils::*;
+
+pub mod memory;
+
+pub use memory::MemoryTransport;
+pub use crate::sys::PipeTransport;
+
+#[derive(Debug)]
+pub enum IoCompletion {
+    Read(Result<usize, Error>),
+    Write(Result<usize, Error>),
+    Event(Event),
+}
+
+// overlapped style io: an operation is started on a buffer and its completion is reported by wait
+pub trait Transport: Send + 'static {
+    /// # Safety
+    /// the buffer must stay valid and untouched until the read completes
+    unsafe fn start_read(&mut self, buffer: NonNull<[u8]>) -> Result<(), Error>;
+    
+    /// # Safety
+    /// the buffer must stay valid and untouched until the write completes
+    unsafe fn start_write(&mut self, buffer: NonNull<[u8]>) -> Result<(), Error>;
+    
+    // blocks until an operation completes or one of the events is signalled, then reports everything that is ready
+    // signalled events are reset before being reported
+    // returns true if nothing was reported before the timeout, waits forever without one
+    fn wait(&mut self, events: &[Event], timeout: Option<Duration>, f: impl FnMut(IoCompletion)) -> Result<bool, Error>;
+    
+    /// # Safety
+    /// no operation may be started afterwards, the buffers of pending ones must stay valid until the transport is dropped
+    unsafe fn close(&mut self) -> Result<(), Error>;
+}
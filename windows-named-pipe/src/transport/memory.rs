@@ -0,0 +1,174 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use crate::{sys, utils::*};
+
+// one direction of the duplex
+#[derive(Debug)]
+struct Queue {
+    bytes: Mutex<(VecDeque<u8>, bool)>, // the flag is set once either end is closed
+    readable: EventOwner, // signalled while there are bytes or the queue is closed
+}
+
+impl Queue {
+    fn new() -> Result<Arc<Self>, Error> {
+        Ok(Arc::new(Self {
+            bytes: Mutex::new((VecDeque::new(), false)),
+            readable: EventOwner(EventManager::register()?),
+        }))
+    }
+    
+    fn push(&self, bytes: &[u8]) -> Result<usize, Error> {
+        let mut queue = self.bytes.lock().unwrap();
+        let (queue, closed) = &mut *queue;
+        
+        if *closed {
+            return Err(sys::broken_pipe().into());
+        }
+        
+        queue.extend(bytes);
+        self.readable.duplicate().set()?;
+        
+        Ok(bytes.len())
+    }
+    
+    // returns None if there is nothing to read yet
+    fn pop(&self, buffer: &mut [u8]) -> Option<Result<usize, Error>> {
+        let mut queue = self.bytes.lock().unwrap();
+        let (queue, closed) = &mut *queue;
+        
+        if queue.is_empty() {
+            return closed.then(|| Err(sys::broken_pipe().into()));
+        }
+        
+        let len = queue.len().min(buffer.len());
+        
+        for (byte, b) in buffer.iter_mut().zip(queue.drain(..len)) {
+            *byte = b;
+        }
+        
+        // waiting resets the event, so keep it signalled for the remaining bytes
+        if !queue.is_empty() && let Err(error) = self.readable.duplicate().set() {
+            return Some(Err(error));
+        }
+        
+        Some(Ok(len))
+    }
+    
+    fn close(&self) -> Result<(), Error> {
+        let mut queue = self.bytes.lock().unwrap();
+        let (_, closed) = &mut *queue;
+        
+        *closed = true;
+        
+        self.readable.duplicate().set()
+    }
+}
+
+// in-process duplex, one end is created for each side by pair
+#[derive(Debug)]
+pub struct MemoryTransport {
+    incoming: Arc<Queue>,
+    outgoing: Arc<Queue>,
+    read: Option<NonNull<[u8]>>,
+    write: Option<Result<usize, Error>>, // writes complete immediately
+}
+
+unsafe impl Send for MemoryTransport {}
+
+impl MemoryTransport {
+    pub fn pair() -> Result<(Self, Self), Error> {
+        let a = Queue::new()?;
+        let b = Queue::new()?;
+        
+        Ok((
+            Self { incoming: a.clone(), outgoing: b.clone(), read: None, write: None },
+            Self { incoming: b, outgoing: a, read: None, write: None },
+        ))
+    }
+    
+    // reports every completion that does not need to wait, returns true if anything was reported
+    fn poll(&mut self, events: &[Event], f: &mut impl FnMut(IoCompletion)) -> Result<bool, Error> {
+        let mut completed = false;
+        
+        for &event in events {
+            if event.signal()? {
+                event.reset()?;
+                f(IoCompletion::Event(event));
+                completed = true;
+            }
+        }
+        
+        if let Some(mut buffer) = self.read && let Some(result) = self.incoming.pop(unsafe { buffer.as_mut() }) {
+            self.read = None;
+            f(IoCompletion::Read(result));
+            completed = true;
+        }
+        
+        if let Some(result) = self.write.take() {
+            f(IoCompletion::Write(result));
+            completed = true;
+        }
+        
+        Ok(completed)
+    }
+}
+
+impl Transport for MemoryTransport {
+    unsafe fn start_read(&mut self, buffer: NonNull<[u8]>) -> Result<(), Error> {
+        self.read.replace(buffer);
+        
+        Ok(())
+    }
+    
+    unsafe fn start_write(&mut self, buffer: NonNull<[u8]>) -> Result<(), Error> {
+        self.write.replace(self.outgoing.push(unsafe { buffer.as_ref() }));
+        
+        Ok(())
+    }
+    
+    fn wait(&mut self, events: &[Event], timeout: Option<Duration>, mut f: impl FnMut(IoCompletion)) -> Result<bool, Error> {
+        if self.poll(events, &mut f)? {
+            return Ok(false);
+        }
+        
+        let mut wait_events = events.to_vec();
+        
+        if self.read.is_some() {
+            wait_events.push(self.incoming.readable.duplicate());
+        }
+        
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        
+        loop {
+            let mut completed = false;
+            
+            let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            
+            let timed_out = wait_signals(&wait_events, timeout, |index| {
+                if index < events.len() {
+                    f(IoCompletion::Event(events[index]));
+                    completed = true;
+                }
+            })?;
+            
+            if self.poll(&[], &mut f)? || completed {
+                return Ok(false);
+            }
+            
+            if timed_out {
+                return Ok(true);
+            }
+        }
+    }
+    
+    unsafe fn close(&mut self) -> Result<(), Error> {
+        self.incoming.close()?;
+        self.outgoing.close()
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        let _ = unsafe { self.close() };
+    }
+}
@@ -0,0 +1,137 @@
+use std::{borrow::Borrow, fmt, marker::PhantomData, time::Duration};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::utils::*;
+
+pub type FormatError = Box<dyn std::error::Error + Send + Sync>;
+
+// turns values into message payloads and back
+pub trait Format {
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FormatError>;
+    
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FormatError>;
+}
+
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Format for Json {
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+    
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FormatError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+// compact binary, both ends must agree on the exact types
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Format for Bincode {
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FormatError> {
+        Ok(bincode::serialize(value)?)
+    }
+    
+    fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FormatError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Format(FormatError), // nothing was sent
+    Frame(FrameError),
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Format(FormatError), // the message is dropped, the next one can still be received
+    Frame(FrameError),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Format(error) => write!(f, "failed to serialize message: {error}"),
+            EncodeError::Frame(error) => write!(f, "failed to send message: {error}"),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Format(error) => write!(f, "failed to deserialize message: {error}"),
+            DecodeError::Frame(error) => write!(f, "failed to receive message: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl std::error::Error for DecodeError {}
+
+impl From<FrameError> for EncodeError {
+    fn from(error: FrameError) -> Self {
+        EncodeError::Frame(error)
+    }
+}
+
+impl From<FrameError> for DecodeError {
+    fn from(error: FrameError) -> Self {
+        DecodeError::Frame(error)
+    }
+}
+
+// sends Out and receives In as framed messages
+// the pipe can be owned or borrowed, e.g. from ServerNamedPipeStatus::Connected
+pub struct TypedPipe<Out, In, F, P = NamedPipe> {
+    pipe: P,
+    format: F,
+    marker: PhantomData<fn(&Out) -> In>,
+}
+
+impl<Out: Serialize, In: DeserializeOwned, F: Format, P: Borrow<NamedPipe>> TypedPipe<Out, In, F, P> {
+    pub fn new(pipe: P, format: F) -> Self {
+        Self { pipe, format, marker: PhantomData }
+    }
+    
+    pub fn pipe(&self) -> &NamedPipe {
+        self.pipe.borrow()
+    }
+    
+    pub fn format(&self) -> &F {
+        &self.format
+    }
+    
+    pub fn into_inner(self) -> P {
+        self.pipe
+    }
+    
+    pub fn send(&self, value: &Out) -> Result<(), EncodeError> {
+        let payload = self.format.serialize(value).map_err(EncodeError::Format)?;
+        
+        Ok(self.pipe().send_message(&payload)?)
+    }
+    
+    fn decode(&self, payload: Option<Vec<u8>>) -> Result<Option<In>, DecodeError> {
+        payload.map(|payload| self.format.deserialize(&payload).map_err(DecodeError::Format)).transpose()
+    }
+    
+    // returns None until a whole message has arrived
+    pub fn recv(&self) -> Result<Option<In>, DecodeError> {
+        self.decode(self.pipe().recv_message()?)
+    }
+    
+    // returns None if no message arrived in time
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<In>, DecodeError> {
+        self.decode(self.pipe().recv_message_timeout(timeout)?)
+    }
+}
@@ -1,37 +1,12 @@
-
 use std::mem::MaybeUninit;
 
 pub use std::ptr::NonNull;
 pub use std::thread::spawn as new_thread;
 
-pub use crate::{path::*, channel, event::*, buffer::*, pipe::*, runtime::*, server_pipe::*};
-
-pub use windows::{
-    core::{
-        Error as WindowsError,
-        Result as WindowsResult,
-    },
-    Win32::{
-        Foundation::{
-            GetLastError,
-            HANDLE,
-        },
-        System::IO::{
-            GetOverlappedResult,
-            OVERLAPPED,
-        },
-    },
-};
-
-pub fn get_overlapped_result(handle: HANDLE, overlapped: *mut OVERLAPPED) -> WindowsResult<usize> {
-    unsafe {
-        let mut bytes = 0;
-        
-        GetOverlappedResult(handle, overlapped, &mut bytes, false)
-                .map(|_| bytes as usize)
-    }
-}
+pub use crate::{path::*, channel, error::{Error, ErrorKind}, event::*, buffer::*, framing::*, pipe::*, runtime::*, server_pipe::*, transport::*};
+pub use crate::sys::RawHandle;
 
+#[cfg(windows)]
 pub unsafe fn assume_init<T>(pointer: NonNull<MaybeUninit<T>>) -> NonNull<T> {
     unsafe { NonNull::new_unchecked(pointer.as_ptr() as *mut T) }
 }
@@ -40,6 +15,7 @@ pub unsafe fn assume_init_slice<T>(pointer: NonNull<[MaybeUninit<T>]>) -> NonNul
     unsafe { NonNull::new_unchecked(pointer.as_ptr() as *mut [T]) }
 }
 
+#[cfg(windows)]
 pub unsafe fn alloc<T>() -> NonNull<MaybeUninit<T>> {
     unsafe { NonNull::new_unchecked(Box::into_raw(Box::<T>::new_uninit())) }
 }
@@ -48,6 +24,7 @@ pub unsafe fn alloc_n<T>(len: usize) -> NonNull<[MaybeUninit<T>]> {
     unsafe { NonNull::new_unchecked(Box::into_raw(Box::<[T]>::new_uninit_slice(len))) }
 }
 
+#[cfg(windows)]
 pub unsafe fn init_zero<T>(pointer: NonNull<MaybeUninit<T>>) -> NonNull<T> {
     unsafe {
         pointer.write(MaybeUninit::zeroed());
@@ -0,0 +1,316 @@
+use std::{thread::scope, time::{Duration, Instant}};
+
+use windows_named_pipe::channel::{Channel, RecvError, SendError, SendTimeoutError, TrySendError};
+
+const WRITERS: usize = 8;
+const MESSAGES: usize = 20000;
+
+#[test]
+fn concurrent_senders_lose_nothing() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    let mut received = Vec::new();
+    
+    scope(|s| {
+        for writer in 0..WRITERS {
+            let sender = &sender;
+            
+            s.spawn(move || {
+                for i in 0..MESSAGES {
+                    sender.send((writer, i)).unwrap();
+                }
+            });
+        }
+        
+        // read while the writers are still busy, to contend on both sides
+        while received.len() < WRITERS * MESSAGES {
+            received.append(&mut receiver.receive_all());
+        }
+    });
+    
+    assert_eq!(received.len(), WRITERS * MESSAGES);
+    
+    // each writer's messages arrive in the order they were sent
+    let mut next = [0; WRITERS];
+    
+    for (writer, i) in received {
+        assert_eq!(i, next[writer]);
+        next[writer] += 1;
+    }
+}
+
+#[test]
+fn concurrent_vectors_lose_nothing() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    scope(|s| {
+        for _ in 0..WRITERS {
+            s.spawn(|| {
+                for _ in 0..MESSAGES / 100 {
+                    sender.send_vec(&mut vec![1u64; 100]).unwrap();
+                }
+            });
+        }
+        
+        for _ in 0..WRITERS {
+            s.spawn(|| sender.flush());
+        }
+    });
+    
+    assert_eq!(receiver.receive_all().into_iter().sum::<u64>(), (WRITERS * MESSAGES) as u64);
+}
+
+#[test]
+fn recv_blocking_wakes_up() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    scope(|s| {
+        s.spawn(|| {
+            for i in 0..3 {
+                std::thread::sleep(Duration::from_millis(10));
+                sender.send(i).unwrap();
+            }
+        });
+        
+        // oldest first
+        for i in 0..3 {
+            assert_eq!(receiver.recv_blocking(), Ok(i));
+        }
+    });
+}
+
+#[test]
+fn recv_timeout() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    let start = Instant::now();
+    
+    assert_eq!(receiver.recv_timeout(Duration::from_millis(20)), Err(RecvError::Timeout));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    
+    assert!(!receiver.wait_nonempty(Duration::ZERO));
+    
+    sender.send(1).unwrap();
+    sender.send(2).unwrap();
+    
+    // waiting does not receive anything
+    assert!(receiver.wait_nonempty(Duration::ZERO));
+    assert!(receiver.wait_nonempty(Duration::ZERO));
+    
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+    assert_eq!(receiver.recv_timeout(Duration::ZERO), Ok(2));
+    assert_eq!(receiver.recv_timeout(Duration::ZERO), Err(RecvError::Timeout));
+}
+
+#[test]
+fn wait_nonempty_from_another_thread() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            sender.send_vec(&mut vec![1, 2, 3]).unwrap();
+        });
+        
+        assert!(receiver.wait_nonempty(Duration::from_secs(10)));
+        assert_eq!(receiver.receive_all(), [1, 2, 3]);
+    });
+}
+
+#[test]
+fn bounded_try_send() {
+    let (sender, receiver) = Channel::bounded(2).unwrap();
+    
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Ok(()));
+    assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(sender.send_timeout(3, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(3)));
+    
+    assert_eq!(receiver.recv_timeout(Duration::ZERO), Ok(1));
+    
+    assert_eq!(sender.try_send(3), Ok(()));
+    assert_eq!(receiver.receive_all(), [2, 3]);
+    
+    // more than the capacity still fits into an empty channel, but nothing after that
+    sender.send_vec(&mut vec![4, 5, 6]).unwrap();
+    assert_eq!(sender.try_send(7), Err(TrySendError::Full(7)));
+    assert_eq!(receiver.receive_all(), [4, 5, 6]);
+}
+
+#[test]
+fn bounded_send_waits_for_room() {
+    const CAPACITY: usize = 16;
+    
+    let (sender, receiver) = Channel::bounded(CAPACITY).unwrap();
+    
+    scope(|s| {
+        for writer in 0..WRITERS {
+            let sender = &sender;
+            
+            s.spawn(move || {
+                for i in 0..MESSAGES / 10 {
+                    sender.send((writer, i)).unwrap();
+                }
+            });
+        }
+        
+        let mut next = [0; WRITERS];
+        
+        for _ in 0..WRITERS * MESSAGES / 10 {
+            let (writer, i) = receiver.recv_timeout(Duration::from_secs(10)).expect("Timed out");
+            
+            assert_eq!(i, next[writer]);
+            next[writer] += 1;
+            
+            // the bound holds the whole time
+            let mut len = 0;
+            unsafe { receiver.raw_buffer(|buffer| len = buffer.len()); }
+            assert!(len <= CAPACITY);
+        }
+    });
+}
+
+#[test]
+fn cloned_handles() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    let mut received = Vec::new();
+    
+    scope(|s| {
+        let receivers: Vec<_> = (0..WRITERS).map(|_| {
+            let receiver = receiver.clone();
+            
+            s.spawn(move || {
+                let mut received = Vec::new();
+                
+                // ends once every sender is gone and everything is taken
+                while let Ok(t) = receiver.recv_blocking() {
+                    received.push(t);
+                }
+                
+                received
+            })
+        }).collect();
+        
+        for writer in 0..WRITERS {
+            let sender = sender.clone();
+            
+            s.spawn(move || {
+                for i in 0..MESSAGES / 10 {
+                    sender.send((writer, i)).unwrap();
+                }
+            });
+        }
+        
+        drop(sender);
+        
+        for thread in receivers {
+            received.append(&mut thread.join().unwrap());
+        }
+    });
+    
+    // every item is received exactly once
+    received.sort_unstable();
+    
+    let expected: Vec<_> = (0..WRITERS).flat_map(|writer| (0..MESSAGES / 10).map(move |i| (writer, i))).collect();
+    
+    assert_eq!(received, expected);
+    assert_eq!(receiver.recv_timeout(Duration::ZERO), Err(RecvError::Disconnected));
+}
+
+#[test]
+fn disconnected_senders() {
+    let (sender, receiver) = Channel::bounded(1).unwrap();
+    let receiver_clone = receiver.clone();
+    
+    sender.send(1).unwrap();
+    
+    // a sender waiting for room gives up once the receivers are gone
+    scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            
+            drop(receiver);
+            assert!(!sender.is_disconnected());
+            drop(receiver_clone);
+        });
+        
+        assert_eq!(sender.send(2), Err(SendError::Disconnected(2)));
+    });
+    
+    assert!(sender.is_disconnected());
+    assert_eq!(sender.try_send(3), Err(TrySendError::Disconnected(3)));
+    assert_eq!(sender.send_timeout(3, Duration::ZERO), Err(SendTimeoutError::Disconnected(3)));
+    assert_eq!(sender.send_vec(&mut vec![3]), Err(SendError::Disconnected(())));
+}
+
+#[test]
+fn disconnected_receivers() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    sender.send(1).unwrap();
+    sender.clone().send(2).unwrap();
+    
+    assert!(!receiver.is_disconnected());
+    
+    drop(sender);
+    
+    // whatever was sent can still be received
+    assert!(receiver.is_disconnected());
+    assert!(receiver.wait_nonempty(Duration::from_secs(10)));
+    assert_eq!(receiver.recv_blocking(), Ok(1));
+    assert_eq!(receiver.recv_blocking(), Ok(2));
+    assert_eq!(receiver.recv_blocking(), Err(RecvError::Disconnected));
+    assert!(!receiver.wait_nonempty(Duration::from_secs(10)));
+    
+    // nothing else holds on to the channel now
+    assert!(receiver.unique().is_ok());
+}
+
+#[test]
+fn fifo_receive() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    for i in 0..10 {
+        sender.send(i).unwrap();
+        
+        // flushing in between changes nothing about the order
+        if i % 3 == 0 {
+            sender.flush();
+        }
+        
+        if i % 4 == 0 {
+            receiver.try_flush();
+        }
+    }
+    
+    assert_eq!(receiver.peek(|&i| i), Some(0));
+    assert_eq!(receiver.receive_next(), Some(0));
+    assert_eq!(receiver.receive_n(3), [1, 2, 3]);
+    assert_eq!(receiver.peek(|&i| i), Some(4));
+    assert_eq!(receiver.receive_latest(), Some(9));
+    assert_eq!(receiver.try_iter().take(2).collect::<Vec<_>>(), [4, 5]);
+    
+    sender.send(10).unwrap();
+    
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [6, 7, 8, 10]);
+    assert_eq!(receiver.receive_next(), None);
+    assert_eq!(receiver.peek(|&i| i), None);
+    assert!(receiver.receive_n(3).is_empty());
+}
+
+#[test]
+fn fifo_unique_receive() {
+    let (sender, receiver) = Channel::new().unwrap();
+    
+    sender.send_vec(&mut (0..10).collect()).unwrap();
+    drop(sender);
+    
+    let receiver = receiver.unique().expect("Receiver should be unique");
+    
+    assert_eq!(receiver.peek(|&i| i), Some(0));
+    assert_eq!(receiver.receive_next(), Some(0));
+    assert_eq!(receiver.receive_n(2), [1, 2]);
+    assert_eq!(receiver.try_iter().take(3).collect::<Vec<_>>(), [3, 4, 5]);
+    assert_eq!(receiver.receive_all(), [6, 7, 8, 9]);
+}
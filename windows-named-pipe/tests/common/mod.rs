@@ -0,0 +1,37 @@
+// shared by the tests, not every one of them uses all of it
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+use windows_named_pipe::{prelude::*, runtime::utils::runtime_reference_implementation};
+
+pub const IO_BUFFER_SIZE: usize = 4096;
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn buffer() -> NamedPipeBuffer {
+    NamedPipeBuffer::new(IO_BUFFER_SIZE)
+}
+
+// both ends of a loopback in this process
+pub fn pair() -> (NamedPipe, NamedPipe) {
+    NamedPipe::loopback_pair(
+        buffer(),
+        buffer(),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair")
+}
+
+pub fn poll<T>(mut f: impl FnMut() -> Option<T>) -> T {
+    let start = Instant::now();
+    
+    loop {
+        if let Some(t) = f() {
+            return t;
+        }
+        
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,50 @@
+use std::{io, time::Duration};
+
+use windows_named_pipe::prelude::client::*;
+
+#[test]
+fn missing_pipe() {
+    let pipe_name = NamedPipePath::new("error_test_missing");
+    let error = Client::try_wait(&pipe_name, Duration::from_millis(20)).expect_err("Pipe should not exist");
+    
+    assert!(matches!(error.kind(), ErrorKind::NotFound));
+    assert_eq!(error.path(), Some(&pipe_name));
+    assert_eq!(error.index(), None);
+    assert_eq!(error.to_string(), format!("pipe does not exist on {pipe_name}"));
+    assert_eq!(io::Error::from(error).kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn context() {
+    let pipe_name = NamedPipePath::new("error_test");
+    let error = Error::new(ErrorKind::Busy).with_path(&pipe_name).with_index(2);
+    
+    assert_eq!(error.index(), Some(2));
+    assert_eq!(error.to_string(), format!("every pipe instance is busy on {pipe_name} (instance 2)"));
+    
+    // the context stays readable through io
+    let error = io::Error::from(error);
+    
+    assert_eq!(error.kind(), io::ErrorKind::ResourceBusy);
+    assert!(error.to_string().contains("instance 2"));
+}
+
+#[test]
+fn into_io() {
+    let kinds = [
+        (ErrorKind::Disconnected, io::ErrorKind::BrokenPipe),
+        (ErrorKind::Timeout, io::ErrorKind::TimedOut),
+        (ErrorKind::WouldBlock, io::ErrorKind::WouldBlock),
+        (ErrorKind::Protocol("nonsense".into()), io::ErrorKind::InvalidData),
+        (ErrorKind::RuntimePanic, io::ErrorKind::Other),
+    ];
+    
+    for (kind, io_kind) in kinds {
+        assert_eq!(io::Error::from(Error::new(kind)).kind(), io_kind);
+    }
+    
+    // os errors without context come out as they went in
+    let error = io::Error::from(Error::new(ErrorKind::Os(io::Error::from_raw_os_error(5))));
+    
+    assert_eq!(error.raw_os_error(), Some(5));
+}
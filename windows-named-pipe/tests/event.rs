@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use windows_named_pipe::event::{EventManager, EventOwner, EventPool};
+
+#[test]
+fn manual_reset() {
+    let event = EventOwner(EventManager::register().expect("Failed to register event"));
+    let event = event.duplicate();
+    
+    assert!(!event.signal().unwrap());
+    
+    event.set().unwrap();
+    event.set().unwrap();
+    
+    // checking does not consume the signal
+    assert!(event.signal().unwrap());
+    assert!(event.signal().unwrap());
+    
+    event.reset().unwrap();
+    
+    assert!(!event.signal().unwrap());
+}
+
+#[test]
+fn wake_once_see_all() {
+    let events = EventManager::register_n::<4>().expect("Failed to register events");
+    let _owners = events.map(EventOwner);
+    
+    events[3].set().unwrap();
+    events[0].set().unwrap();
+    events[2].set().unwrap();
+    
+    let mut indices = Vec::new();
+    
+    events.wait_signals_index(|index| indices.push(index)).unwrap();
+    
+    assert_eq!(indices, [0, 2, 3]);
+    
+    // everything reported has been reset
+    assert!(events.iter().all(|event| !event.signal().unwrap()));
+}
+
+#[test]
+fn wake_from_another_thread() {
+    let events = EventManager::register_n::<2>().expect("Failed to register events");
+    let _owners = events.map(EventOwner);
+    let event = events[1];
+    
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        event.set().unwrap();
+    });
+    
+    let mut signalled = Vec::new();
+    
+    events.wait_signals_event(|event| signalled.push(event)).unwrap();
+    
+    assert_eq!(signalled, [event]);
+    
+    thread.join().unwrap();
+}
+
+#[test]
+fn timeout_without_signal() {
+    let events = EventManager::register_n::<2>().expect("Failed to register events");
+    let _owners = events.map(EventOwner);
+    
+    let start = Instant::now();
+    let mut indices = Vec::new();
+    
+    assert!(events.wait_signals_index_timeout(Duration::from_millis(20), |index| indices.push(index)).unwrap());
+    
+    assert!(indices.is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+fn timeout_with_signal() {
+    let events = EventManager::register_n::<2>().expect("Failed to register events");
+    let _owners = events.map(EventOwner);
+    let event = events[0];
+    
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        event.set().unwrap();
+    });
+    
+    let mut signalled = Vec::new();
+    
+    assert!(!events.wait_signals_event_timeout(Duration::from_secs(10), |event| signalled.push(event)).unwrap());
+    
+    assert_eq!(signalled, [event]);
+    
+    // a zero timeout only polls
+    event.set().unwrap();
+    assert!(!events.wait_signals_index_timeout(Duration::ZERO, |_| ()).unwrap());
+    assert!(events.wait_signals_index_timeout(Duration::ZERO, |_| ()).unwrap());
+    
+    thread.join().unwrap();
+}
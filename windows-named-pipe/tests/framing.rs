@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use windows_named_pipe::framing::*;
+
+fn encode(codec: &FrameCodec, payloads: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    
+    for payload in payloads {
+        codec.encode(payload, &mut bytes).expect("Failed to encode");
+    }
+    
+    bytes
+}
+
+#[test]
+fn round_trip() {
+    let codec = FrameCodec::default();
+    let payloads: [&[u8]; 4] = [b"hello", b"", b"binary\n\0\xff\n", &[0; 1000]];
+    let mut bytes = encode(&codec, &payloads);
+    
+    assert_eq!(bytes.len(), payloads.iter().map(|payload| HEADER_SIZE + payload.len()).sum::<usize>());
+    
+    for payload in payloads {
+        assert_eq!(codec.decode(&mut bytes).expect("Failed to decode").as_deref(), Some(payload));
+    }
+    
+    assert_eq!(codec.decode(&mut bytes).expect("Failed to decode"), None);
+    codec.finish(&bytes).expect("Bytes left over");
+}
+
+#[test]
+fn decode_slice() {
+    let codec = FrameCodec::default();
+    let bytes = encode(&codec, &[b"first", b"second"]);
+    let mut slice = &bytes[..];
+    
+    assert_eq!(codec.decode(&mut slice).expect("Failed to decode").as_deref(), Some(&b"first"[..]));
+    assert_eq!(codec.decode(&mut slice).expect("Failed to decode").as_deref(), Some(&b"second"[..]));
+    assert!(slice.is_empty());
+}
+
+#[test]
+fn partial_frames() {
+    let codec = FrameCodec::default();
+    let bytes = encode(&codec, &[b"split across\nmany reads", b"and another"]);
+    let mut source = VecDeque::new();
+    let mut received = Vec::new();
+    
+    // one byte at a time, like the slowest possible pipe
+    for &byte in &bytes {
+        source.push_back(byte);
+        
+        while let Some(payload) = codec.decode(&mut source).expect("Failed to decode") {
+            received.push(payload);
+        }
+    }
+    
+    assert_eq!(received, [&b"split across\nmany reads"[..], b"and another"]);
+    assert!(source.is_empty());
+}
+
+#[test]
+fn oversize() {
+    let codec = FrameCodec::new(4);
+    let mut bytes = Vec::new();
+    
+    codec.encode(b"four", &mut bytes).expect("Failed to encode");
+    assert!(matches!(codec.encode(b"five!", &mut bytes), Err(FrameError::Oversize { len: 5, max: 4 })));
+    
+    // nothing is written for a frame that is too large
+    assert_eq!(bytes.len(), HEADER_SIZE + 4);
+    
+    // a peer with a larger maximum
+    FrameCodec::default().encode(b"five!", &mut bytes).expect("Failed to encode");
+    
+    assert_eq!(codec.decode(&mut bytes).expect("Failed to decode").as_deref(), Some(&b"four"[..]));
+    
+    // the header alone is enough to tell, and the frame stays in place
+    bytes.truncate(HEADER_SIZE);
+    assert!(matches!(codec.decode(&mut bytes), Err(FrameError::Oversize { len: 5, max: 4 })));
+    assert!(matches!(codec.decode(&mut bytes), Err(FrameError::Oversize { len: 5, max: 4 })));
+}
+
+#[test]
+fn truncated() {
+    let codec = FrameCodec::default();
+    let mut bytes = encode(&codec, &[b"whole", b"cut short"]);
+    
+    bytes.truncate(bytes.len() - 3);
+    
+    assert_eq!(codec.decode(&mut bytes).expect("Failed to decode").as_deref(), Some(&b"whole"[..]));
+    assert_eq!(codec.decode(&mut bytes).expect("Failed to decode"), None);
+    assert!(matches!(codec.finish(&bytes), Err(FrameError::Truncated { available }) if available == HEADER_SIZE + 6));
+    
+    // even within the header
+    assert!(matches!(codec.finish(&&[0u8; 2][..]), Err(FrameError::Truncated { available: 2 })));
+}
+
+#[test]
+fn max_frame_size() {
+    assert_eq!(FrameCodec::default().max_frame_size(), DEFAULT_MAX_FRAME_SIZE);
+    assert_eq!(FrameCodec::new(usize::MAX).max_frame_size(), u32::MAX as usize);
+}
+
+#[test]
+fn content_length_round_trip() {
+    let codec = ContentLengthCodec::default();
+    let mut bytes = Vec::new();
+    
+    codec.encode(br#"{"jsonrpc":"2.0"}"#, &mut bytes).expect("Failed to encode");
+    assert_eq!(bytes, b"Content-Length: 17\r\n\r\n{\"jsonrpc\":\"2.0\"}");
+    
+    // other headers are allowed and the name is not case sensitive
+    bytes.extend(b"content-type: application/vscode-jsonrpc; charset=utf-8\r\ncontent-length:  5\r\n\r\nhello");
+    
+    let mut source = VecDeque::new();
+    let mut received = Vec::new();
+    
+    for &byte in &bytes {
+        source.push_back(byte);
+        
+        while let Some(payload) = codec.decode(&mut source).expect("Failed to decode") {
+            received.push(payload);
+        }
+    }
+    
+    assert_eq!(received, [&br#"{"jsonrpc":"2.0"}"#[..], b"hello"]);
+    assert!(source.is_empty());
+}
+
+#[test]
+fn content_length_errors() {
+    let codec = ContentLengthCodec::new(4);
+    
+    assert!(matches!(codec.encode(b"hello", &mut Vec::new()), Err(FrameError::Oversize { len: 5, max: 4 })));
+    assert!(matches!(codec.decode(&mut &b"Content-Length: 5\r\n\r\n"[..]), Err(FrameError::Oversize { len: 5, max: 4 })));
+    assert!(matches!(codec.decode(&mut &b"Content-Type: text\r\n\r\n"[..]), Err(FrameError::Malformed(_))));
+    assert!(matches!(codec.decode(&mut &b"Content-Length: many\r\n\r\n"[..]), Err(FrameError::Malformed(_))));
+    assert!(matches!(codec.decode(&mut &b"no colon\r\n\r\n"[..]), Err(FrameError::Malformed(_))));
+    
+    // a header that never ends
+    assert!(matches!(codec.decode(&mut vec![b'a'; MAX_HEADER_SIZE]), Err(FrameError::Malformed(_))));
+    assert!(matches!(codec.decode(&mut vec![b'a'; MAX_HEADER_SIZE - 1]), Ok(None)));
+    
+    let mut bytes = b"Content-Length: 4\r\n\r\nab".to_vec();
+    
+    assert!(matches!(codec.decode(&mut bytes), Ok(None)));
+    assert!(matches!(codec.finish(&bytes), Err(FrameError::Truncated { .. })));
+}
@@ -0,0 +1,220 @@
+#![cfg(feature = "futures-io")]
+
+use std::{
+    pin::pin,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Instant,
+};
+
+use futures::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use windows_named_pipe::{prelude::*, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+struct Task {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+// a single threaded executor that only polls again once woken, so a lost wakeup fails instead of being polled over
+fn block_on<T>(future: impl Future<Output = T>) -> T {
+    let mut future = pin!(future);
+    let task = Arc::new(Task { woken: AtomicBool::new(false), thread: thread::current() });
+    let waker = Waker::from(task.clone());
+    let mut cx = Context::from_waker(&waker);
+    
+    loop {
+        if let Poll::Ready(t) = future.as_mut().poll(&mut cx) {
+            return t;
+        }
+        
+        let deadline = Instant::now() + TIMEOUT;
+        
+        while !task.woken.swap(false, Ordering::SeqCst) {
+            let now = Instant::now();
+            
+            assert!(now < deadline, "Lost wakeup");
+            thread::park_timeout(deadline - now);
+        }
+    }
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 7919) as u8).collect()
+}
+
+#[test]
+fn recv_wakes_up() {
+    const ROUNDS: usize = 10000;
+    
+    let (sender, receiver) = channel::Channel::new().unwrap();
+    let (reply_sender, reply_receiver) = channel::Channel::new().unwrap();
+    
+    // waits for every reply before sending on, so the task is waiting for nearly every message
+    let sending = thread::spawn(move || {
+        for i in 0..ROUNDS {
+            sender.send(i).unwrap();
+            assert_eq!(reply_receiver.recv_timeout(TIMEOUT), Ok(i));
+        }
+    });
+    
+    block_on(async {
+        for i in 0..ROUNDS {
+            assert_eq!(receiver.recv_async().await, Ok(i));
+            reply_sender.send_async(i).await.unwrap();
+        }
+        
+        // the sender is dropped once the thread is done
+        assert_eq!(receiver.recv_async().await, Err(channel::RecvError::Disconnected));
+    });
+    
+    sending.join().unwrap();
+}
+
+#[test]
+fn send_waits_for_room() {
+    const CAPACITY: usize = 4;
+    const MESSAGES: usize = 100000;
+    
+    let (sender, receiver) = channel::Channel::bounded(CAPACITY).unwrap();
+    
+    let receiving = thread::spawn(move || {
+        (0..MESSAGES).map(|_| receiver.recv_blocking().unwrap()).collect::<Vec<_>>()
+    });
+    
+    block_on(async {
+        for i in 0..MESSAGES {
+            sender.send_async(i).await.unwrap();
+        }
+    });
+    
+    assert!(receiving.join().unwrap().into_iter().eq(0..MESSAGES));
+    
+    // every receiver is gone
+    assert_eq!(block_on(sender.send_async(0)), Err(channel::SendError::Disconnected(0)));
+}
+
+#[test]
+fn copy_both_ways_through_a_bounded_queue() {
+    const LEN: usize = 3 << 20;
+    
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer().bounded(IO_BUFFER_SIZE),
+        buffer().bounded(IO_BUFFER_SIZE),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    let data = payload(LEN);
+    
+    let write = async |pipe: &NamedPipe| {
+        let mut writer = AsyncNamedPipe::new(pipe);
+        
+        writer.write_all(&data).await.expect("Failed to write");
+        writer.flush().await.expect("Failed to flush");
+    };
+    
+    let read = async |pipe: &NamedPipe| {
+        let mut received = vec![0; LEN];
+        
+        AsyncNamedPipe::new(pipe).read_exact(&mut received).await.expect("Failed to read");
+        received
+    };
+    
+    // one thread does all four at once, so every wait has to be woken by a runtime thread
+    let (_, _, a_received, b_received) = block_on(async { futures::join!(write(&a), write(&b), read(&a), read(&b)) });
+    
+    assert!(a_received == data, "Received data differs");
+    assert!(b_received == data, "Received data differs");
+}
+
+#[test]
+fn buf_read_lines() {
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer(),
+        buffer(),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    let writing = thread::spawn(move || {
+        for i in 0..1000 {
+            a.write_line(&format!("line {i}")).expect("Failed to write line");
+        }
+        
+        a.write(b"no newline").expect("Failed to write");
+        a.wait_drained(Some(TIMEOUT)).expect("Failed to flush");
+        a.interrupt().expect("Failed to interrupt");
+    });
+    
+    let lines = block_on(async {
+        let mut reader = AsyncNamedPipe::new(b);
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        
+        // only ends with the end of the stream
+        while reader.read_line(&mut line).await.expect("Failed to read line") > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        
+        lines
+    });
+    
+    writing.join().unwrap();
+    
+    assert_eq!(lines.len(), 1001);
+    assert!(lines[..1000].iter().enumerate().all(|(i, line)| *line == format!("line {i}\n")));
+    assert_eq!(lines[1000], "no newline");
+}
+
+#[test]
+fn close_stops_writing() {
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer(),
+        buffer(),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    let mut a = AsyncNamedPipe::new(a);
+    let mut b = AsyncNamedPipe::new(b);
+    let mut received = Vec::new();
+    
+    block_on(async {
+        a.write_all(b"last words").await.expect("Failed to write");
+        a.close().await.expect("Failed to close");
+        
+        assert_eq!(a.write_all(b"late").await.expect_err("Wrote after the close").kind(), std::io::ErrorKind::BrokenPipe);
+        
+        // still readable after the close
+        b.write_all(b"reply").await.expect("Failed to write");
+        b.flush().await.expect("Failed to flush");
+        
+        let mut reply = [0; 5];
+        
+        a.read_exact(&mut reply).await.expect("Failed to read");
+        assert_eq!(&reply, b"reply");
+        
+        // only the interrupt ends the stream for the reader
+        a.pipe().interrupt().expect("Failed to interrupt");
+        b.read_to_end(&mut received).await.expect("Failed to read");
+    });
+    
+    assert_eq!(received, b"last words");
+    a.into_inner().join().expect("Runtime panicked");
+}
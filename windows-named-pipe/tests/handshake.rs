@@ -0,0 +1,212 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, thread::scope, time::{Duration, Instant}};
+
+use windows_named_pipe::{handshake::HELLO_SIZE, prelude::{client::*, server::*}, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+// runs both ends at once, neither can finish before the other has sent its hello
+fn handshake(a: &mut NamedPipe, b: &mut NamedPipe, handshake_a: Handshake, handshake_b: Handshake) -> (Result<Protocol, HandshakeError>, Result<Protocol, HandshakeError>) {
+    scope(|s| {
+        let b = s.spawn(|| b.handshake(&handshake_b));
+        let a = a.handshake(&handshake_a);
+        
+        (a, b.join().unwrap())
+    })
+}
+
+#[test]
+fn negotiates_version_and_features() {
+    let (mut a, mut b) = pair();
+    
+    assert_eq!(a.protocol(), None);
+    
+    let (protocol_a, protocol_b) = handshake(
+        &mut a,
+        &mut b,
+        Handshake::new(1, 3).with_features(0b011),
+        Handshake::new(2, 5).with_features(0b110),
+    );
+    
+    let expected = Protocol { version: 3, features: 0b010 };
+    
+    assert_eq!(protocol_a.expect("Handshake failed"), expected);
+    assert_eq!(protocol_b.expect("Handshake failed"), expected);
+    assert_eq!(a.protocol(), Some(expected));
+    assert_eq!(b.protocol(), Some(expected));
+    assert!(expected.has(0b010));
+    assert!(!expected.has(0b011));
+    
+    // the pipe carries on as usual
+    a.write_line("after").expect("Failed to write line");
+    assert_eq!(b.read_line_timeout(TIMEOUT), ReadLineResult::Line("after".into()));
+}
+
+#[test]
+fn data_right_after_the_hello() {
+    let (a, mut b) = pair();
+    
+    // written before b even started its handshake
+    a.write(&Handshake::new(1, 1).hello()).expect("Failed to write");
+    a.write_line("early").expect("Failed to write line");
+    
+    assert_eq!(b.handshake(&Handshake::new(1, 1)).expect("Handshake failed").version, 1);
+    assert_eq!(b.read_line_timeout(TIMEOUT), ReadLineResult::Line("early".into()));
+}
+
+#[test]
+fn incompatible_versions() {
+    let (mut a, mut b) = pair();
+    let (result_a, result_b) = handshake(&mut a, &mut b, Handshake::new(1, 2), Handshake::new(3, 4));
+    
+    assert!(matches!(result_a, Err(HandshakeError::Incompatible { local, remote }) if local == (1..=2) && remote == (3..=4)));
+    assert!(matches!(result_b, Err(HandshakeError::Incompatible { local, remote }) if local == (3..=4) && remote == (1..=2)));
+    assert_eq!(a.protocol(), None);
+    
+    let error = HandshakeError::Incompatible { local: 1..=2, remote: 3..=4 };
+    
+    assert_eq!(error.to_string(), "no common protocol version, supported are 1..=2 but the peer supports 3..=4");
+}
+
+#[test]
+fn bad_magic() {
+    let (mut a, mut b) = pair();
+    let (result_a, result_b) = handshake(&mut a, &mut b, Handshake::new(1, 1), Handshake::new(1, 1).with_magic(*b"ELSE"));
+    
+    assert!(matches!(result_a, Err(HandshakeError::BadMagic { received, .. }) if &received == b"ELSE"));
+    assert!(matches!(result_b, Err(HandshakeError::BadMagic { expected, .. }) if &expected == b"ELSE"));
+}
+
+#[test]
+fn peer_without_handshake() {
+    let (mut a, b) = pair();
+    let start = Instant::now();
+    
+    assert!(matches!(a.handshake(&Handshake::new(1, 1).with_timeout(Duration::from_millis(50))), Err(HandshakeError::Timeout)));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    
+    // to the peer the hello is just data
+    let mut received = Vec::new();
+    
+    while received.len() < HELLO_SIZE {
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        
+        match b.read_blocking() {
+            ReadResult::Data(data) => received.extend(data),
+            result => panic!("Unexpected read result: {result:?}"),
+        }
+    }
+    
+    assert_eq!(received, Handshake::new(1, 1).hello());
+}
+
+#[test]
+fn peer_gone() {
+    let (mut a, b) = pair();
+    
+    b.interrupt().expect("Failed to interrupt");
+    b.join().expect("Runtime panicked");
+    
+    let start = Instant::now();
+    
+    assert!(matches!(a.handshake(&Handshake::new(1, 1)), Err(HandshakeError::Disconnected)));
+    assert!(start.elapsed() < TIMEOUT);
+}
+
+#[test]
+fn server_and_client() {
+    let pipe_name = NamedPipePath::new("handshake_test");
+    
+    // the silent client would hold up everyone else for as long as the test may take if the handshake blocked the server
+    let server_handshake = Handshake::new(1, 3).with_features(0b101).with_timeout(TIMEOUT);
+    let silent_connected = AtomicBool::new(false);
+    let finished = AtomicBool::new(false);
+    
+    let wait = || {
+        let start = Instant::now();
+        
+        while let NamedPipeCheck::Unavailable = Client::check_pipe(&pipe_name).expect("Failed to check pipe") {
+            assert!(start.elapsed() < TIMEOUT, "Timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        
+        Client::wait(&pipe_name).expect("Failed to wait pipe")
+    };
+    
+    let connect = |handshake: Handshake| {
+        poll(|| silent_connected.load(Ordering::Relaxed).then_some(()));
+        wait().initialize_with_handshake(buffer(), runtime_reference_implementation(|_| ()), &handshake)
+    };
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+            let mut done = false;
+            let mut rejected = false;
+            let start = Instant::now();
+            
+            server.create_pipes(None, None, 3).expect("Failed to create pipes");
+            
+            while !(done && rejected) {
+                assert!(start.elapsed() < TIMEOUT, "Timed out");
+                
+                for index in server.get_connected_pipes() {
+                    let pipe = server.pipes()[index].pipe_mut();
+                    
+                    pipe.notify_connection_with_handshake(runtime_reference_implementation(|_| ()), &server_handshake).expect("Failed to connect pipe");
+                }
+                
+                for pipe in server.pipes() {
+                    let event = pipe.event();
+                    let pipe = pipe.pipe_mut();
+                    
+                    match pipe.update_status() {
+                        ServerNamedPipeStatus::Idle => pipe.start_connecting(event).expect("Failed to start connection"),
+                        ServerNamedPipeStatus::Disconnected(DisconnectReason::Handshake(_)) => rejected = true,
+                        ServerNamedPipeStatus::Connected(connected_pipe) => {
+                            assert_eq!(connected_pipe.protocol(), Some(Protocol { version: 3, features: 0b100 }));
+                            
+                            match connected_pipe.read_line() {
+                                ReadLineResult::Line(line) if line == "bye" => done = true, // the client is done once it has the echo
+                                ReadLineResult::Line(line) => connected_pipe.write_line(&line).expect("Failed to write line"),
+                                _ => {}
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            
+            finished.store(true, Ordering::Relaxed);
+            server.close().expect("Failed to close server");
+        });
+        
+        s.spawn(|| {
+            let pipe = wait().initialize(buffer(), runtime_reference_implementation(|_| ())).expect("Failed to initialize pipe");
+            
+            silent_connected.store(true, Ordering::Relaxed);
+            poll(|| finished.load(Ordering::Relaxed).then_some(()));
+            
+            pipe.interrupt().expect("Failed to interrupt");
+        });
+        
+        s.spawn(move || {
+            let pipe = connect(Handshake::new(2, 5).with_features(0b110)).expect("Handshake failed");
+            
+            assert_eq!(pipe.protocol(), Some(Protocol { version: 3, features: 0b100 }));
+            
+            pipe.write_line("hello").expect("Failed to write line");
+            assert_eq!(pipe.read_line_timeout(TIMEOUT), ReadLineResult::Line("hello".into()));
+            
+            pipe.write_line("bye").expect("Failed to write line");
+        });
+        
+        s.spawn(move || {
+            // the server may already have hung up before its hello was read
+            assert!(matches!(connect(Handshake::new(4, 5)), Err(HandshakeError::Incompatible { .. } | HandshakeError::Disconnected)));
+        });
+    });
+}
@@ -0,0 +1,233 @@
+#![cfg(feature = "test-util")]
+
+use std::{ptr::NonNull, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::scope, time::{Duration, Instant}};
+
+use windows_named_pipe::{prelude::{client::*, server::*}, runtime::{heartbeat::*, utils::runtime_reference_implementation}};
+
+mod common;
+
+use common::*;
+
+fn heartbeat() -> Heartbeat {
+    Heartbeat::new(Duration::from_secs(1), Duration::from_secs(5))
+}
+
+fn pair(clock: &ManualClock) -> (NamedPipe, NamedPipe) {
+    NamedPipe::loopback_pair(
+        buffer(),
+        buffer(),
+        runtime_heartbeat_implementation(|_| (), heartbeat(), clock.clone()),
+        runtime_heartbeat_implementation(|_| (), heartbeat(), clock.clone()),
+    ).expect("Failed to create loopback pair")
+}
+
+// writes start only once they are not held anymore
+struct HeldWrites {
+    transport: MemoryTransport,
+    held: Arc<AtomicBool>,
+    write: Option<NonNull<[u8]>>,
+}
+
+unsafe impl Send for HeldWrites {}
+
+impl Transport for HeldWrites {
+    unsafe fn start_read(&mut self, buffer: NonNull<[u8]>) -> Result<(), Error> {
+        unsafe { self.transport.start_read(buffer) }
+    }
+    
+    unsafe fn start_write(&mut self, buffer: NonNull<[u8]>) -> Result<(), Error> {
+        self.write = Some(buffer);
+        
+        Ok(())
+    }
+    
+    fn wait(&mut self, events: &[Event], timeout: Option<Duration>, f: impl FnMut(IoCompletion)) -> Result<bool, Error> {
+        if !self.held.load(Ordering::Relaxed) && let Some(buffer) = self.write.take() {
+            unsafe { self.transport.start_write(buffer)? };
+        }
+        
+        self.transport.wait(events, timeout, f)
+    }
+    
+    unsafe fn close(&mut self) -> Result<(), Error> {
+        unsafe { self.transport.close() }
+    }
+}
+
+// neither reads nor writes until interrupted
+fn stalled<T: Transport>() -> impl NamedPipeRuntimeExecutor<T> {
+    |runtime| while !runtime.wait().0.interrupt {}
+}
+
+fn read_exact(pipe: &NamedPipe, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    
+    poll(|| {
+        if let ReadResult::Data(data) = pipe.read() {
+            bytes.extend(data);
+        }
+        
+        (bytes.len() >= len).then_some(())
+    });
+    
+    bytes
+}
+
+#[test]
+fn pings_are_not_read() {
+    let clock = ManualClock::new();
+    let (a, b) = pair(&clock);
+    
+    for i in 0..5 {
+        // long enough for a ping from both ends
+        clock.advance(heartbeat().interval);
+        std::thread::sleep(Duration::from_millis(10));
+        
+        a.write_line(&format!("line {i}")).expect("Failed to write line");
+        assert_eq!(b.read_line_timeout(TIMEOUT), ReadLineResult::Line(format!("line {i}")));
+    }
+    
+    // more than fits in a chunk or the io buffer
+    let data: Vec<u8> = (0..200_000).map(|i| (i * 7) as u8).collect();
+    
+    b.write(&data).expect("Failed to write");
+    assert_eq!(read_exact(&a, data.len()), data);
+    assert_eq!(a.read(), ReadResult::Empty);
+    
+    assert!(!a.is_finished());
+    assert!(!b.is_finished());
+}
+
+#[test]
+fn drained_once_written() {
+    let clock = ManualClock::new();
+    let held = Arc::new(AtomicBool::new(true));
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    let a = HeldWrites { transport: a, held: held.clone(), write: None };
+    let a = NamedPipe::new(a, buffer(), runtime_heartbeat_implementation(|_| (), heartbeat(), clock.clone())).expect("Failed to create pipe");
+    let b = NamedPipe::new(b, buffer(), runtime_heartbeat_implementation(|_| (), heartbeat(), clock.clone())).expect("Failed to create pipe");
+    
+    // taken by the runtime, but not written yet
+    a.write(b"held").expect("Failed to write");
+    assert!(matches!(a.wait_drained(Some(Duration::from_millis(50))).expect_err("Drained before the write").kind(), ErrorKind::Timeout));
+    
+    held.store(false, Ordering::Relaxed);
+    a.wait_drained(Some(TIMEOUT)).expect("Failed to drain");
+    
+    assert_eq!(read_exact(&b, 4), b"held");
+}
+
+#[test]
+fn pings_keep_the_connection_alive() {
+    let clock = ManualClock::new();
+    let (a, b) = pair(&clock);
+    
+    // far past the timeout, but never without a ping for long
+    for _ in 0..20 {
+        clock.advance(heartbeat().interval / 2);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    
+    assert!(!a.is_finished());
+    assert!(!b.is_finished());
+    assert_eq!(a.disconnect_reason(), None);
+}
+
+#[test]
+fn silent_peer_is_dead() {
+    let clock = ManualClock::new();
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer(),
+        buffer(),
+        runtime_heartbeat_implementation(|_| (), heartbeat(), clock.clone()),
+        stalled(),
+    ).expect("Failed to create loopback pair");
+    
+    // the runtime takes the time it starts at as the last time it heard from the peer
+    std::thread::sleep(Duration::from_millis(20));
+    
+    clock.advance(heartbeat().timeout - Duration::from_millis(1));
+    std::thread::sleep(Duration::from_millis(20));
+    
+    assert!(!a.is_finished());
+    
+    clock.advance(Duration::from_millis(1));
+    poll(|| a.is_finished().then_some(()));
+    
+    assert!(matches!(a.disconnect_reason(), Some(DisconnectReason::PeerTimeout(silence)) if silence >= heartbeat().timeout));
+    
+    b.interrupt().expect("Failed to interrupt");
+}
+
+#[test]
+fn disconnect_reasons() {
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer(),
+        buffer(),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    assert_eq!(a.disconnect_reason(), None);
+    
+    b.interrupt().expect("Failed to interrupt");
+    poll(|| (a.is_finished() && b.is_finished()).then_some(()));
+    
+    assert_eq!(b.disconnect_reason(), Some(DisconnectReason::Interrupted));
+    assert_eq!(a.disconnect_reason(), Some(DisconnectReason::Closed));
+}
+
+#[test]
+fn server_sees_dead_client() {
+    let pipe_name = NamedPipePath::new("heartbeat_test");
+    let done = AtomicBool::new(false);
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+            let event = server.create_pipe(None, None).expect("Failed to create pipe").event();
+            let start = Instant::now();
+            
+            loop {
+                assert!(start.elapsed() < TIMEOUT, "Timed out");
+                
+                let connected = !server.get_connected_pipes().is_empty();
+                let pipe = server.pipes()[0].pipe_mut();
+                
+                if connected {
+                    let heartbeat = Heartbeat::new(Duration::from_millis(10), Duration::from_millis(100));
+                    
+                    pipe.notify_connection(runtime_heartbeat_implementation(|_| (), heartbeat, SystemClock)).expect("Failed to connect pipe");
+                }
+                
+                match pipe.update_status() {
+                    ServerNamedPipeStatus::Idle => pipe.start_connecting(event).expect("Failed to start connection"),
+                    ServerNamedPipeStatus::Disconnected(reason) => {
+                        assert!(matches!(reason, DisconnectReason::PeerTimeout(_)), "{reason}");
+                        break;
+                    }
+                    _ => {}
+                }
+                
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            
+            done.store(true, Ordering::Relaxed);
+            server.close().expect("Failed to close server");
+        });
+        
+        s.spawn(|| {
+            let start = Instant::now();
+            
+            while let NamedPipeCheck::Unavailable = Client::check_pipe(&pipe_name).expect("Failed to check pipe") {
+                assert!(start.elapsed() < TIMEOUT, "Timed out");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            
+            let pipe = Client::wait(&pipe_name).expect("Failed to wait pipe").initialize(buffer(), stalled()).expect("Failed to initialize pipe");
+            
+            poll(|| done.load(Ordering::Relaxed).then_some(()));
+            pipe.interrupt().expect("Failed to interrupt");
+        });
+    });
+}
@@ -0,0 +1,165 @@
+use std::{io::{self, BufRead, Read, Write}, thread::scope, time::Duration};
+
+use windows_named_pipe::{prelude::*, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 7919) as u8).collect()
+}
+
+#[test]
+fn copy_megabytes() {
+    const LEN: usize = 8 << 20;
+    
+    let (a, b) = pair();
+    let data = payload(LEN);
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut writer = PipeIo::new(&a);
+            
+            assert_eq!(io::copy(&mut &data[..], &mut writer).expect("Failed to copy"), LEN as u64);
+            writer.flush().expect("Failed to flush");
+            
+            // everything is written once flushed, so closing loses nothing
+            a.interrupt().expect("Failed to interrupt");
+        });
+        
+        let mut reader = PipeIo::new(&b);
+        let mut received = Vec::new();
+        
+        reader.set_read_timeout(Some(TIMEOUT));
+        
+        // only ends with the end of the stream
+        assert_eq!(io::copy(&mut reader, &mut received).expect("Failed to copy"), LEN as u64);
+        assert!(received == data, "Received data differs");
+    });
+}
+
+fn copy(from: &NamedPipe, to: &NamedPipe, data: &[u8]) -> Vec<u8> {
+    let mut received = Vec::new();
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut writer = PipeIo::new(from);
+            
+            writer.write_all(data).expect("Failed to write");
+            writer.flush().expect("Failed to flush");
+        });
+        
+        io::copy(&mut PipeIo::new(to).take(data.len() as u64), &mut received).expect("Failed to copy");
+    });
+    
+    received
+}
+
+#[test]
+fn copy_both_ways_through_a_bounded_queue() {
+    const LEN: usize = 3 << 20;
+    
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer().bounded(IO_BUFFER_SIZE),
+        buffer().bounded(IO_BUFFER_SIZE),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    let data = payload(LEN);
+    
+    // both ends write and read at once, the writes wait for room instead of failing
+    scope(|s| {
+        let a_to_b = s.spawn(|| copy(&a, &b, &data));
+        let b_to_a = copy(&b, &a, &data);
+        
+        assert!(a_to_b.join().unwrap() == data, "Received data differs");
+        assert!(b_to_a == data, "Received data differs");
+    });
+}
+
+#[test]
+fn buf_read_lines() {
+    let (a, b) = pair();
+    let mut writer = PipeIo::new(&a);
+    
+    for i in 0..1000 {
+        writeln!(writer, "line {i}").expect("Failed to write");
+    }
+    
+    write!(writer, "no newline").expect("Failed to write");
+    writer.flush().expect("Failed to flush");
+    a.interrupt().expect("Failed to interrupt");
+    
+    let lines: Vec<String> = PipeIo::new(&b).lines().collect::<Result<_, _>>().expect("Failed to read lines");
+    
+    assert_eq!(lines.len(), 1001);
+    assert!(lines[..1000].iter().enumerate().all(|(i, line)| *line == format!("line {i}")));
+    assert_eq!(lines[1000], "no newline");
+}
+
+#[test]
+fn read_timeout() {
+    let (a, b) = pair();
+    let mut reader = PipeIo::new(&b);
+    let mut bytes = [0; 16];
+    
+    reader.set_read_timeout(Some(Duration::from_millis(20)));
+    assert_eq!(reader.read_timeout(), Some(Duration::from_millis(20)));
+    assert_eq!(reader.read(&mut bytes).expect_err("Nothing was written").kind(), io::ErrorKind::TimedOut);
+    
+    // a timeout loses nothing
+    a.write(b"late").expect("Failed to write");
+    reader.set_read_timeout(Some(TIMEOUT));
+    
+    let mut received: Vec<u8> = Vec::new();
+    
+    while received.len() < 4 {
+        let len = reader.read(&mut bytes).expect("Failed to read");
+        
+        received.extend(&bytes[..len]);
+    }
+    
+    assert_eq!(received, b"late");
+}
+
+#[test]
+fn write_timeout() {
+    const CAPACITY: usize = 64;
+    
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    
+    // never takes anything out of the write queue
+    let stalled = NamedPipe::new(a, buffer().bounded(CAPACITY), |runtime: &mut NamedPipeRuntime<MemoryTransport>| {
+        while !runtime.wait().0.interrupt {}
+    }).expect("Failed to create pipe");
+    
+    let _b = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    let mut writer = PipeIo::new(&stalled);
+    
+    writer.set_write_timeout(Some(Duration::from_millis(20)));
+    writer.write_all(&[0; CAPACITY]).expect("Failed to write");
+    
+    assert_eq!(writer.write(&[0]).expect_err("Queue should be full").kind(), io::ErrorKind::TimedOut);
+    assert_eq!(writer.flush().expect_err("Queue is never drained").kind(), io::ErrorKind::TimedOut);
+    
+    stalled.interrupt().expect("Failed to interrupt");
+}
+
+#[test]
+fn closed_pipe() {
+    let (a, b) = pair();
+    
+    a.interrupt().expect("Failed to interrupt");
+    
+    let mut reader = PipeIo::new(b);
+    
+    assert_eq!(reader.read(&mut [0; 16]).expect("Failed to read"), 0);
+    assert_eq!(reader.fill_buf().expect("Failed to read"), b"");
+    
+    // the peer is gone, so the pipe closes by itself
+    assert_eq!(reader.write(b"too late").expect_err("Pipe is closed").kind(), io::ErrorKind::BrokenPipe);
+    
+    reader.into_inner().join().expect("Runtime panicked");
+}
@@ -0,0 +1,286 @@
+#![cfg(feature = "json")]
+
+use std::{sync::atomic::{AtomicUsize, Ordering}, thread::scope, time::{Duration, Instant}};
+
+use serde_json::{Value, json};
+use windows_named_pipe::{jsonrpc::*, prelude::{client::*, server::*}, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+// counts the notifications of its client
+fn server() -> JsonRpcServer<u64> {
+    let mut server = JsonRpcServer::default();
+    
+    server
+        .register("subtract", |_, params| match params {
+            Some(Value::Array(params)) => match params[..] {
+                [Value::Number(ref a), Value::Number(ref b)] => Ok(json!(a.as_i64().unwrap() - b.as_i64().unwrap())),
+                _ => Err(ErrorObject::invalid_params("expected two numbers")),
+            }
+            _ => Err(ErrorObject::invalid_params("expected two numbers")),
+        })
+        .register("count", |count, _| {
+            *count += 1;
+            Ok(Value::Null)
+        })
+        .register("get_count", |count, _| Ok(json!(*count)))
+        .register("sleep", |_, params| {
+            std::thread::sleep(Duration::from_millis(params.unwrap()[0].as_u64().unwrap()));
+            Ok(Value::Null)
+        });
+    
+    server
+}
+
+fn handle(server: &JsonRpcServer<u64>, message: &str) -> Option<Value> {
+    server.handle(&mut 0, message.as_bytes()).map(|response| serde_json::from_slice(&response).expect("Invalid response"))
+}
+
+fn error_code(response: &Value) -> i64 {
+    response["error"]["code"].as_i64().expect("Not an error")
+}
+
+#[test]
+fn specification_examples() {
+    let server = server();
+    
+    assert_eq!(
+        handle(&server, r#"{"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": 1}"#),
+        Some(json!({ "jsonrpc": "2.0", "result": 19, "id": 1 })),
+    );
+    assert_eq!(
+        handle(&server, r#"{"jsonrpc": "2.0", "method": "subtract", "params": [23, 42], "id": "two"}"#),
+        Some(json!({ "jsonrpc": "2.0", "result": -19, "id": "two" })),
+    );
+    
+    // notifications are never answered
+    assert_eq!(handle(&server, r#"{"jsonrpc": "2.0", "method": "count"}"#), None);
+    assert_eq!(handle(&server, r#"{"jsonrpc": "2.0", "method": "missing"}"#), None);
+    
+    let response = handle(&server, r#"{"jsonrpc": "2.0", "method": "missing", "id": "1"}"#).unwrap();
+    
+    assert_eq!(error_code(&response), METHOD_NOT_FOUND);
+    assert_eq!(response["id"], "1");
+    
+    let response = handle(&server, r#"{"jsonrpc": "2.0", "method": "subtract", "params": ["a"], "id": 3}"#).unwrap();
+    
+    assert_eq!(error_code(&response), INVALID_PARAMS);
+    assert_eq!(response["id"], 3);
+    
+    let response = handle(&server, r#"{"jsonrpc": "2.0", "method": "foobar, "params": "bar", "baz]"#).unwrap();
+    
+    assert_eq!(error_code(&response), PARSE_ERROR);
+    assert_eq!(response["id"], Value::Null);
+    
+    for invalid in [
+        r#"{"jsonrpc": "2.0", "method": 1, "params": "bar"}"#,
+        r#"{"jsonrpc": "1.0", "method": "subtract", "id": 1}"#,
+        r#"{"jsonrpc": "2.0", "method": "subtract", "params": 1, "id": 1}"#,
+        r#"{"jsonrpc": "2.0", "method": "subtract", "id": [1]}"#,
+        r#"[]"#,
+        r#"5"#,
+    ] {
+        assert_eq!(error_code(&handle(&server, invalid).unwrap()), INVALID_REQUEST, "{invalid}");
+    }
+}
+
+#[test]
+fn batches() {
+    let server = server();
+    
+    // every invalid entry gets an error of its own
+    let responses = handle(&server, "[1, 2, 3]").unwrap();
+    
+    assert_eq!(responses.as_array().unwrap().len(), 3);
+    assert!(responses.as_array().unwrap().iter().all(|response| error_code(response) == INVALID_REQUEST));
+    
+    let responses = handle(&server, r#"[
+        {"jsonrpc": "2.0", "method": "subtract", "params": [1, 2], "id": "1"},
+        {"jsonrpc": "2.0", "method": "count"},
+        {"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": "2"},
+        {"foo": "boo"},
+        {"jsonrpc": "2.0", "method": "missing", "id": "5"}
+    ]"#).unwrap();
+    
+    let responses = responses.as_array().unwrap();
+    
+    assert_eq!(responses.len(), 4);
+    assert_eq!(responses[0], json!({ "jsonrpc": "2.0", "result": -1, "id": "1" }));
+    assert_eq!(responses[1], json!({ "jsonrpc": "2.0", "result": 19, "id": "2" }));
+    assert_eq!(error_code(&responses[2]), INVALID_REQUEST);
+    assert_eq!(error_code(&responses[3]), METHOD_NOT_FOUND);
+    
+    // nothing at all for a batch of notifications
+    assert_eq!(handle(&server, r#"[{"jsonrpc": "2.0", "method": "count"}, {"jsonrpc": "2.0", "method": "count"}]"#), None);
+}
+
+#[test]
+fn client() {
+    let (a, b) = pair();
+    let client = JsonRpcClient::new(a, ContentLengthCodec::default());
+    let server = server();
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut count = 0;
+            
+            while !b.is_finished() {
+                server.serve_pending(&mut count, &b).expect("Failed to serve");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+        
+        assert_eq!(client.request("subtract", Some(json!([42, 23])), TIMEOUT).expect("Request failed"), 19);
+        
+        client.notify("count", None).expect("Failed to notify");
+        client.notify("count", None).expect("Failed to notify");
+        assert_eq!(client.request("get_count", None, TIMEOUT).expect("Request failed"), 2);
+        
+        assert!(matches!(
+            client.request("missing", None, TIMEOUT),
+            Err(JsonRpcError::Error(ErrorObject { code: METHOD_NOT_FOUND, .. })),
+        ));
+        
+        let results = client.batch(vec![
+            BatchCall::request("subtract", Some(json!([1, 2]))),
+            BatchCall::notification("count", None),
+            BatchCall::request("subtract", Some(json!(["a"]))),
+            BatchCall::request("get_count", None),
+        ], TIMEOUT).expect("Failed to send batch");
+        
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().expect("Request failed"), -1);
+        assert!(matches!(results[1], Err(JsonRpcError::Error(ErrorObject { code: INVALID_PARAMS, .. }))));
+        assert_eq!(results[2].as_ref().expect("Request failed"), 3);
+        
+        assert!(client.batch(vec![BatchCall::notification("count", None)], TIMEOUT).expect("Failed to send batch").is_empty());
+        
+        // the late response is dropped
+        let start = Instant::now();
+        
+        assert!(matches!(client.request("sleep", Some(json!([250])), Duration::from_millis(20)), Err(JsonRpcError::Timeout)));
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert_eq!(client.request("get_count", None, TIMEOUT).expect("Request failed"), 4);
+        
+        b.interrupt().expect("Failed to interrupt");
+    });
+    
+    // the peer is gone
+    let start = Instant::now();
+    
+    while !client.is_closed() {
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    
+    assert!(matches!(client.request("get_count", None, TIMEOUT), Err(JsonRpcError::Disconnected)));
+}
+
+#[test]
+fn sessions_per_client() {
+    const CLIENTS: usize = 2;
+    
+    let pipe_name = NamedPipePath::new("jsonrpc_test");
+    let done = AtomicUsize::new(0);
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+            let mut rpc = self::server();
+            let start = Instant::now();
+            
+            server.create_pipes(None, None, CLIENTS).expect("Failed to create pipes");
+            
+            while done.load(Ordering::Relaxed) < CLIENTS {
+                assert!(start.elapsed() < TIMEOUT, "Timed out");
+                
+                for index in server.get_connected_pipes() {
+                    server.pipes()[index].pipe_mut().notify_connection(runtime_reference_implementation(|_| ())).expect("Failed to connect pipe");
+                }
+                
+                for pipe in server.pipes() {
+                    let event = pipe.event();
+                    let pipe = pipe.pipe_mut();
+                    
+                    if let ServerNamedPipeStatus::Idle = pipe.update_status() {
+                        pipe.start_connecting(event).expect("Failed to start connection");
+                    }
+                }
+                
+                assert!(rpc.poll(&mut server).is_empty());
+                
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            
+            server.close().expect("Failed to close server");
+        });
+        
+        for i in 0..CLIENTS as u64 {
+            let pipe_name = &pipe_name;
+            let done = &done;
+            
+            s.spawn(move || {
+                let start = Instant::now();
+                
+                while let NamedPipeCheck::Unavailable = Client::check_pipe(pipe_name).expect("Failed to check pipe") {
+                    assert!(start.elapsed() < TIMEOUT, "Timed out");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                
+                let pipe = Client::wait(pipe_name).expect("Failed to wait pipe").initialize(buffer(), runtime_reference_implementation(|_| ())).expect("Failed to initialize pipe");
+                let client = JsonRpcClient::new(pipe, ContentLengthCodec::default());
+                
+                // each client counts on its own
+                for _ in 0..=i {
+                    client.notify("count", None).expect("Failed to notify");
+                }
+                
+                assert_eq!(client.request("get_count", None, TIMEOUT).expect("Request failed"), i + 1);
+                
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+    });
+}
+
+#[test]
+fn serve_sessions_per_connection() {
+    let pipe_name = NamedPipePath::new("jsonrpc_serve_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    let closer = server.closer();
+    let rpc = self::server();
+    
+    // a single instance, every client after the first takes it over from the one before
+    server.set_pool(ServerPool { max_instances: Some(1), ..Default::default() });
+    
+    scope(|s| {
+        s.spawn(|| {
+            for _ in 0..3 {
+                let start = Instant::now();
+                
+                while Client::check_pipe(&pipe_name).expect("Failed to check pipe") != NamedPipeCheck::Available {
+                    assert!(start.elapsed() < TIMEOUT, "Timed out");
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                
+                let client = Client::wait(&pipe_name).expect("Failed to wait pipe");
+                let pipe = client.initialize(buffer(), runtime_reference_implementation(|_| ())).expect("Failed to initialize pipe");
+                let rpc = JsonRpcClient::new(pipe, ContentLengthCodec::default());
+                
+                // the count of the client before is gone, and a timeout too large for an instant still waits
+                rpc.notify("count", None).expect("Failed to notify");
+                assert_eq!(rpc.request("get_count", None, Duration::MAX).expect("Request failed"), 1);
+                
+                rpc.pipe().interrupt().expect("Failed to interrupt");
+                drop(rpc);
+                client.close().expect("Failed to close client");
+            }
+            
+            closer.close().expect("Failed to close server");
+        });
+        
+        rpc.serve(&mut server, || runtime_reference_implementation(|_| ())).expect("Failed to serve");
+    });
+}
@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use windows_named_pipe::{prelude::*, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+fn read_line(pipe: &NamedPipe) -> String {
+    match pipe.read_line_timeout(Duration::from_secs(10)) {
+        ReadLineResult::Line(line) => line,
+        result => panic!("Expected a line, got {result:?}"),
+    }
+}
+
+#[test]
+fn read_lines() {
+    let (a, b) = pair();
+    
+    for i in 0..100 {
+        a.write_line(&format!("line {i}")).expect("Failed to write line");
+    }
+    
+    for i in 0..100 {
+        assert_eq!(read_line(&b), format!("line {i}"));
+    }
+    
+    assert_eq!(b.read_line(), ReadLineResult::Empty);
+}
+
+#[test]
+fn interrupt_and_join() {
+    let (a, b) = pair();
+    
+    b.write_line("last words").expect("Failed to write line");
+    assert_eq!(read_line(&a), "last words");
+    
+    a.interrupt().expect("Failed to interrupt");
+    a.join().expect("Runtime panicked");
+    
+    // the peer runtime ends once its transport is closed
+    poll(|| b.is_finished().then_some(()));
+    b.join().expect("Runtime panicked");
+}
+
+#[test]
+fn concurrent_writers_lose_nothing() {
+    const WRITERS: usize = 4;
+    const LINES: usize = 2000;
+    
+    let (a, b) = pair();
+    
+    std::thread::scope(|s| {
+        for writer in 0..WRITERS {
+            let a = &a;
+            
+            s.spawn(move || {
+                for i in 0..LINES {
+                    a.write_line(&format!("{writer} {i}")).expect("Failed to write line");
+                }
+            });
+        }
+    });
+    
+    // lines from one writer stay in order and are never interleaved with another's
+    let mut next = [0; WRITERS];
+    
+    for _ in 0..WRITERS * LINES {
+        let line = read_line(&b);
+        let (writer, i) = line.split_once(' ').expect("Interleaved line");
+        let writer: usize = writer.parse().expect("Interleaved line");
+        
+        assert_eq!(i.parse::<usize>().expect("Interleaved line"), next[writer]);
+        next[writer] += 1;
+    }
+    
+    assert_eq!(next, [LINES; WRITERS]);
+}
+
+#[test]
+fn read_line_timeout() {
+    let (a, b) = pair();
+    
+    assert_eq!(b.read_line_timeout(Duration::from_millis(20)), ReadLineResult::Empty);
+    
+    // half a line keeps waiting for the rest
+    a.write(b"half a ").expect("Failed to write");
+    
+    poll(|| (b.read_line() == ReadLineResult::NotALine).then_some(()));
+    assert_eq!(b.read_line_timeout(Duration::from_millis(20)), ReadLineResult::NotALine);
+    
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            a.write_line("line").expect("Failed to write line");
+        });
+        
+        assert_eq!(read_line(&b), "half a line");
+    });
+}
+
+#[test]
+fn read_blocking() {
+    let (a, b) = pair();
+    
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            a.write(b"bytes").expect("Failed to write");
+        });
+        
+        let mut bytes = Vec::new();
+        
+        while bytes.len() < 5 {
+            match b.read_blocking() {
+                ReadResult::Data(data) => bytes.extend(data),
+                result => panic!("Unexpected read result: {result:?}"),
+            }
+        }
+        
+        assert_eq!(bytes, b"bytes");
+    });
+    
+    // wakes up once the peer is gone
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            a.interrupt().expect("Failed to interrupt");
+        });
+        
+        assert_eq!(b.read_blocking(), ReadResult::Eof);
+    });
+}
+
+#[test]
+fn eof_after_buffered_data() {
+    let (a, b) = pair();
+    
+    assert_eq!(b.state(), NamedPipeState::Open);
+    
+    a.write(b"first\nsecond").expect("Failed to write");
+    assert_eq!(read_line(&b), "first");
+    poll(|| (b.read_line() == ReadLineResult::NotALine).then_some(()));
+    
+    a.interrupt().expect("Failed to interrupt");
+    poll(|| (b.state() == NamedPipeState::HalfClosed).then_some(()));
+    
+    // the last line does not need a newline once nothing more arrives
+    assert_eq!(b.read_line(), ReadLineResult::Line("second".into()));
+    assert_eq!(b.state(), NamedPipeState::Closed { reason: DisconnectReason::Closed });
+    assert_eq!(b.read_line(), ReadLineResult::Eof);
+    assert_eq!(b.read_line_timeout(Duration::from_secs(10)), ReadLineResult::Eof);
+    assert_eq!(b.read(), ReadResult::Eof);
+    assert_eq!(b.read_blocking(), ReadResult::Eof);
+    
+    poll(|| a.is_finished().then_some(()));
+    assert_eq!(a.state(), NamedPipeState::Closed { reason: DisconnectReason::Interrupted });
+}
+
+#[test]
+fn write_after_close() {
+    let (a, b) = pair();
+    
+    a.interrupt().expect("Failed to interrupt");
+    poll(|| (a.state() != NamedPipeState::Open && b.state() != NamedPipeState::Open).then_some(()));
+    
+    for pipe in [&a, &b] {
+        assert!(matches!(pipe.write_line("too late").expect_err("Pipe is closed").kind(), ErrorKind::Disconnected));
+        assert!(matches!(pipe.writer().write(b"too late").expect_err("Pipe is closed").kind(), ErrorKind::Disconnected));
+    }
+}
+
+#[test]
+fn panicking_runtime() {
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    let panicking = NamedPipe::new(a, buffer(), |_: &mut NamedPipeRuntime<MemoryTransport>| panic!("runtime panic")).expect("Failed to create pipe");
+    let _b = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    assert_eq!(panicking.read_blocking(), ReadResult::Eof);
+    assert_eq!(panicking.state(), NamedPipeState::Closed { reason: DisconnectReason::Panicked });
+    assert!(matches!(panicking.write(b"too late").expect_err("Runtime panicked").kind(), ErrorKind::RuntimePanic));
+    assert!(panicking.join().is_err());
+}
+
+#[test]
+fn bounded_write_backpressure() {
+    const CAPACITY: usize = 64;
+    
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    
+    // never takes anything out of the write queue
+    let stalled = NamedPipe::new(a, buffer().bounded(CAPACITY), |runtime: &mut NamedPipeRuntime<MemoryTransport>| {
+        while !runtime.wait().0.interrupt {}
+    }).expect("Failed to create pipe");
+    
+    let _b = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    stalled.write(&[0; CAPACITY / 2]).expect("Failed to write");
+    stalled.write(&[0; CAPACITY / 2]).expect("Failed to write");
+    
+    assert!(matches!(stalled.write(&[0]).expect_err("Queue should be full").kind(), ErrorKind::WouldBlock));
+    assert!(matches!(stalled.write_timeout(b"line", Duration::from_millis(20)).expect_err("Queue should be full").kind(), ErrorKind::Timeout));
+    
+    stalled.interrupt().expect("Failed to interrupt");
+    stalled.join().expect("Runtime panicked");
+}
+
+#[test]
+fn bounded_write_at_capacity() {
+    const CAPACITY: usize = 64;
+    const LINES: usize = 2000;
+    
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer().bounded(CAPACITY),
+        buffer(),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..LINES {
+                let line = format!("line {i}\n");
+                
+                a.write_timeout(line.as_bytes(), Duration::from_secs(10)).expect("Failed to write");
+            }
+        });
+        
+        for i in 0..LINES {
+            assert_eq!(read_line(&b), format!("line {i}"));
+        }
+    });
+}
+
+#[test]
+fn bounded_lines_and_messages_wait_for_room() {
+    const CAPACITY: usize = 64;
+    const COUNT: usize = 500;
+    
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer().bounded(CAPACITY),
+        buffer(),
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..COUNT {
+                a.write_line_timeout(&format!("line {i}"), Duration::MAX).expect("Failed to write line");
+            }
+            
+            for i in 0..COUNT {
+                a.send_message_timeout(format!("message {i}").as_bytes(), Duration::MAX).expect("Failed to send message");
+            }
+        });
+        
+        for i in 0..COUNT {
+            assert_eq!(read_line(&b), format!("line {i}"));
+        }
+        
+        for i in 0..COUNT {
+            assert_eq!(recv_message(&b), format!("message {i}").as_bytes());
+        }
+    });
+}
+
+#[test]
+fn owned_writer_handles() {
+    const WRITERS: usize = 4;
+    
+    let (a, b) = pair();
+    
+    let threads: Vec<_> = (0..WRITERS).map(|writer| {
+        let a = a.writer();
+        
+        std::thread::spawn(move || a.write_line(&format!("writer {writer}")).expect("Failed to write line"))
+    }).collect();
+    
+    threads.into_iter().for_each(|thread| thread.join().unwrap());
+    
+    let mut lines: Vec<_> = (0..WRITERS).map(|_| read_line(&b)).collect();
+    
+    lines.sort();
+    
+    assert_eq!(lines, (0..WRITERS).map(|writer| format!("writer {writer}")).collect::<Vec<_>>());
+    
+    // a handle outliving the pipe fails once the queue behind it is gone
+    let writer = a.writer();
+    
+    a.interrupt().expect("Failed to interrupt");
+    drop(a.join().expect("Runtime panicked"));
+    
+    assert!(writer.write_line("too late").is_err());
+}
+
+fn recv_message(pipe: &NamedPipe) -> Vec<u8> {
+    pipe.recv_message_timeout(Duration::from_secs(10)).expect("Failed to receive message").expect("Timed out")
+}
+
+#[test]
+fn messages() {
+    let (a, b) = pair();
+    let binary = [b'\n', 0, 0xff, b'\n', b'\r'];
+    
+    a.send_message(b"hello").expect("Failed to send message");
+    a.send_message(&binary).expect("Failed to send message");
+    a.send_message(b"").expect("Failed to send message");
+    
+    // larger than the io buffers
+    let large: Vec<u8> = (0..IO_BUFFER_SIZE * 3).map(|i| i as u8).collect();
+    
+    a.send_message(&large).expect("Failed to send message");
+    
+    assert_eq!(recv_message(&b), b"hello");
+    assert_eq!(recv_message(&b), binary);
+    assert_eq!(recv_message(&b), b"");
+    assert_eq!(recv_message(&b), large);
+    assert_eq!(b.recv_message().expect("Failed to receive message"), None);
+    
+    // lines and messages share the stream
+    b.write_line("line").expect("Failed to write line");
+    assert_eq!(read_line(&a), "line");
+}
+
+#[test]
+fn oversize_message() {
+    let (mut a, mut b) = pair();
+    
+    a.set_max_frame_size(8);
+    assert_eq!(a.max_frame_size(), 8);
+    
+    assert!(matches!(a.send_message(b"too long!"), Err(FrameError::Oversize { len: 9, max: 8 })));
+    a.send_message(b"fits").expect("Failed to send message");
+    assert_eq!(recv_message(&b), b"fits");
+    
+    b.set_max_frame_size(4);
+    a.send_message(b"ignored").expect("Failed to send message");
+    
+    assert!(matches!(
+        b.recv_message_timeout(Duration::from_secs(10)),
+        Err(FrameError::Oversize { len: 7, max: 4 }),
+    ));
+}
+
+#[test]
+fn truncated_message() {
+    let (a, b) = pair();
+    
+    // the header promises more than is ever written
+    a.write(&[10, 0, 0, 0, 1, 2, 3]).expect("Failed to write");
+    a.flush();
+    
+    assert_eq!(b.recv_message_timeout(Duration::from_millis(20)).expect("Failed to receive message"), None);
+    
+    a.interrupt().expect("Failed to interrupt");
+    a.join().expect("Runtime panicked");
+    
+    poll(|| b.is_finished().then_some(()));
+    
+    assert!(matches!(b.recv_message(), Err(FrameError::Truncated { available: 7 })));
+}
@@ -0,0 +1,224 @@
+use windows_named_pipe::{mux, prelude::*, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+// both ends of an in-memory duplex
+fn duplex(window: usize) -> (Multiplexer, Multiplexer) {
+    duplex_with(window, buffer)
+}
+
+fn duplex_with(window: usize, buffer: impl Fn() -> NamedPipeBuffer) -> (Multiplexer, Multiplexer) {
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    let a = NamedPipe::new(a, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    let b = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    (Multiplexer::with_window(a, window), Multiplexer::with_window(b, window))
+}
+
+fn read_line(stream: &Stream) -> String {
+    match stream.read_line_timeout(TIMEOUT) {
+        ReadLineResult::Line(line) => line,
+        result => panic!("Expected a line, got {result:?}"),
+    }
+}
+
+fn read_exact(stream: &Stream, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    
+    while bytes.len() < len {
+        match stream.read_blocking() {
+            ReadResult::Data(read) => bytes.extend(read),
+            result => panic!("Stream ended early, got {result:?}"),
+        }
+    }
+    
+    bytes
+}
+
+#[test]
+fn open_and_accept() {
+    let (a, b) = duplex(mux::DEFAULT_WINDOW);
+    let opened = a.open().expect("Failed to open stream");
+    
+    // written before the peer accepted
+    opened.write_line("early").expect("Failed to write line");
+    
+    let accepted = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    
+    assert_eq!(accepted.id(), opened.id());
+    assert!(accepted.is_open());
+    assert!(opened.wait_open(TIMEOUT));
+    
+    assert_eq!(read_line(&accepted), "early");
+    
+    accepted.write_line("reply").expect("Failed to write line");
+    assert_eq!(read_line(&opened), "reply");
+    
+    assert!(a.accept().is_none());
+    assert!(b.accept().is_none());
+}
+
+#[test]
+fn streams_from_both_ends() {
+    let (a, b) = duplex(mux::DEFAULT_WINDOW);
+    let streams_a: Vec<_> = (0..3).map(|_| a.open().expect("Failed to open stream")).collect();
+    let streams_b: Vec<_> = (0..3).map(|_| b.open().expect("Failed to open stream")).collect();
+    
+    // ids are only unique per opener
+    let accepted_b: Vec<_> = (0..3).map(|_| b.accept_timeout(TIMEOUT).expect("No stream to accept")).collect();
+    let accepted_a: Vec<_> = (0..3).map(|_| a.accept_timeout(TIMEOUT).expect("No stream to accept")).collect();
+    
+    for (i, stream) in streams_a.iter().chain(&streams_b).enumerate() {
+        stream.write_line(&format!("stream {i}")).expect("Failed to write line");
+    }
+    
+    for (i, stream) in accepted_b.iter().chain(&accepted_a).enumerate() {
+        assert_eq!(read_line(stream), format!("stream {i}"));
+    }
+}
+
+#[test]
+fn window_keeps_streams_independent() {
+    const WINDOW: usize = 64 << 10;
+    const LEN: usize = 1 << 20;
+    
+    let (a, b) = duplex(WINDOW);
+    let busy = a.open().expect("Failed to open stream");
+    let quiet = a.open().expect("Failed to open stream");
+    let busy_peer = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    let quiet_peer = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    
+    let data: Vec<u8> = (0..LEN).map(|i| (i * 7) as u8).collect();
+    
+    busy.write(&data).expect("Failed to write");
+    
+    // nobody reads the busy stream, so it stops at its window
+    poll(|| (busy.unsent() == LEN - WINDOW).then_some(()));
+    
+    // while the quiet one goes right through
+    for i in 0..10 {
+        quiet.write_line(&format!("line {i}")).expect("Failed to write line");
+        assert_eq!(read_line(&quiet_peer), format!("line {i}"));
+    }
+    
+    assert_eq!(busy.unsent(), LEN - WINDOW);
+    
+    // and everything arrives once it is read
+    assert_eq!(read_exact(&busy_peer, LEN), data);
+    poll(|| (busy.unsent() == 0).then_some(()));
+}
+
+#[test]
+fn close_handshake() {
+    let (a, b) = duplex(mux::DEFAULT_WINDOW);
+    let opened = a.open().expect("Failed to open stream");
+    let accepted = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    
+    opened.write_line("last words").expect("Failed to write line");
+    opened.close().expect("Failed to close");
+    
+    assert!(opened.write_line("too late").is_err());
+    
+    // whatever was written before the close still arrives
+    assert_eq!(read_line(&accepted), "last words");
+    poll(|| accepted.is_remote_closed().then_some(()));
+    assert_eq!(accepted.read_blocking(), ReadResult::Eof);
+    assert_eq!(accepted.read(), ReadResult::Eof);
+    assert_eq!(accepted.read_line(), ReadLineResult::Eof);
+    
+    // the other direction stays open until its end is closed too
+    accepted.write_line("still here").expect("Failed to write line");
+    assert_eq!(read_line(&opened), "still here");
+    
+    assert_eq!(a.stream_count(), 1);
+    drop(accepted);
+    
+    poll(|| (a.stream_count() == 0 && b.stream_count() == 0).then_some(()));
+    assert!(opened.is_remote_closed());
+}
+
+#[test]
+fn dropped_stream_does_not_hold_up_writer() {
+    const WINDOW: usize = 16 << 10;
+    
+    let (a, b) = duplex(WINDOW);
+    let opened = a.open().expect("Failed to open stream");
+    
+    drop(b.accept_timeout(TIMEOUT).expect("No stream to accept"));
+    
+    opened.write(&vec![0; WINDOW * 8]).expect("Failed to write");
+    poll(|| (opened.unsent() == 0).then_some(()));
+}
+
+#[test]
+fn pipe_gone() {
+    let (a, b) = duplex(mux::DEFAULT_WINDOW);
+    let opened = a.open().expect("Failed to open stream");
+    let accepted = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    
+    accepted.write_line("before").expect("Failed to write line");
+    assert_eq!(read_line(&opened), "before");
+    
+    b.pipe().interrupt().expect("Failed to interrupt");
+    
+    poll(|| (a.is_broken() && opened.is_broken()).then_some(()));
+    
+    assert_eq!(opened.read_blocking(), ReadResult::Eof);
+    assert!(opened.write_line("after").is_err());
+    assert!(a.open().is_err());
+}
+
+#[test]
+fn bounded_write_queue_waits_for_room() {
+    const LEN: usize = 1 << 20;
+    
+    let (a, b) = duplex_with(mux::DEFAULT_WINDOW, || buffer().bounded(IO_BUFFER_SIZE));
+    let opened = a.open().expect("Failed to open stream");
+    let accepted = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    
+    let data: Vec<u8> = (0..LEN).map(|i| (i * 7) as u8).collect();
+    
+    // far more than fits into the write queue at once, nothing is lost
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for chunk in data.chunks(LEN / 16) {
+                opened.write(chunk).expect("Failed to write");
+            }
+        });
+        
+        assert_eq!(read_exact(&accepted, LEN), data);
+    });
+    
+    poll(|| (opened.unsent() == 0).then_some(()));
+}
+
+#[test]
+fn bounded_write_queue_in_both_directions() {
+    const LEN: usize = 1 << 20;
+    const WINDOW: usize = 32 << 10;
+    
+    let (a, b) = duplex_with(WINDOW, || buffer().bounded(IO_BUFFER_SIZE));
+    let a_stream = a.open().expect("Failed to open stream");
+    let b_stream = b.accept_timeout(TIMEOUT).expect("No stream to accept");
+    
+    let data: Vec<u8> = (0..LEN).map(|i| (i * 7) as u8).collect();
+    
+    // the window updates that let the writers go on come through the same full queues, the dispatch threads must not wait on them
+    std::thread::scope(|s| {
+        for stream in [&a_stream, &b_stream] {
+            let data = &data;
+            
+            s.spawn(move || {
+                for chunk in data.chunks(LEN / 16) {
+                    stream.write(chunk).expect("Failed to write");
+                }
+            });
+            
+            s.spawn(move || assert_eq!(read_exact(stream, LEN), *data));
+        }
+    });
+    
+    poll(|| (a_stream.unsent() == 0 && b_stream.unsent() == 0).then_some(()));
+}
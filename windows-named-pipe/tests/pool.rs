@@ -0,0 +1,177 @@
+use std::{io::{BufRead, Write}, thread::{scope, sleep}, time::Duration};
+
+use windows_named_pipe::{prelude::{client::*, server::*}, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+fn connect(pipe_name: &NamedPipePath) -> (Client, PipeIo) {
+    while Client::check_pipe(pipe_name).expect("Failed to check pipe") != NamedPipeCheck::Available {
+        sleep(Duration::from_millis(10));
+    }
+    
+    let client = Client::wait(pipe_name).expect("Failed to wait pipe");
+    let pipe = client.initialize(buffer(), runtime_reference_implementation(|_| ())).expect("Failed to initialize pipe");
+    let mut pipe = PipeIo::new(pipe);
+    
+    pipe.set_read_timeout(Some(TIMEOUT));
+    
+    // answered once the server has taken the connection
+    let mut answer = String::new();
+    
+    writeln!(pipe, "hello").expect("Failed to write");
+    pipe.read_line(&mut answer).expect("Failed to read line");
+    assert_eq!(answer, "hello\n");
+    
+    (client, pipe)
+}
+
+fn hang_up((client, pipe): (Client, PipeIo)) {
+    let pipe = pipe.into_inner();
+    
+    pipe.interrupt().expect("Failed to interrupt");
+    pipe.join().expect("Runtime panicked");
+    client.close().expect("Failed to close client");
+}
+
+// closes the server once the clients are done, even if one of them failed
+struct CloseOnDrop(ServerCloser);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        let Self(closer) = self;
+        
+        closer.close().expect("Failed to close server");
+    }
+}
+
+// answers every line until the client hangs up
+fn echo(pipe: NamedPipe) {
+    let mut pipe = PipeIo::new(pipe);
+    let mut line = String::new();
+    
+    pipe.set_read_timeout(Some(TIMEOUT));
+    
+    while pipe.read_line(&mut line).expect("Failed to read line") > 0 {
+        pipe.write_all(line.as_bytes()).expect("Failed to write");
+        line.clear();
+    }
+}
+
+#[test]
+fn busy_beyond_max_instances() {
+    const MAX_INSTANCES: u32 = 2;
+    
+    let pipe_name = NamedPipePath::new("pool_busy_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    let closer = CloseOnDrop(server.closer());
+    
+    server.set_pool(ServerPool { max_instances: Some(MAX_INSTANCES), ..Default::default() });
+    
+    scope(|s| {
+        s.spawn(|| {
+            let _closer = closer;
+            let mut clients: Vec<_> = (0..MAX_INSTANCES).map(|_| connect(&pipe_name)).collect();
+            
+            // no instance is left and none is added
+            for _ in 0..10 {
+                assert_eq!(Client::check_pipe(&pipe_name).expect("Failed to check pipe"), NamedPipeCheck::Busy);
+                assert!(Client::try_wait(&pipe_name, Duration::from_millis(10)).expect("Failed to wait pipe").is_none());
+            }
+            
+            // the instance of an ended connection takes the next client
+            hang_up(clients.remove(0));
+            clients.push(connect(&pipe_name));
+            
+            clients.into_iter().for_each(hang_up);
+        });
+        
+        server.serve(|| runtime_reference_implementation(|_| ()), echo).expect("Failed to serve");
+    });
+    
+    assert_eq!(server.pipes().len(), MAX_INSTANCES as usize);
+}
+
+#[test]
+fn grow_up_to_max_instances() {
+    let pipe_name = NamedPipePath::new("pool_grow_test");
+    let mut server = Server::new(pipe_name, &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    
+    server.set_pool(ServerPool { max_instances: Some(5), ..Default::default() });
+    
+    // one to start with, then twice as many until the limit
+    for (added, instances) in [(1, 1), (1, 2), (2, 4), (1, 5), (0, 5)] {
+        assert_eq!(server.grow(None, None).expect("Failed to grow").len(), added);
+        assert_eq!(server.pipes().len(), instances);
+    }
+    
+    server.close().expect("Failed to close server");
+}
+
+#[test]
+fn idle_instances_are_closed() {
+    const CLIENTS: usize = 3;
+    
+    let pipe_name = NamedPipePath::new("pool_shrink_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    
+    server.set_pool(ServerPool { shrink_after: Some(Duration::from_millis(20)), ..Default::default() });
+    
+    scope(|s| {
+        s.spawn(|| {
+            let clients: Vec<_> = (0..CLIENTS).map(|_| connect(&pipe_name)).collect();
+            
+            // one instance is still listening, the others are left idle once their clients are gone
+            clients.into_iter().for_each(hang_up);
+            sleep(Duration::from_millis(500));
+            
+            hang_up(connect(&pipe_name));
+        });
+        
+        for pipe in server.incoming(|| runtime_reference_implementation(|_| ())).take(CLIENTS + 1) {
+            let pipe = pipe.expect("Failed to accept");
+            
+            s.spawn(move || echo(pipe));
+        }
+    });
+    
+    // the last client took the instance that was listening, a closed one took its place
+    let closed = server.pipes().iter_mut()
+        .map(|pipe| matches!(pipe.pipe_mut().update_status(), ServerNamedPipeStatus::Closed))
+        .filter(|&closed| closed)
+        .count();
+    
+    assert_eq!(server.pipes().len(), CLIENTS + 1);
+    assert_eq!(closed, CLIENTS - 1);
+    server.close().expect("Failed to close server");
+}
+
+#[test]
+fn default_wait_of_the_server() {
+    const DEFAULT_TIMEOUT: Duration = Duration::from_millis(300);
+    
+    let pipe_name = NamedPipePath::new("pool_default_wait_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, DEFAULT_TIMEOUT).expect("Failed to create server");
+    
+    for pipe in server.create_pipes(None, None, 1).expect("Failed to create pipes") {
+        let event = pipe.event();
+        
+        pipe.pipe_mut().start_connecting(event).expect("Failed to start connection");
+    }
+    
+    let client = Client::wait(&pipe_name).expect("Failed to wait pipe");
+    
+    while Client::check_pipe(&pipe_name).expect("Failed to check pipe") != NamedPipeCheck::Busy {
+        sleep(Duration::from_millis(10));
+    }
+    
+    // the only instance is taken, the wait takes as long as the server says
+    let start = std::time::Instant::now();
+    
+    assert!(Client::try_wait_default(&pipe_name).expect("Failed to wait pipe").is_none());
+    assert!(start.elapsed() >= DEFAULT_TIMEOUT);
+    
+    client.close().expect("Failed to close client");
+    server.close().expect("Failed to close server");
+}
@@ -0,0 +1,197 @@
+use std::{sync::Arc, thread::scope, time::{Duration, Instant}};
+
+use windows_named_pipe::prelude::*;
+
+mod common;
+
+use common::*;
+
+fn handlers() -> RpcHandlers {
+    let mut handlers = RpcHandlers::new();
+    
+    handlers
+        .register("echo", |payload| Ok(payload.to_owned()))
+        .register("reverse", |payload| Ok(payload.iter().rev().copied().collect()))
+        .register("fail", |payload| Err(String::from_utf8_lossy(payload).into_owned()))
+        .register("sleep", |payload| {
+            std::thread::sleep(Duration::from_millis(payload[0] as u64));
+            Ok(payload.to_owned())
+        });
+    
+    handlers
+}
+
+#[test]
+fn calls() {
+    let (a, b) = pair();
+    let client = RpcClient::new(a);
+    let handlers = handlers();
+    
+    scope(|s| {
+        s.spawn(|| handlers.serve(&b).expect("Failed to serve"));
+        
+        assert_eq!(client.call("echo", b"hello", TIMEOUT).expect("Call failed"), b"hello");
+        assert_eq!(client.call("reverse", b"abc", TIMEOUT).expect("Call failed"), b"cba");
+        assert_eq!(client.call("echo", b"", TIMEOUT).expect("Call failed"), b"");
+        
+        b.interrupt().expect("Failed to interrupt");
+    });
+}
+
+#[test]
+fn typed_errors() {
+    let (a, b) = pair();
+    let client = RpcClient::new(a);
+    let handlers = handlers();
+    
+    scope(|s| {
+        s.spawn(|| handlers.serve(&b).expect("Failed to serve"));
+        
+        assert!(matches!(client.call("missing", b"", TIMEOUT), Err(RpcError::UnknownMethod(method)) if method == "missing"));
+        assert!(matches!(client.call("fail", b"bad input", TIMEOUT), Err(RpcError::Remote(message)) if message == "bad input"));
+        
+        // the timed out response arrives late and is dropped
+        let start = Instant::now();
+        
+        assert!(matches!(client.call("sleep", &[250], Duration::from_millis(20)), Err(RpcError::Timeout)));
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert_eq!(client.call("echo", b"still works", TIMEOUT).expect("Call failed"), b"still works");
+        
+        b.interrupt().expect("Failed to interrupt");
+    });
+}
+
+#[test]
+fn calls_in_flight() {
+    let (a, b) = pair();
+    let client = RpcClient::new(a);
+    let handlers = handlers();
+    
+    scope(|s| {
+        // two servers on the same pipe, so a slow call does not hold up a fast one
+        for _ in 0..2 {
+            s.spawn(|| handlers.serve(&b).expect("Failed to serve"));
+        }
+        
+        let slow = s.spawn(|| {
+            let result = client.call("sleep", &[200], TIMEOUT);
+            
+            (result, Instant::now())
+        });
+        
+        std::thread::sleep(Duration::from_millis(20));
+        
+        assert_eq!(client.call("echo", b"fast", TIMEOUT).expect("Call failed"), b"fast");
+        
+        let fast_done = Instant::now();
+        let (result, slow_done) = slow.join().unwrap();
+        
+        assert_eq!(result.expect("Call failed"), [200]);
+        assert!(fast_done < slow_done);
+        
+        b.interrupt().expect("Failed to interrupt");
+    });
+}
+
+#[test]
+fn concurrent_callers() {
+    let (a, b) = pair();
+    let client = RpcClient::new(a);
+    let handlers = handlers();
+    
+    scope(|s| {
+        s.spawn(|| handlers.serve(&b).expect("Failed to serve"));
+        
+        let callers: Vec<_> = (0..8).map(|i| {
+            let client = &client;
+            
+            s.spawn(move || {
+                for j in 0..50 {
+                    let payload = format!("{i} {j}");
+                    
+                    assert_eq!(client.call("echo", payload.as_bytes(), TIMEOUT).expect("Call failed"), payload.as_bytes());
+                }
+            })
+        }).collect();
+        
+        for caller in callers {
+            caller.join().unwrap();
+        }
+        
+        b.interrupt().expect("Failed to interrupt");
+    });
+}
+
+#[test]
+fn callbacks() {
+    let (a, b) = pair();
+    let client = RpcClient::new(a);
+    let handlers = handlers();
+    let (sender, receiver) = channel::Channel::new().unwrap();
+    
+    for (method, payload) in [("echo", "one"), ("missing", "two"), ("reverse", "three")] {
+        let sender = sender.clone();
+        
+        client.call_with(method, payload.as_bytes(), Some(TIMEOUT), move |result| {
+            let _ = sender.send((payload, result));
+        });
+    }
+    
+    // served after the calls were made
+    let mut served = 0;
+    
+    while served < 3 {
+        served += handlers.serve_pending(&b).expect("Failed to serve");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    
+    let mut results: Vec<_> = (0..3).map(|_| receiver.recv_timeout(TIMEOUT).expect("No result")).collect();
+    
+    results.sort_by_key(|&(payload, _)| payload);
+    
+    assert!(matches!(&results[0], ("one", Ok(result)) if result == b"one"));
+    assert!(matches!(&results[1], ("three", Ok(result)) if result == b"eerht"));
+    assert!(matches!(&results[2], ("two", Err(RpcError::UnknownMethod(_)))));
+}
+
+#[test]
+fn disconnect_fails_pending_calls() {
+    let (a, b) = pair();
+    let client = Arc::new(RpcClient::new(a));
+    
+    // nothing serves b, so the call stays pending until b goes away
+    let pending = {
+        let client = client.clone();
+        
+        std::thread::spawn(move || client.call("echo", b"lost", TIMEOUT))
+    };
+    
+    std::thread::sleep(Duration::from_millis(20));
+    
+    b.interrupt().expect("Failed to interrupt");
+    b.join().expect("Runtime panicked");
+    
+    let start = Instant::now();
+    
+    assert!(matches!(pending.join().unwrap(), Err(RpcError::Disconnected)));
+    assert!(start.elapsed() < TIMEOUT);
+    
+    // later calls fail right away
+    assert!(client.is_closed());
+    assert!(matches!(client.call("echo", b"", TIMEOUT), Err(RpcError::Disconnected)));
+}
+
+#[test]
+fn drop_fails_pending_callbacks() {
+    let (a, _b) = pair();
+    let client = RpcClient::new(a);
+    let (sender, receiver) = channel::Channel::new().unwrap();
+    
+    client.call_with("echo", b"", None, move |result| {
+        let _ = sender.send(result);
+    });
+    
+    drop(client);
+    
+    assert!(matches!(receiver.recv_timeout(TIMEOUT), Ok(Err(RpcError::Disconnected))));
+}
@@ -0,0 +1,139 @@
+use std::{io::{BufRead, Read, Write}, thread::scope, time::Duration};
+
+use windows_named_pipe::{prelude::{client::*, server::*}, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+fn connect(pipe_name: &NamedPipePath) -> (Client, PipeIo) {
+    while Client::check_pipe(pipe_name).expect("Failed to check pipe") != NamedPipeCheck::Available {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    
+    let client = Client::wait(pipe_name).expect("Failed to wait pipe");
+    let pipe = client.initialize(buffer(), runtime_reference_implementation(|_| ())).expect("Failed to initialize pipe");
+    let mut pipe = PipeIo::new(pipe);
+    
+    pipe.set_read_timeout(Some(TIMEOUT));
+    (client, pipe)
+}
+
+// writes the line and returns the answer
+fn ask(pipe: &mut PipeIo, line: &str) -> String {
+    let mut answer = String::new();
+    
+    writeln!(pipe, "{line}").expect("Failed to write");
+    pipe.read_line(&mut answer).expect("Failed to read line");
+    
+    answer
+}
+
+// the server only sees the end of the connection once the client handle is closed as well
+fn hang_up(client: Client, pipe: PipeIo) {
+    let pipe = pipe.into_inner();
+    
+    pipe.interrupt().expect("Failed to interrupt");
+    pipe.join().expect("Runtime panicked");
+    client.close().expect("Failed to close client");
+}
+
+// closes the server once the clients are done, even if one of them failed
+struct CloseOnDrop(ServerCloser);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        let Self(closer) = self;
+        
+        closer.close().expect("Failed to close server");
+    }
+}
+
+#[test]
+fn incoming_rearms_instances() {
+    const CLIENTS: usize = 10;
+    
+    let pipe_name = NamedPipePath::new("incoming_rearm_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    
+    server.set_pool(ServerPool { min_idle: 2, ..Default::default() });
+    
+    scope(|s| {
+        s.spawn(|| {
+            for i in 0..CLIENTS {
+                let (client, mut pipe) = connect(&pipe_name);
+                
+                assert_eq!(ask(&mut pipe, &format!("client {i}")), format!("hello client {i}\n"));
+                hang_up(client, pipe);
+            }
+        });
+        
+        // on the caller's thread, one client after the other
+        for (i, pipe) in server.incoming(|| runtime_reference_implementation(|_| ())).take(CLIENTS).enumerate() {
+            let mut pipe = PipeIo::new(pipe.expect("Failed to accept"));
+            let mut line = String::new();
+            
+            pipe.set_read_timeout(Some(TIMEOUT));
+            pipe.read_line(&mut line).expect("Failed to read line");
+            assert_eq!(line, format!("client {i}\n"));
+            
+            write!(pipe, "hello {line}").expect("Failed to write");
+            pipe.flush().expect("Failed to flush");
+            
+            // the client hangs up, which ends the connection
+            assert_eq!(pipe.read(&mut [0; 16]).expect("Failed to read"), 0);
+        }
+    });
+    
+    // the instance of every ended connection was reused, one more keeps two listening while a client is served
+    assert_eq!(server.pipes().len(), 3);
+    server.close().expect("Failed to close server");
+}
+
+#[test]
+fn serve_on_threads() {
+    const CLIENTS: usize = 4;
+    const LINES: usize = 20;
+    
+    let pipe_name = NamedPipePath::new("serve_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    let closer = CloseOnDrop(server.closer());
+    
+    scope(|s| {
+        s.spawn(|| {
+            let _closer = closer;
+            
+            // every client is served at the same time
+            let clients: Vec<_> = (0..CLIENTS).map(|i| {
+                let pipe_name = &pipe_name;
+                
+                s.spawn(move || {
+                    let (client, mut pipe) = connect(pipe_name);
+                    
+                    for j in 0..LINES {
+                        assert_eq!(ask(&mut pipe, &format!("client {i} line {j}")), format!("hello client {i} line {j}\n"));
+                    }
+                    
+                    hang_up(client, pipe);
+                })
+            }).collect();
+            
+            for client in clients {
+                client.join().expect("Client failed");
+            }
+        });
+        
+        server.serve(|| runtime_reference_implementation(|_| ()), |pipe| {
+            let mut pipe = PipeIo::new(pipe);
+            let mut line = String::new();
+            
+            pipe.set_read_timeout(Some(TIMEOUT));
+            
+            // answers until the client hangs up
+            while pipe.read_line(&mut line).expect("Failed to read line") > 0 {
+                write!(pipe, "hello {line}").expect("Failed to write");
+                line.clear();
+            }
+        }).expect("Failed to serve");
+    });
+}
@@ -76,7 +76,10 @@ pub fn test() {
                             return Some(()); // exit mainloop
                         }
                     }
-                    &ServerNamedPipeStatus::Disconnected => panic!("Should have exited already!"),
+                    ServerNamedPipeStatus::Handshaking(_) => panic!("Pipe never did a handshake"),
+                    ServerNamedPipeStatus::Released(_) => panic!("Pipe was never handed out"),
+                    ServerNamedPipeStatus::Closed => panic!("Pipe was never closed"),
+                    ServerNamedPipeStatus::Disconnected(_) => panic!("Should have exited already!"),
                     ServerNamedPipeStatus::ThreadPanic(_error) => panic!("Thread poisoned"),
                 }
                 
@@ -121,3 +124,105 @@ pub fn test() {
         });
     });
 }
+
+fn buffer() -> NamedPipeBuffer {
+    NamedPipeBuffer::new(IO_BUFFER_SIZE)
+}
+
+fn status_name(status: &ServerNamedPipeStatus) -> &'static str {
+    match status {
+        ServerNamedPipeStatus::None => unreachable!(),
+        ServerNamedPipeStatus::Idle => "Idle",
+        ServerNamedPipeStatus::Pending => "Pending",
+        ServerNamedPipeStatus::Connected(_) => "Connected",
+        ServerNamedPipeStatus::Handshaking(_) => "Handshaking",
+        ServerNamedPipeStatus::Released(_) => "Released",
+        ServerNamedPipeStatus::Disconnected(_) => "Disconnected",
+        ServerNamedPipeStatus::ThreadPanic(_) => "ThreadPanic",
+        ServerNamedPipeStatus::Closed => "Closed",
+    }
+}
+
+#[test]
+pub fn reset() {
+    const CLIENTS: usize = 5;
+    
+    let pipe_name = NamedPipePath::new("reset_test");
+    let frame_length = Duration::from_secs_f64(1. / FPS);
+    
+    scope(|s| {
+        // one after another, each waits until the instance is listening again
+        s.spawn(|| {
+            for i in 0..CLIENTS {
+                while Client::check_pipe(&pipe_name).expect("Failed to check pipe") != NamedPipeCheck::Available {
+                    spin_sleep::sleep(frame_length);
+                }
+                
+                let client = Client::wait(&pipe_name).expect("Failed to wait pipe");
+                let pipe = client.initialize(buffer(), pipe_runtime()).expect("Failed to initialize pipe");
+                
+                pipe.write_line(&format!("client {i}")).expect("Failed to write line");
+                
+                let line = mainloop(frame_length, || match pipe.read_line() {
+                    ReadLineResult::Line(line) => Some(line),
+                    _ => None,
+                });
+                
+                assert_eq!(line, format!("hello client {i}"));
+                
+                pipe.interrupt().expect("Failed to interrupt");
+                pipe.join().expect("Runtime panicked");
+                client.close().expect("Failed to close client");
+            }
+        });
+        
+        let mut server = Server::new(pipe_name.clone(), &buffer, WINDOWS_BUFFER_SIZE, CLIENT_DEFAULT_TIMEOUT).expect("Failed to create server");
+        let event = server.create_pipe(None, None).expect("Failed to create pipe").event();
+        let mut statuses = Vec::new();
+        let mut served = 0;
+        
+        mainloop(frame_length, || {
+            let connected = !server.get_connected_pipes().is_empty();
+            let pipe = server.pipes()[0].pipe_mut();
+            let mut seen = |status| if statuses.last() != Some(&status) { statuses.push(status) };
+            
+            seen(status_name(pipe.update_status()));
+            
+            if connected {
+                pipe.notify_connection(pipe_runtime()).expect("Failed to connect pipe");
+            }
+            
+            match pipe.update_status() {
+                &ServerNamedPipeStatus::Idle => pipe.start_connecting(event).expect("Failed to start connection"),
+                ServerNamedPipeStatus::Connected(connected_pipe) => {
+                    if let ReadLineResult::Line(line) = connected_pipe.read_line() {
+                        connected_pipe.write_line(&format!("hello {line}")).expect("Failed to write line");
+                    }
+                }
+                ServerNamedPipeStatus::Disconnected(_) => {
+                    seen("Disconnected");
+                    served += 1;
+                    
+                    // the buffer of the ended connection is there for the next one
+                    assert!(matches!(unsafe { pipe.buffer() }, Some(LazyBuffer::Buffered(_))));
+                    
+                    if served < CLIENTS {
+                        pipe.reset(LazyBuffer::Unbuffered(&buffer)).expect("Failed to reset pipe");
+                    }
+                }
+                ServerNamedPipeStatus::Pending => {}
+                status => panic!("Unexpected status {}", status_name(status)),
+            }
+            
+            seen(status_name(pipe.update_status()));
+            
+            (served == CLIENTS).then_some(())
+        });
+        
+        // the one instance went through every state once per client
+        assert_eq!(server.pipes().len(), 1);
+        assert_eq!(statuses, ["Idle", "Pending", "Connected", "Disconnected"].repeat(CLIENTS));
+        
+        server.close().expect("Failed to close server");
+    });
+}
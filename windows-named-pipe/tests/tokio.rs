@@ -0,0 +1,191 @@
+#![cfg(feature = "tokio")]
+
+use std::{io::{BufRead, Write}, time::Duration};
+
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt}, time::timeout};
+use windows_named_pipe::{prelude::{client::*, server::*}, runtime::utils::runtime_reference_implementation};
+
+mod common;
+
+use common::*;
+
+fn pair(buffer_a: NamedPipeBuffer, buffer_b: NamedPipeBuffer) -> (AsyncNamedPipe, AsyncNamedPipe) {
+    let (a, b) = NamedPipe::loopback_pair(
+        buffer_a,
+        buffer_b,
+        runtime_reference_implementation(|_| ()),
+        runtime_reference_implementation(|_| ()),
+    ).expect("Failed to create loopback pair");
+    
+    (AsyncNamedPipe::new(a), AsyncNamedPipe::new(b))
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 7919) as u8).collect()
+}
+
+// a single thread, so nothing gets done unless the runtime threads wake the tasks
+#[tokio::test(flavor = "current_thread")]
+async fn copy_both_ways_through_a_bounded_queue() {
+    const LEN: usize = 3 << 20;
+    
+    let (a, b) = pair(buffer().bounded(IO_BUFFER_SIZE), buffer().bounded(IO_BUFFER_SIZE));
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    let data = payload(LEN);
+    
+    let write = async |writer: &mut tokio::io::WriteHalf<AsyncNamedPipe>| {
+        writer.write_all(&data).await.expect("Failed to write");
+        writer.flush().await.expect("Failed to flush");
+    };
+    
+    let read = async |reader: &mut tokio::io::ReadHalf<AsyncNamedPipe>| {
+        let mut received = vec![0; LEN];
+        
+        reader.read_exact(&mut received).await.expect("Failed to read");
+        received
+    };
+    
+    let (_, _, a_received, b_received) = timeout(TIMEOUT, async {
+        tokio::join!(write(&mut a_write), write(&mut b_write), read(&mut a_read), read(&mut b_read))
+    }).await.expect("Timed out");
+    
+    assert!(a_received == data, "Received data differs");
+    assert!(b_received == data, "Received data differs");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn lines_until_shutdown() {
+    let (mut a, b) = pair(buffer(), buffer());
+    let mut lines = b.lines();
+    
+    let reader = async {
+        let mut received = Vec::new();
+        
+        // only ends with the end of the stream
+        while let Some(line) = lines.next_line().await.expect("Failed to read line") {
+            received.push(line);
+        }
+        
+        received
+    };
+    
+    let writer = async {
+        for i in 0..1000 {
+            a.write_all(format!("line {i}\n").as_bytes()).await.expect("Failed to write");
+            tokio::task::yield_now().await;
+        }
+        
+        a.write_all(b"no newline").await.expect("Failed to write");
+        
+        // everything is written once flushed, so the shutdown loses nothing
+        a.shutdown().await.expect("Failed to shut down");
+        
+        assert_eq!(a.write_all(b"late").await.expect_err("Wrote after the shutdown").kind(), std::io::ErrorKind::BrokenPipe);
+        assert!(!a.pipe().is_finished());
+        
+        // only the interrupt ends the stream for the reader
+        a.pipe().interrupt().expect("Failed to interrupt");
+    };
+    
+    let (received, ()) = timeout(TIMEOUT, async { tokio::join!(reader, writer) }).await.expect("Timed out");
+    
+    assert_eq!(received.len(), 1001);
+    assert!(received[..1000].iter().enumerate().all(|(i, line)| *line == format!("line {i}")));
+    assert_eq!(received[1000], "no newline");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reads_after_shutdown() {
+    let (mut a, mut b) = pair(buffer(), buffer());
+    let mut line = String::new();
+    
+    a.shutdown().await.expect("Failed to shut down");
+    b.write_all(b"reply\n").await.expect("Failed to write");
+    
+    timeout(TIMEOUT, a.read_line(&mut line)).await.expect("Timed out").expect("Failed to read line");
+    
+    assert_eq!(line, "reply\n");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn closed_pipe() {
+    let (a, mut b) = pair(buffer(), buffer());
+    
+    // the read is already waiting when the pipe closes
+    let read = async {
+        let mut bytes = [0; 16];
+        
+        b.read(&mut bytes).await.expect("Failed to read")
+    };
+    
+    let close = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        a.pipe().interrupt().expect("Failed to interrupt");
+    };
+    
+    let (len, ()) = timeout(TIMEOUT, async { tokio::join!(read, close) }).await.expect("Timed out");
+    
+    assert_eq!(len, 0);
+    
+    // the peer is gone, so the pipe closes by itself
+    let error = timeout(TIMEOUT, b.write_all(b"too late")).await.expect("Timed out").expect_err("Pipe is closed");
+    
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn incoming() {
+    const CLIENTS: usize = 5;
+    
+    let pipe_name = NamedPipePath::new("tokio_incoming_test");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+    let closer = server.closer();
+    
+    // one client after the other, each waits for its answer
+    let clients = std::thread::spawn(move || {
+        for i in 0..CLIENTS {
+            while Client::check_pipe(&pipe_name).expect("Failed to check pipe") != NamedPipeCheck::Available {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            
+            let pipe = Client::wait(&pipe_name).expect("Failed to wait pipe")
+                .initialize(buffer(), runtime_reference_implementation(|_| ()))
+                .expect("Failed to initialize pipe");
+            let mut pipe = PipeIo::new(pipe);
+            let mut line = String::new();
+            
+            pipe.set_read_timeout(Some(TIMEOUT));
+            writeln!(pipe, "client {i}").expect("Failed to write");
+            pipe.read_line(&mut line).expect("Failed to read line");
+            
+            assert_eq!(line, format!("hello client {i}\n"));
+        }
+    });
+    
+    let mut incoming = server.incoming(|| runtime_reference_implementation(|_| ()));
+    
+    for i in 0..CLIENTS {
+        let pipe = timeout(TIMEOUT, incoming.accept()).await.expect("Timed out")
+            .expect("Server closed")
+            .expect("Failed to accept");
+        let mut pipe = tokio::io::BufReader::new(AsyncNamedPipe::new(pipe));
+        let mut line = String::new();
+        
+        timeout(TIMEOUT, pipe.read_line(&mut line)).await.expect("Timed out").expect("Failed to read line");
+        assert_eq!(line, format!("client {i}\n"));
+        
+        pipe.write_all(format!("hello {line}").as_bytes()).await.expect("Failed to write");
+        timeout(TIMEOUT, pipe.flush()).await.expect("Timed out").expect("Failed to flush");
+    }
+    
+    clients.join().expect("Client failed");
+    
+    // the stream ends once the server is closed, even from another thread
+    closer.close().expect("Failed to close server");
+    assert!(timeout(TIMEOUT, incoming.accept()).await.expect("Timed out").is_none());
+    
+    // the next client would have found an instance waiting already
+    assert!(server.pipes().iter_mut().any(|pipe| matches!(pipe.pipe_mut().update_status(), ServerNamedPipeStatus::Pending)));
+    server.close().expect("Failed to close server");
+}
@@ -0,0 +1,201 @@
+use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}}, time::{Duration, Instant}};
+
+use windows_named_pipe::{
+    prelude::*,
+    runtime::utils::{runtime_reference_implementation, runtime_reference_implementation_with_tick},
+};
+
+mod common;
+
+use common::*;
+
+const IO_BUFFER_SIZE: usize = 16;
+
+fn buffer() -> NamedPipeBuffer {
+    NamedPipeBuffer::new(IO_BUFFER_SIZE)
+}
+
+fn read_line(pipe: &NamedPipe) -> String {
+    poll(|| match pipe.read_line() {
+        ReadLineResult::Line(line) => Some(line),
+        _ => None,
+    })
+}
+
+fn round_trip<T: Transport>((a, b): (T, T)) {
+    let a = NamedPipe::new(a, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    let b = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    // longer than the io buffers so it takes several reads and writes
+    let line = "a line that does not fit into a single io buffer";
+    
+    a.write_line(line).expect("Failed to write line");
+    assert_eq!(read_line(&b), line);
+    
+    b.write_line(line).expect("Failed to write line");
+    assert_eq!(read_line(&a), line);
+    
+    a.interrupt().expect("Failed to interrupt");
+    a.join().expect("Runtime panicked");
+    
+    // b sees the transport closing
+    poll(|| b.is_finished().then_some(()));
+}
+
+#[test]
+fn memory_round_trip() {
+    round_trip(MemoryTransport::pair().expect("Failed to create transport"));
+}
+
+#[cfg(unix)]
+#[test]
+fn socketpair_round_trip() {
+    round_trip(PipeTransport::pair().expect("Failed to create transport"));
+}
+
+#[test]
+fn custom_executor() {
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    
+    // echoes every read back to the peer, one operation at a time
+    let echo = NamedPipe::new(a, buffer(), |runtime: &mut NamedPipeRuntime<MemoryTransport>| {
+        runtime.read().expect("Failed to read");
+        
+        loop {
+            let (result, error) = runtime.wait();
+            
+            assert!(error.is_none());
+            
+            if result.interrupt {
+                break;
+            }
+            
+            if let Some(Ok(len)) = result.read {
+                let bytes = runtime.read_buf().unwrap()[..len].to_owned();
+                
+                runtime.write_buf().unwrap()[..len].copy_from_slice(&bytes);
+                runtime.write(len).expect("Failed to write");
+            }
+            
+            if let Some(Err(_)) = result.read {
+                break;
+            }
+            
+            if result.write.is_some() {
+                runtime.read().expect("Failed to read");
+            }
+        }
+    }).expect("Failed to create pipe");
+    
+    let pipe = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    pipe.write_line("echo").expect("Failed to write line");
+    assert_eq!(read_line(&pipe), "echo");
+    
+    echo.interrupt().expect("Failed to interrupt");
+    echo.join().expect("Runtime panicked");
+}
+
+#[test]
+fn wait_timeout() {
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    
+    let waiter = NamedPipe::new(a, buffer(), |runtime: &mut NamedPipeRuntime<MemoryTransport>| {
+        runtime.read().expect("Failed to read");
+        
+        // nothing arrives, so the wait runs out
+        let start = Instant::now();
+        let (result, error) = runtime.wait_timeout(Duration::from_millis(20));
+        
+        assert!(error.is_none());
+        assert!(result.timed_out);
+        assert!(result.read.is_none() && !result.data && !result.interrupt);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        
+        // then it does
+        let (result, error) = runtime.wait_timeout(Duration::from_secs(10));
+        
+        assert!(error.is_none());
+        assert!(!result.timed_out);
+        assert!(matches!(result.read, Some(Ok(_))));
+    }).expect("Failed to create pipe");
+    
+    let pipe = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    std::thread::sleep(Duration::from_millis(50));
+    
+    pipe.write_line("wake up").expect("Failed to write line");
+    
+    waiter.join().expect("Runtime panicked");
+}
+
+#[test]
+fn reference_implementation_ticks() {
+    let (a, b) = MemoryTransport::pair().expect("Failed to create transport");
+    let ticks = Arc::new(AtomicUsize::new(0));
+    
+    let tick = {
+        let ticks = ticks.clone();
+        
+        move |_: &mut NamedPipeRuntime<MemoryTransport>| {
+            ticks.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    };
+    
+    let a = NamedPipe::new(a, buffer(), runtime_reference_implementation_with_tick(|_| (), Duration::from_millis(5), tick)).expect("Failed to create pipe");
+    let b = NamedPipe::new(b, buffer(), runtime_reference_implementation(|_| ())).expect("Failed to create pipe");
+    
+    // ticks keep coming while idle
+    poll(|| (ticks.load(Ordering::Relaxed) >= 3).then_some(()));
+    
+    // and the pipe still works
+    a.write_line("tick").expect("Failed to write line");
+    assert_eq!(read_line(&b), "tick");
+    
+    b.write_line("tock").expect("Failed to write line");
+    assert_eq!(read_line(&a), "tock");
+    
+    a.interrupt().expect("Failed to interrupt");
+    a.join().expect("Runtime panicked");
+}
+
+// the name is held through a lock next to the socket, a live server elsewhere keeps its name and is never connected to
+#[cfg(unix)]
+#[test]
+fn live_server_keeps_its_name() {
+    use windows_named_pipe::prelude::server::Server;
+    
+    let pipe_name = NamedPipePath::new("transport_live_test");
+    let mut lock_path = pipe_name.as_path().as_os_str().to_owned();
+    
+    lock_path.push(".lock");
+    
+    let lock = std::fs::File::options().create(true).truncate(false).write(true).open(lock_path).expect("Failed to open lock");
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, Duration::from_secs(10)).expect("Failed to create server");
+    
+    lock.lock().expect("Failed to lock");
+    assert!(server.create_pipes(None, None, 1).is_err());
+    
+    // it is taken over once its owner is gone
+    lock.unlock().expect("Failed to unlock");
+    server.create_pipes(None, None, 1).expect("Failed to create pipes");
+    server.close().expect("Failed to close server");
+}
+
+// a socket left behind by a dead server is replaced
+#[cfg(unix)]
+#[test]
+fn dead_server_is_replaced() {
+    use windows_named_pipe::prelude::{client::Client, server::Server};
+    
+    let pipe_name = NamedPipePath::new("transport_dead_test");
+    
+    drop(std::os::unix::net::UnixListener::bind(pipe_name.as_path()));
+    
+    let mut server = Server::new(pipe_name.clone(), &buffer, 65536, Duration::from_secs(10)).expect("Failed to create server");
+    
+    server.create_pipes(None, None, 1).expect("Failed to create pipes");
+    Client::wait(&pipe_name).expect("Failed to wait pipe").close().expect("Failed to close client");
+    server.close().expect("Failed to close server");
+}
@@ -0,0 +1,144 @@
+#![cfg(feature = "typed")]
+
+use std::{thread::scope, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+use windows_named_pipe::{prelude::{client::*, server::*}, runtime::utils::runtime_reference_implementation, typed::*};
+
+mod common;
+
+use common::*;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Request {
+    Add(i32, i32),
+    Echo(String),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Response {
+    id: u64,
+    text: String,
+    bytes: Vec<u8>,
+}
+
+fn round_trip<F: Format>(format: impl Fn() -> F) {
+    let (a, b) = pair();
+    let client = TypedPipe::<Request, Response, _>::new(a, format());
+    let server = TypedPipe::<Response, Request, _>::new(b, format());
+    
+    client.send(&Request::Add(1, 2)).expect("Failed to send");
+    client.send(&Request::Echo("multi\nline".into())).expect("Failed to send");
+    
+    assert_eq!(server.recv_timeout(TIMEOUT).expect("Failed to receive"), Some(Request::Add(1, 2)));
+    assert_eq!(server.recv_timeout(TIMEOUT).expect("Failed to receive"), Some(Request::Echo("multi\nline".into())));
+    assert!(server.recv().expect("Failed to receive").is_none());
+    
+    let response = Response { id: 7, text: "done".into(), bytes: vec![0, b'\n', 255] };
+    
+    server.send(&response).expect("Failed to send");
+    assert_eq!(client.recv_timeout(TIMEOUT).expect("Failed to receive"), Some(response));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_round_trip() {
+    round_trip(|| Json);
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn bincode_round_trip() {
+    round_trip(|| Bincode);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn decode_errors_do_not_poison() {
+    let (a, b) = pair();
+    let receiver = TypedPipe::<(), Request, _, _>::new(&b, Json);
+    
+    a.send_message(b"not json").expect("Failed to send message");
+    a.send_message(br#"{"Unknown":1}"#).expect("Failed to send message");
+    a.send_message(br#"{"Add":[3,4]}"#).expect("Failed to send message");
+    
+    assert!(matches!(receiver.recv_timeout(TIMEOUT), Err(DecodeError::Format(_))));
+    assert!(matches!(receiver.recv_timeout(TIMEOUT), Err(DecodeError::Format(_))));
+    assert_eq!(receiver.recv_timeout(TIMEOUT).expect("Failed to receive"), Some(Request::Add(3, 4)));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn oversize_messages() {
+    let (mut a, b) = pair();
+    
+    a.set_max_frame_size(8);
+    
+    let sender = TypedPipe::<String, (), _>::new(a, Json);
+    
+    assert!(matches!(sender.send(&"far too long".to_owned()), Err(EncodeError::Frame(FrameError::Oversize { .. }))));
+    sender.send(&"short".to_owned()).expect("Failed to send");
+    
+    assert_eq!(TypedPipe::<(), String, _, _>::new(&b, Json).recv_timeout(TIMEOUT).expect("Failed to receive"), Some("short".to_owned()));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn server_and_client() {
+    let pipe_name = NamedPipePath::new("typed_test");
+    
+    scope(|s| {
+        s.spawn(|| {
+            let mut server = Server::new(pipe_name.clone(), &buffer, 65536, TIMEOUT).expect("Failed to create server");
+            let event = server.create_pipe(None, None).expect("Failed to create pipe").event();
+            let start = Instant::now();
+            
+            loop {
+                assert!(start.elapsed() < TIMEOUT, "Timed out");
+                
+                let connected = !server.get_connected_pipes().is_empty();
+                let pipe = server.pipes()[0].pipe_mut();
+                
+                if connected {
+                    pipe.notify_connection(runtime_reference_implementation(|_| ())).expect("Failed to connect pipe");
+                }
+                
+                match pipe.update_status() {
+                    ServerNamedPipeStatus::Idle => pipe.start_connecting(event).expect("Failed to start connection"),
+                    ServerNamedPipeStatus::Connected(connected_pipe) => {
+                        let typed = TypedPipe::<Response, Request, _, _>::new(connected_pipe, Json);
+                        
+                        match typed.recv().expect("Failed to receive") {
+                            Some(Request::Echo(text)) => typed.send(&Response { id: 1, text, bytes: Vec::new() }).expect("Failed to send"),
+                            Some(Request::Add(..)) => break, // the client is done once it has the response
+                            None => {}
+                        }
+                    }
+                    _ => {}
+                }
+                
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            
+            server.close().expect("Failed to close server");
+        });
+        
+        s.spawn(|| {
+            let start = Instant::now();
+            
+            while let NamedPipeCheck::Unavailable = Client::check_pipe(&pipe_name).expect("Failed to check pipe") {
+                assert!(start.elapsed() < TIMEOUT, "Timed out");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            
+            let pipe = Client::wait(&pipe_name).expect("Failed to wait pipe").initialize(buffer(), runtime_reference_implementation(|_| ())).expect("Failed to initialize pipe");
+            let typed = TypedPipe::<Request, Response, _>::new(pipe, Json);
+            
+            typed.send(&Request::Echo("hello".into())).expect("Failed to send");
+            
+            assert_eq!(typed.recv_timeout(TIMEOUT).expect("Failed to receive"), Some(Response { id: 1, text: "hello".into(), bytes: Vec::new() }));
+            
+            typed.send(&Request::Add(0, 0)).expect("Failed to send");
+        });
+    });
+}